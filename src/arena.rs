@@ -0,0 +1,76 @@
+//! Ordered storage for the entities of a program.
+
+use std::ops::{Index, IndexMut, Range};
+
+/// A collection of values which are never removed, each identified by the
+/// position it was inserted at.
+///
+/// Because ids are handed out in insertion order, iteration follows the order
+/// entities were declared in, and loading the same program always assigns the
+/// same ids.
+#[derive(Clone, Debug)]
+pub(crate) struct Arena<T> {
+  vals: Vec<T>,
+}
+
+impl<T> Arena<T> {
+  pub fn new() -> Arena<T> {
+    Arena { vals: Vec::new() }
+  }
+
+  /// Add a value, returning its id.
+  pub fn insert(&mut self, val: T) -> usize {
+    self.vals.push(val);
+    self.vals.len() - 1
+  }
+
+  pub fn get(&self, id: usize) -> Option<&T> {
+    self.vals.get(id)
+  }
+
+  pub fn get_mut(&mut self, id: usize) -> Option<&mut T> {
+    self.vals.get_mut(id)
+  }
+
+  pub fn len(&self) -> usize {
+    self.vals.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.vals.is_empty()
+  }
+
+  /// All the ids, in insertion order.
+  pub fn ids(&self) -> Range<usize> {
+    0..self.vals.len()
+  }
+
+  /// All the values with their ids, in insertion order.
+  pub fn iter(&self) -> impl DoubleEndedIterator<Item = (usize, &T)> {
+    self.vals.iter().enumerate()
+  }
+
+  /// All the values, in insertion order.
+  pub fn values(&self) -> std::slice::Iter<'_, T> {
+    self.vals.iter()
+  }
+}
+
+impl<T> Default for Arena<T> {
+  fn default() -> Arena<T> {
+    Arena::new()
+  }
+}
+
+impl<T> Index<usize> for Arena<T> {
+  type Output = T;
+  fn index(&self, id: usize) -> &T {
+    &self.vals[id]
+  }
+}
+
+impl<T> IndexMut<usize> for Arena<T> {
+  fn index_mut(&mut self, id: usize) -> &mut T {
+    &mut self.vals[id]
+  }
+}
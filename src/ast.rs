@@ -1,25 +1,127 @@
+use anyhow::{anyhow, Error};
 use lalrpop_util::lalrpop_mod;
 use serde_derive::{Deserialize, Serialize};
 
 lalrpop_mod!(#[allow(clippy::all)] pub parse, "/ast/parse.rs");
 
+#[macro_use]
+pub mod visit;
+pub mod fold;
+pub mod visit_mut;
+
 use std::vec::Vec;
 
+/// The version of the JSON encoding of the AST, as described by
+/// [JSON_SCHEMA].
+///
+/// The encoding is the one derived by serde: structs are objects keyed by field
+/// name, and enums are externally tagged. The top-level object also records the
+/// version it was written with. The version is incremented whenever a change to
+/// the AST alters the encoding of existing programs.
+///
+/// Version 1 predates the version field, so a document without one is taken to
+/// be version 1 and upgraded when it is loaded.
+pub const JSON_FORMAT_VERSION: u32 = 2;
+
+/// A JSON Schema document describing the JSON encoding of a [File].
+pub const JSON_SCHEMA: &str = include_str!("../schema/ast-2.schema.json");
+
+/// Parse the source of a single Rado file into its AST.
+pub fn parse_file(src: &str) -> Result<File, Error> {
+  let tokens = crate::token::lex(src)?;
+  parse::FileParser::new()
+    .parse(tokens)
+    .map_err(|e| anyhow!("error parsing file: {:?}", e))
+}
+
 #[derive(Clone, Hash, Debug, Serialize, Deserialize)]
 pub struct File {
   pub stmts: Vec<Stmt>,
 }
 
+impl File {
+  /// Load a file's AST from its JSON encoding. See [JSON_SCHEMA] for the
+  /// format. Documents from older versions of the encoding are accepted and
+  /// upgraded; documents from newer versions are rejected.
+  pub fn from_json(s: &str) -> Result<File, Error> {
+    let err = |e| anyhow!("error loading AST from JSON: {}", e);
+    let mut json: serde_json::Value = serde_json::from_str(s).map_err(err)?;
+    let version = match json.as_object_mut().and_then(|o| o.remove("version")) {
+      None => 1,
+      Some(v) => match v.as_u64() {
+        Some(n) if n >= 1 && n <= u64::from(JSON_FORMAT_VERSION) => n,
+        _ => {
+          return Err(anyhow!(
+            "unsupported AST JSON version {}; expected at most {}",
+            v,
+            JSON_FORMAT_VERSION
+          ))
+        }
+      },
+    };
+    if version < 2 {
+      upgrade_v1_conds(&mut json);
+    }
+    serde_json::from_value(json).map_err(err)
+  }
+
+  /// Serialize this AST to pretty-printed JSON. See [JSON_SCHEMA] for the
+  /// format.
+  pub fn to_json(&self) -> Result<String, Error> {
+    #[derive(Serialize)]
+    struct Versioned<'a> {
+      version: u32,
+      #[serde(flatten)]
+      file: &'a File,
+    }
+    let versioned = Versioned {
+      version: JSON_FORMAT_VERSION,
+      file: self,
+    };
+    serde_json::to_string_pretty(&versioned)
+      .map_err(|e| anyhow!("error serializing AST to JSON: {}", e))
+  }
+}
+
+/// Upgrades version 1 conditional blocks, which had no tags, to version 2 by
+/// giving each an empty tag list.
+fn upgrade_v1_conds(json: &mut serde_json::Value) {
+  use serde_json::Value;
+  match json {
+    Value::Object(o) => {
+      if let Some(Value::Array(cond)) = o.get_mut("Cond") {
+        if cond.len() == 3 {
+          cond.insert(0, Value::Array(vec![]));
+        }
+      }
+      o.values_mut().for_each(upgrade_v1_conds);
+    }
+    Value::Array(a) => a.iter_mut().for_each(upgrade_v1_conds),
+    _ => {}
+  }
+}
+
 #[derive(Clone, Hash, Debug, Serialize, Deserialize)]
 pub struct Ident(pub String);
 
 pub type Num = num_rational::BigRational;
 pub type Path = Vec<Ident>;
 
+/// A range of byte offsets into the source of a file.
+#[derive(Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Span {
+  pub start: usize,
+  pub end: usize,
+}
+
 #[derive(Clone, Hash, Debug, Serialize, Deserialize)]
 pub struct DeclName {
   pub ident: Ident,
   pub human: Option<String>,
+  /// The location of the identifier in the source. Spans are not part of the
+  /// serialized encoding, so ASTs loaded from JSON or RSON have none.
+  #[serde(skip)]
+  pub span: Option<Span>,
 }
 
 #[derive(Clone, Hash, Debug, Serialize, Deserialize)]
@@ -32,7 +134,9 @@ pub enum ModVec<T> {
 pub enum Stmt {
   Decl(Decl),
   Prop(Prop),
-  Cond(Expr, Vec<Stmt>, Vec<Stmt>),
+  /// A conditional block, with its tags, which are negated for the else
+  /// block, its condition, and its two blocks.
+  Cond(Vec<(bool, Ident)>, Expr, Vec<Stmt>, Vec<Stmt>),
   Modify(Decl),
   Override(Decl),
   Delete(Decl),
@@ -108,6 +212,10 @@ pub struct Config {
   pub name: DeclName,
   pub ty: Ty,
   pub default: Option<Expr>,
+  // Left out of the encoding when empty, so that configs without a block are
+  // encoded as they were before configs could have one.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub stmts: Vec<Stmt>,
 }
 
 #[derive(Clone, Hash, Debug, Serialize, Deserialize)]
@@ -115,12 +223,20 @@ pub struct ConfigEnum {
   pub name: DeclName,
   pub variants: Vec<DeclName>,
   pub default: Option<Expr>,
+  // Left out of the encoding when empty, so that configs without a block are
+  // encoded as they were before configs could have one.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub stmts: Vec<Stmt>,
 }
 
 #[derive(Clone, Hash, Debug, Serialize, Deserialize)]
 pub struct TypedConfig {
   pub name: DeclName,
   pub default: Option<Expr>,
+  // Left out of the encoding when empty, so that configs without a block are
+  // encoded as they were before configs could have one.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub stmts: Vec<Stmt>,
 }
 
 #[derive(Clone, Hash, Debug, Serialize, Deserialize)]
@@ -159,6 +275,25 @@ pub enum Decl {
   Random(Random),
 }
 
+impl Decl {
+  /// The declared name, if this kind of declaration has exactly one.
+  pub fn name(&self) -> Option<&DeclName> {
+    match self {
+      Decl::Region(r) => Some(&r.name),
+      Decl::Link(l) => l.name.as_ref(),
+      Decl::Item(i) => Some(&i.name),
+      Decl::Location(l) => Some(&l.name),
+      Decl::Fn(f) => Some(&f.name),
+      Decl::Enum(e) => Some(&e.name),
+      Decl::Config(c) => Some(&c.name),
+      Decl::ConfigEnum(c) => Some(&c.name),
+      Decl::Configset(c) => Some(&c.name),
+      Decl::Random(r) => Some(&r.name),
+      Decl::Items(_) | Decl::Locations(_) | Decl::Configs(_) => None,
+    }
+  }
+}
+
 #[derive(Clone, Hash, Debug, Serialize, Deserialize)]
 pub struct Requires {
   pub cond: Expr,
@@ -226,6 +361,16 @@ pub struct StartIn {
   pub region: Path,
 }
 
+#[derive(Clone, Hash, Debug, Serialize, Deserialize)]
+pub struct Exclusive {
+  pub tags: Vec<(bool, Ident)>,
+}
+
+#[derive(Clone, Hash, Debug, Serialize, Deserialize)]
+pub struct Disallow {
+  pub value: Ident,
+}
+
 #[derive(Clone, Hash, Debug, Serialize, Deserialize)]
 pub enum Prop {
   Requires(Requires),
@@ -242,6 +387,33 @@ pub enum Prop {
   Grants(Grants),
   StartWith(StartWith),
   StartIn(StartIn),
+  Exclusive(Exclusive),
+  Disallow(Disallow),
+}
+
+impl Prop {
+  /// The keyword introducing this kind of property statement, for use in
+  /// messages.
+  pub fn keyword(&self) -> &'static str {
+    match self {
+      Prop::Requires(_) => "requires",
+      Prop::Visible(_) => "visible",
+      Prop::Unlock(_) => "unlock",
+      Prop::Tag(_) => "tag",
+      Prop::Alias(_) => "alias",
+      Prop::Provides(_) => "provides",
+      Prop::Progressive(_) => "progressive",
+      Prop::Val(_) => "val",
+      Prop::Max(_) => "max",
+      Prop::Consumable => "consumable",
+      Prop::Avail(_) => "avail",
+      Prop::Grants(_) => "grants",
+      Prop::StartWith(_) => "start with",
+      Prop::StartIn(_) => "start in",
+      Prop::Exclusive(_) => "exclusive",
+      Prop::Disallow(_) => "disallow",
+    }
+  }
 }
 
 #[derive(Clone, Hash, Debug, Serialize, Deserialize)]
@@ -303,16 +475,55 @@ pub enum Ty {
 #[cfg(test)]
 mod test {
   use super::*;
-  use failure::{format_err, Error};
+  use anyhow::{format_err, Error};
   use std::{fs, path};
 
+  // Write `actual` next to the gold file for inspection, then compare it with
+  // the gold file, printing a diff and panicking if they differ.
+  fn check_gold(
+    format: &str,
+    gold_path: &path::Path,
+    parse_path: &path::Path,
+    actual: &str,
+  ) -> Result<(), Error> {
+    match fs::write(parse_path, actual) {
+      Ok(()) => println!(
+        "Successfully wrote parsed AST as {} to {}",
+        format,
+        parse_path.to_string_lossy()
+      ),
+      Err(e) => println!(
+        "Error writing parsed AST to {}: {}. Continuing anyway.",
+        parse_path.to_string_lossy(),
+        e
+      ),
+    }
+
+    let gold = fs::read_to_string(gold_path).map_err(|e| {
+      format_err!(
+        "Error reading gold {} file {}: {}",
+        format,
+        gold_path.to_string_lossy(),
+        e
+      )
+    })?;
+
+    let diff = difference::Changeset::new(&gold, actual, "\n");
+    if diff.distance > 0 {
+      println!("{}", diff);
+      panic!(
+        "Parsed AST does not match gold {} file! See diff printed above for differences.",
+        format
+      );
+    }
+    Ok(())
+  }
+
   macro_rules! sample {
     ($name:ident, $file:expr) => {
       #[test]
       fn $name() -> Result<(), Error> {
         let sample_path = path::PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/", $file));
-        let gold_path = sample_path.with_extension("gold.rson");
-        let parse_path = sample_path.with_extension("parse.rson");
 
         let sample = fs::read_to_string(&sample_path).map_err(|e| {
           format_err!(
@@ -321,36 +532,28 @@ mod test {
             e
           )
         })?;
-        let tokens = crate::token::lex(&sample).unwrap();
-        let parsed = parse::FileParser::new().parse(tokens).unwrap();
+        let parsed = parse_file(&sample)?;
 
         let parsed_rson = rson_rs::ser::pretty::to_string(&parsed)
           .map_err(|e| format_err!("Error serializing parsed AST to RSON: {}", e))?;
-        match fs::write(&parse_path, &parsed_rson) {
-          Ok(()) => println!(
-            "Successfully wrote parsed AST as RSON to {}",
-            parse_path.to_string_lossy()
-          ),
-          Err(e) => println!(
-            "Error writing parsed AST to {}: {}. Continuing anyway.",
-            parse_path.to_string_lossy(),
-            e
-          ),
-        }
-
-        let gold = fs::read_to_string(&gold_path).map_err(|e| {
-          format_err!(
-            "Error reading gold RSON file {}: {}",
-            gold_path.to_string_lossy(),
-            e
-          )
-        })?;
-
-        let diff = difference::Changeset::new(&gold, &parsed_rson, "\n");
-        if diff.distance > 0 {
-          println!("{}", diff);
-          panic!("Parsed AST does not match gold file! See diff printed above for differences.");
-        }
+        check_gold(
+          "RSON",
+          &sample_path.with_extension("gold.rson"),
+          &sample_path.with_extension("parse.rson"),
+          &parsed_rson,
+        )?;
+
+        let parsed_json = parsed.to_json()?;
+        check_gold(
+          "JSON",
+          &sample_path.with_extension("gold.json"),
+          &sample_path.with_extension("parse.json"),
+          &parsed_json,
+        )?;
+
+        // Loading the JSON back must give the same AST.
+        let reloaded = File::from_json(&parsed_json)?;
+        assert_eq!(parsed_rson, rson_rs::ser::pretty::to_string(&reloaded)?);
         Ok(())
       }
     };
@@ -359,4 +562,107 @@ mod test {
   sample!(sample_alttp_items, "samples/alttp/items.rado");
   sample!(sample_alttp_regions, "samples/alttp/regions.rado");
   sample!(sample_alttp_config, "samples/alttp/config.rado");
+
+  #[test]
+  fn comparison_operators() -> Result<(), Error> {
+    let f = parse_file("fn A = X < Y fn B = X <= Y fn C = X > Y fn D = X >= Y")?;
+    let ops = f
+      .stmts
+      .iter()
+      .map(|s| match s {
+        Stmt::Decl(Decl::Fn(FnDecl {
+          body: Expr::Bin(_, op, _),
+          ..
+        })) => format!("{:?}", op),
+        s => panic!("unexpected statement {:?}", s),
+      })
+      .collect::<Vec<_>>();
+    assert_eq!(vec!["LT", "LE", "GT", "GE"], ops);
+    Ok(())
+  }
+
+  #[test]
+  fn json_matches_schema() -> Result<(), Error> {
+    let schema: serde_json::Value = serde_json::from_str(JSON_SCHEMA)?;
+    assert!(schema["$id"]
+      .as_str()
+      .unwrap()
+      .ends_with(&format!("ast-{}.schema.json", JSON_FORMAT_VERSION)));
+    let validator = jsonschema::validator_for(&schema).unwrap();
+    let check = |json: &str| -> Result<(), Error> {
+      let instance: serde_json::Value = serde_json::from_str(json)?;
+      let errors = validator.iter_errors(&instance).map(|e| e.to_string());
+      assert_eq!(Vec::<String>::new(), errors.collect::<Vec<_>>());
+      Ok(())
+    };
+    for name in &["items", "regions", "config"] {
+      let path = format!(
+        "{}/samples/alttp/{}.gold.json",
+        env!("CARGO_MANIFEST_DIR"),
+        name
+      );
+      check(&std::fs::read_to_string(path)?)?;
+    }
+    let f = parse_file("exclusive A, not B #[A, not B] if true { item I } else { }")?;
+    check(&f.to_json()?)?;
+    let f = parse_file("config C : enum { A B } default A { disallow B }")?;
+    check(&f.to_json()?)?;
+    let invalid = serde_json::json!({"version": 2, "stmts": [{"Decl": {"Region": {}}}]});
+    assert!(!validator.is_valid(&invalid));
+    assert!(!validator.is_valid(&serde_json::json!({"stmts": []})));
+    Ok(())
+  }
+
+  #[test]
+  fn json_load_and_compile() {
+    let f = File::from_json(
+      r#"{"stmts": [
+        {"Decl": {"Region": {"name": {"ident": "Outer"}, "stmts": [
+          {"Decl": {"Region": {"name": {"ident": "Inner", "human": "The Inside"}, "stmts": []}}}
+        ]}}}
+      ]}"#,
+    )
+    .unwrap();
+    match &f.stmts[0] {
+      Stmt::Decl(Decl::Region(r)) => {
+        assert!(r.name.human.is_none());
+        assert_eq!(1, r.stmts.len());
+      }
+      s => panic!("unexpected statement {:?}", s),
+    }
+    crate::Program::from_ast(f).unwrap();
+    assert!(File::from_json(r#"{"stmts": [{"Decl": {"Region": {}}}]}"#).is_err());
+  }
+
+  #[test]
+  fn json_versions() -> Result<(), Error> {
+    let f = parse_file("#[A] if true { if false { item I } }")?;
+    let json = f.to_json()?;
+    let value: serde_json::Value = serde_json::from_str(&json)?;
+    assert_eq!(serde_json::json!(JSON_FORMAT_VERSION), value["version"]);
+    assert_eq!(1, File::from_json(&json)?.stmts.len());
+
+    // Version 1 had no version field and no tags on conditional blocks.
+    let v1 = r#"{"stmts": [
+      {"Cond": [{"Bool": true}, [{"Cond": [{"Bool": false}, [], []]}], []]}
+    ]}"#;
+    match &File::from_json(v1)?.stmts[..] {
+      [Stmt::Cond(tags, _, if_true, _)] => {
+        assert!(tags.is_empty());
+        match &if_true[..] {
+          [Stmt::Cond(tags, ..)] => assert!(tags.is_empty()),
+          s => panic!("unexpected statements {:?}", s),
+        }
+      }
+      s => panic!("unexpected statements {:?}", s),
+    }
+
+    for bad in &[
+      r#"{"version": 3, "stmts": []}"#,
+      r#"{"version": "2", "stmts": []}"#,
+    ] {
+      assert!(File::from_json(bad).is_err(), "{}", bad);
+    }
+    Ok(())
+  }
 }
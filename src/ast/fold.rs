@@ -0,0 +1,568 @@
+//! Owning transformation of the AST.
+//!
+//! A [Fold] consumes each node and produces a replacement for it. As with the
+//! visitors, each default method calls the matching `walk_*` function in this
+//! module, which rebuilds the node from the folded versions of its children.
+//! Overriding a single method is therefore enough to rewrite every occurrence
+//! of that kind of node in a tree.
+
+use super::*;
+
+/// A transformation which rebuilds the AST from owned nodes.
+pub trait Fold {
+  fn fold_file(&mut self, f: File) -> File {
+    walk_file(self, f)
+  }
+  fn fold_ident(&mut self, i: Ident) -> Ident {
+    walk_ident(self, i)
+  }
+  fn fold_num(&mut self, n: Num) -> Num {
+    walk_num(self, n)
+  }
+  fn fold_path(&mut self, p: Path) -> Path {
+    walk_path(self, p)
+  }
+  fn fold_decl_name(&mut self, n: DeclName) -> DeclName {
+    walk_decl_name(self, n)
+  }
+  fn fold_stmt(&mut self, s: Stmt) -> Stmt {
+    walk_stmt(self, s)
+  }
+  fn fold_decl(&mut self, d: Decl) -> Decl {
+    walk_decl(self, d)
+  }
+  fn fold_region(&mut self, r: Region) -> Region {
+    walk_region(self, r)
+  }
+  fn fold_link_dir(&mut self, d: LinkDir) -> LinkDir {
+    walk_link_dir(self, d)
+  }
+  fn fold_link(&mut self, l: Link) -> Link {
+    walk_link(self, l)
+  }
+  fn fold_item(&mut self, i: Item) -> Item {
+    walk_item(self, i)
+  }
+  fn fold_items(&mut self, i: Items) -> Items {
+    walk_items(self, i)
+  }
+  fn fold_location(&mut self, l: Location) -> Location {
+    walk_location(self, l)
+  }
+  fn fold_locations(&mut self, l: Locations) -> Locations {
+    walk_locations(self, l)
+  }
+  fn fold_param(&mut self, p: Param) -> Param {
+    walk_param(self, p)
+  }
+  fn fold_fn_decl(&mut self, f: FnDecl) -> FnDecl {
+    walk_fn_decl(self, f)
+  }
+  fn fold_enum(&mut self, e: Enum) -> Enum {
+    walk_enum(self, e)
+  }
+  fn fold_config(&mut self, c: Config) -> Config {
+    walk_config(self, c)
+  }
+  fn fold_config_enum(&mut self, c: ConfigEnum) -> ConfigEnum {
+    walk_config_enum(self, c)
+  }
+  fn fold_typed_config(&mut self, c: TypedConfig) -> TypedConfig {
+    walk_typed_config(self, c)
+  }
+  fn fold_configs(&mut self, c: Configs) -> Configs {
+    walk_configs(self, c)
+  }
+  fn fold_configset(&mut self, c: Configset) -> Configset {
+    walk_configset(self, c)
+  }
+  fn fold_random(&mut self, r: Random) -> Random {
+    walk_random(self, r)
+  }
+  fn fold_prop(&mut self, p: Prop) -> Prop {
+    walk_prop(self, p)
+  }
+  fn fold_requires(&mut self, r: Requires) -> Requires {
+    walk_requires(self, r)
+  }
+  fn fold_visible(&mut self, v: Visible) -> Visible {
+    walk_visible(self, v)
+  }
+  fn fold_unlock(&mut self, u: Unlock) -> Unlock {
+    walk_unlock(self, u)
+  }
+  fn fold_tag(&mut self, t: Tag) -> Tag {
+    walk_tag(self, t)
+  }
+  fn fold_alias(&mut self, a: Alias) -> Alias {
+    walk_alias(self, a)
+  }
+  fn fold_provides(&mut self, p: Provides) -> Provides {
+    walk_provides(self, p)
+  }
+  fn fold_progressive(&mut self, p: Progressive) -> Progressive {
+    walk_progressive(self, p)
+  }
+  fn fold_val(&mut self, v: Val) -> Val {
+    walk_val(self, v)
+  }
+  fn fold_max(&mut self, m: Max) -> Max {
+    walk_max(self, m)
+  }
+  fn fold_avail(&mut self, a: Avail) -> Avail {
+    walk_avail(self, a)
+  }
+  fn fold_grants(&mut self, g: Grants) -> Grants {
+    walk_grants(self, g)
+  }
+  fn fold_start_with(&mut self, s: StartWith) -> StartWith {
+    walk_start_with(self, s)
+  }
+  fn fold_start_in(&mut self, s: StartIn) -> StartIn {
+    walk_start_in(self, s)
+  }
+  fn fold_exclusive(&mut self, x: Exclusive) -> Exclusive {
+    walk_exclusive(self, x)
+  }
+  fn fold_disallow(&mut self, d: Disallow) -> Disallow {
+    walk_disallow(self, d)
+  }
+  fn fold_expr(&mut self, e: Expr) -> Expr {
+    walk_expr(self, e)
+  }
+  fn fold_bin_op(&mut self, o: BinOp) -> BinOp {
+    walk_bin_op(self, o)
+  }
+  fn fold_builtin(&mut self, b: Builtin) -> Builtin {
+    walk_builtin(self, b)
+  }
+  fn fold_match_arm(&mut self, a: MatchArm) -> MatchArm {
+    walk_match_arm(self, a)
+  }
+  fn fold_ty(&mut self, t: Ty) -> Ty {
+    walk_ty(self, t)
+  }
+}
+
+// Folds each element of a list that may be a modifier list.
+fn fold_mod_vec<T>(m: ModVec<T>, mut f: impl FnMut(T) -> T) -> ModVec<T> {
+  match m {
+    ModVec::New(v) => ModVec::New(v.into_iter().map(f).collect()),
+    ModVec::Mod(v) => ModVec::Mod(v.into_iter().map(|(add, t)| (add, f(t))).collect()),
+  }
+}
+
+fn fold_stmts<F: Fold + ?Sized>(f: &mut F, stmts: Vec<Stmt>) -> Vec<Stmt> {
+  stmts.into_iter().map(|s| f.fold_stmt(s)).collect()
+}
+
+fn fold_exprs<F: Fold + ?Sized>(f: &mut F, exprs: Vec<Expr>) -> Vec<Expr> {
+  exprs.into_iter().map(|e| f.fold_expr(e)).collect()
+}
+
+pub fn walk_file<F: Fold + ?Sized>(f: &mut F, file: File) -> File {
+  File {
+    stmts: fold_stmts(f, file.stmts),
+  }
+}
+
+pub fn walk_ident<F: Fold + ?Sized>(_f: &mut F, i: Ident) -> Ident {
+  i
+}
+
+pub fn walk_num<F: Fold + ?Sized>(_f: &mut F, n: Num) -> Num {
+  n
+}
+
+pub fn walk_path<F: Fold + ?Sized>(f: &mut F, p: Path) -> Path {
+  p.into_iter().map(|i| f.fold_ident(i)).collect()
+}
+
+pub fn walk_decl_name<F: Fold + ?Sized>(f: &mut F, n: DeclName) -> DeclName {
+  DeclName {
+    ident: f.fold_ident(n.ident),
+    human: n.human,
+    span: n.span,
+  }
+}
+
+pub fn walk_stmt<F: Fold + ?Sized>(f: &mut F, s: Stmt) -> Stmt {
+  match s {
+    Stmt::Decl(d) => Stmt::Decl(f.fold_decl(d)),
+    Stmt::Prop(p) => Stmt::Prop(f.fold_prop(p)),
+    Stmt::Cond(tags, cond, then, otherwise) => Stmt::Cond(
+      tags
+        .into_iter()
+        .map(|(pos, t)| (pos, f.fold_ident(t)))
+        .collect(),
+      f.fold_expr(cond),
+      fold_stmts(f, then),
+      fold_stmts(f, otherwise),
+    ),
+    Stmt::Modify(d) => Stmt::Modify(f.fold_decl(d)),
+    Stmt::Override(d) => Stmt::Override(f.fold_decl(d)),
+    Stmt::Delete(d) => Stmt::Delete(f.fold_decl(d)),
+  }
+}
+
+pub fn walk_decl<F: Fold + ?Sized>(f: &mut F, d: Decl) -> Decl {
+  match d {
+    Decl::Region(r) => Decl::Region(f.fold_region(r)),
+    Decl::Link(l) => Decl::Link(f.fold_link(l)),
+    Decl::Item(i) => Decl::Item(f.fold_item(i)),
+    Decl::Items(i) => Decl::Items(f.fold_items(i)),
+    Decl::Location(l) => Decl::Location(f.fold_location(l)),
+    Decl::Locations(l) => Decl::Locations(f.fold_locations(l)),
+    Decl::Fn(d) => Decl::Fn(f.fold_fn_decl(d)),
+    Decl::Enum(e) => Decl::Enum(f.fold_enum(e)),
+    Decl::Config(c) => Decl::Config(f.fold_config(c)),
+    Decl::ConfigEnum(c) => Decl::ConfigEnum(f.fold_config_enum(c)),
+    Decl::Configs(c) => Decl::Configs(f.fold_configs(c)),
+    Decl::Configset(c) => Decl::Configset(f.fold_configset(c)),
+    Decl::Random(r) => Decl::Random(f.fold_random(r)),
+  }
+}
+
+pub fn walk_region<F: Fold + ?Sized>(f: &mut F, r: Region) -> Region {
+  Region {
+    name: f.fold_decl_name(r.name),
+    stmts: fold_stmts(f, r.stmts),
+  }
+}
+
+pub fn walk_link_dir<F: Fold + ?Sized>(_f: &mut F, d: LinkDir) -> LinkDir {
+  d
+}
+
+pub fn walk_link<F: Fold + ?Sized>(f: &mut F, l: Link) -> Link {
+  Link {
+    name: l.name.map(|n| f.fold_decl_name(n)),
+    dir: f.fold_link_dir(l.dir),
+    regions: fold_mod_vec(l.regions, |p| f.fold_path(p)),
+    stmts: fold_stmts(f, l.stmts),
+  }
+}
+
+pub fn walk_item<F: Fold + ?Sized>(f: &mut F, i: Item) -> Item {
+  Item {
+    name: f.fold_decl_name(i.name),
+    stmts: fold_stmts(f, i.stmts),
+  }
+}
+
+pub fn walk_items<F: Fold + ?Sized>(f: &mut F, i: Items) -> Items {
+  Items {
+    tags: fold_mod_vec(i.tags, |t| f.fold_ident(t)),
+    items: i.items.into_iter().map(|i| f.fold_item(i)).collect(),
+    nested: i.nested.into_iter().map(|i| f.fold_items(i)).collect(),
+  }
+}
+
+pub fn walk_location<F: Fold + ?Sized>(f: &mut F, l: Location) -> Location {
+  Location {
+    name: f.fold_decl_name(l.name),
+    stmts: fold_stmts(f, l.stmts),
+  }
+}
+
+pub fn walk_locations<F: Fold + ?Sized>(f: &mut F, l: Locations) -> Locations {
+  Locations {
+    decls: l.decls.into_iter().map(|l| f.fold_location(l)).collect(),
+  }
+}
+
+pub fn walk_param<F: Fold + ?Sized>(f: &mut F, p: Param) -> Param {
+  Param {
+    name: f.fold_ident(p.name),
+    ty: p.ty.map(|t| f.fold_ty(t)),
+  }
+}
+
+pub fn walk_fn_decl<F: Fold + ?Sized>(f: &mut F, d: FnDecl) -> FnDecl {
+  FnDecl {
+    name: f.fold_decl_name(d.name),
+    params: d.params.into_iter().map(|p| f.fold_param(p)).collect(),
+    ret_ty: d.ret_ty.map(|t| f.fold_ty(t)),
+    body: f.fold_expr(d.body),
+  }
+}
+
+pub fn walk_enum<F: Fold + ?Sized>(f: &mut F, e: Enum) -> Enum {
+  Enum {
+    name: f.fold_decl_name(e.name),
+    variants: e
+      .variants
+      .into_iter()
+      .map(|n| f.fold_decl_name(n))
+      .collect(),
+  }
+}
+
+pub fn walk_config<F: Fold + ?Sized>(f: &mut F, c: Config) -> Config {
+  Config {
+    name: f.fold_decl_name(c.name),
+    ty: f.fold_ty(c.ty),
+    default: c.default.map(|e| f.fold_expr(e)),
+    stmts: fold_stmts(f, c.stmts),
+  }
+}
+
+pub fn walk_config_enum<F: Fold + ?Sized>(f: &mut F, c: ConfigEnum) -> ConfigEnum {
+  ConfigEnum {
+    name: f.fold_decl_name(c.name),
+    variants: c
+      .variants
+      .into_iter()
+      .map(|n| f.fold_decl_name(n))
+      .collect(),
+    default: c.default.map(|e| f.fold_expr(e)),
+    stmts: fold_stmts(f, c.stmts),
+  }
+}
+
+pub fn walk_typed_config<F: Fold + ?Sized>(f: &mut F, c: TypedConfig) -> TypedConfig {
+  TypedConfig {
+    name: f.fold_decl_name(c.name),
+    default: c.default.map(|e| f.fold_expr(e)),
+    stmts: fold_stmts(f, c.stmts),
+  }
+}
+
+pub fn walk_configs<F: Fold + ?Sized>(f: &mut F, c: Configs) -> Configs {
+  Configs {
+    ty: f.fold_ty(c.ty),
+    configs: c
+      .configs
+      .into_iter()
+      .map(|c| f.fold_typed_config(c))
+      .collect(),
+  }
+}
+
+pub fn walk_configset<F: Fold + ?Sized>(f: &mut F, c: Configset) -> Configset {
+  Configset {
+    name: f.fold_decl_name(c.name),
+    vals: c
+      .vals
+      .into_iter()
+      .map(|(p, e)| (f.fold_path(p), f.fold_expr(e)))
+      .collect(),
+    configsets: c.configsets.into_iter().map(|p| f.fold_path(p)).collect(),
+  }
+}
+
+pub fn walk_random<F: Fold + ?Sized>(f: &mut F, r: Random) -> Random {
+  Random {
+    name: f.fold_decl_name(r.name),
+    vals: fold_mod_vec(r.vals, |e| f.fold_expr(e)),
+  }
+}
+
+pub fn walk_prop<F: Fold + ?Sized>(f: &mut F, p: Prop) -> Prop {
+  match p {
+    Prop::Requires(r) => Prop::Requires(f.fold_requires(r)),
+    Prop::Visible(v) => Prop::Visible(f.fold_visible(v)),
+    Prop::Unlock(u) => Prop::Unlock(f.fold_unlock(u)),
+    Prop::Tag(t) => Prop::Tag(f.fold_tag(t)),
+    Prop::Alias(a) => Prop::Alias(f.fold_alias(a)),
+    Prop::Provides(p) => Prop::Provides(f.fold_provides(p)),
+    Prop::Progressive(p) => Prop::Progressive(f.fold_progressive(p)),
+    Prop::Val(v) => Prop::Val(f.fold_val(v)),
+    Prop::Max(m) => Prop::Max(f.fold_max(m)),
+    Prop::Consumable => Prop::Consumable,
+    Prop::Avail(a) => Prop::Avail(f.fold_avail(a)),
+    Prop::Grants(g) => Prop::Grants(f.fold_grants(g)),
+    Prop::StartWith(s) => Prop::StartWith(f.fold_start_with(s)),
+    Prop::StartIn(s) => Prop::StartIn(f.fold_start_in(s)),
+    Prop::Exclusive(x) => Prop::Exclusive(f.fold_exclusive(x)),
+    Prop::Disallow(d) => Prop::Disallow(f.fold_disallow(d)),
+  }
+}
+
+pub fn walk_requires<F: Fold + ?Sized>(f: &mut F, r: Requires) -> Requires {
+  Requires {
+    cond: f.fold_expr(r.cond),
+  }
+}
+
+pub fn walk_visible<F: Fold + ?Sized>(f: &mut F, v: Visible) -> Visible {
+  Visible {
+    cond: f.fold_expr(v.cond),
+  }
+}
+
+pub fn walk_unlock<F: Fold + ?Sized>(f: &mut F, u: Unlock) -> Unlock {
+  Unlock {
+    item: f.fold_path(u.item),
+  }
+}
+
+pub fn walk_tag<F: Fold + ?Sized>(f: &mut F, t: Tag) -> Tag {
+  Tag {
+    tags: fold_mod_vec(t.tags, |t| f.fold_ident(t)),
+  }
+}
+
+pub fn walk_alias<F: Fold + ?Sized>(f: &mut F, a: Alias) -> Alias {
+  Alias {
+    names: fold_mod_vec(a.names, |n| f.fold_ident(n)),
+  }
+}
+
+pub fn walk_provides<F: Fold + ?Sized>(f: &mut F, p: Provides) -> Provides {
+  Provides {
+    items: fold_mod_vec(p.items, |p| f.fold_path(p)),
+  }
+}
+
+pub fn walk_progressive<F: Fold + ?Sized>(f: &mut F, p: Progressive) -> Progressive {
+  Progressive {
+    items: fold_mod_vec(p.items, |p| f.fold_path(p)),
+  }
+}
+
+pub fn walk_val<F: Fold + ?Sized>(f: &mut F, v: Val) -> Val {
+  Val {
+    name: f.fold_ident(v.name),
+    ty: v.ty.map(|t| f.fold_ty(t)),
+    val: f.fold_expr(v.val),
+  }
+}
+
+pub fn walk_max<F: Fold + ?Sized>(f: &mut F, m: Max) -> Max {
+  Max {
+    expr: f.fold_expr(m.expr),
+  }
+}
+
+pub fn walk_avail<F: Fold + ?Sized>(f: &mut F, a: Avail) -> Avail {
+  Avail {
+    items: fold_mod_vec(a.items, |(has, p, n)| {
+      (has, f.fold_path(p), n.map(|n| f.fold_num(n)))
+    }),
+  }
+}
+
+pub fn walk_grants<F: Fold + ?Sized>(f: &mut F, g: Grants) -> Grants {
+  Grants {
+    items: fold_mod_vec(g.items, |(has, p)| (has, f.fold_path(p))),
+  }
+}
+
+pub fn walk_start_with<F: Fold + ?Sized>(f: &mut F, s: StartWith) -> StartWith {
+  StartWith {
+    items: s.items.into_iter().map(|p| f.fold_path(p)).collect(),
+  }
+}
+
+pub fn walk_start_in<F: Fold + ?Sized>(f: &mut F, s: StartIn) -> StartIn {
+  StartIn {
+    region: f.fold_path(s.region),
+  }
+}
+
+pub fn walk_exclusive<F: Fold + ?Sized>(f: &mut F, x: Exclusive) -> Exclusive {
+  Exclusive {
+    tags: x
+      .tags
+      .into_iter()
+      .map(|(pos, t)| (pos, f.fold_ident(t)))
+      .collect(),
+  }
+}
+
+pub fn walk_disallow<F: Fold + ?Sized>(f: &mut F, d: Disallow) -> Disallow {
+  Disallow {
+    value: f.fold_ident(d.value),
+  }
+}
+
+pub fn walk_expr<F: Fold + ?Sized>(f: &mut F, e: Expr) -> Expr {
+  match e {
+    Expr::Grouped(e) => Expr::Grouped(Box::new(f.fold_expr(*e))),
+    Expr::Num(n) => Expr::Num(f.fold_num(n)),
+    Expr::Bool(b) => Expr::Bool(b),
+    Expr::List(es) => Expr::List(fold_exprs(f, es)),
+    Expr::Name(p) => Expr::Name(f.fold_path(p)),
+    Expr::Call(func, args) => Expr::Call(Box::new(f.fold_expr(*func)), fold_exprs(f, args)),
+    Expr::Builtin(b, args) => Expr::Builtin(f.fold_builtin(b), fold_exprs(f, args)),
+    Expr::Not(e) => Expr::Not(Box::new(f.fold_expr(*e))),
+    Expr::Bin(l, op, r) => {
+      let l = Box::new(f.fold_expr(*l));
+      let op = f.fold_bin_op(op);
+      Expr::Bin(l, op, Box::new(f.fold_expr(*r)))
+    }
+    Expr::And(es) => Expr::And(fold_exprs(f, es)),
+    Expr::Or(es) => Expr::Or(fold_exprs(f, es)),
+    Expr::If(cond, then, otherwise) => Expr::If(
+      Box::new(f.fold_expr(*cond)),
+      Box::new(f.fold_expr(*then)),
+      Box::new(f.fold_expr(*otherwise)),
+    ),
+    Expr::Match(e, arms) => Expr::Match(
+      Box::new(f.fold_expr(*e)),
+      arms.into_iter().map(|a| f.fold_match_arm(a)).collect(),
+    ),
+  }
+}
+
+pub fn walk_bin_op<F: Fold + ?Sized>(_f: &mut F, o: BinOp) -> BinOp {
+  o
+}
+
+pub fn walk_builtin<F: Fold + ?Sized>(_f: &mut F, b: Builtin) -> Builtin {
+  b
+}
+
+pub fn walk_match_arm<F: Fold + ?Sized>(f: &mut F, a: MatchArm) -> MatchArm {
+  MatchArm {
+    pat: f.fold_path(a.pat),
+    expr: f.fold_expr(a.expr),
+  }
+}
+
+pub fn walk_ty<F: Fold + ?Sized>(f: &mut F, t: Ty) -> Ty {
+  match t {
+    Ty::Num => Ty::Num,
+    Ty::Bool => Ty::Bool,
+    Ty::Item => Ty::Item,
+    Ty::Fn(params, ret) => Ty::Fn(
+      params.into_iter().map(|p| f.fold_ty(p)).collect(),
+      Box::new(f.fold_ty(*ret)),
+    ),
+    Ty::List(t) => Ty::List(Box::new(f.fold_ty(*t))),
+    Ty::Name(p) => Ty::Name(f.fold_path(p)),
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  // Renames a single identifier everywhere it appears.
+  struct Rename(&'static str, &'static str);
+
+  impl Fold for Rename {
+    fn fold_ident(&mut self, i: Ident) -> Ident {
+      if i.0 == self.0 {
+        Ident(self.1.to_owned())
+      } else {
+        i
+      }
+    }
+  }
+
+  #[test]
+  fn renames_everywhere() {
+    let f = parse_file(
+      "item Lamp { alias Light }
+       region R { requires Lamp or Other; link to Lamp }
+       fn F(x: Lamp) = count(Lamp)",
+    )
+    .unwrap();
+    let renamed = Rename("Lamp", "Lantern").fold_file(f);
+    let rson = rson_rs::ser::to_string(&renamed).unwrap();
+    assert!(!rson.contains("Lamp"));
+    assert_eq!(5, rson.matches("Lantern").count());
+  }
+}
@@ -0,0 +1,800 @@
+//! Read-only traversal of the AST.
+//!
+//! The [Visit] trait has one method for each AST type. Every method has a
+//! default implementation which calls the matching `walk_*` function in this
+//! module, which in turn visits each child node. An implementation therefore
+//! only needs to override the methods for the nodes it is interested in; to
+//! continue the traversal into the children of such a node, call the `walk_*`
+//! function from the overriding method.
+//!
+//! The lifetime `'ast` allows visitors to hold on to references into the tree
+//! after visiting them.
+
+use super::*;
+
+// Generates a visitor trait and its walk functions. It is shared by Visit and
+// VisitMut, which differ only in the mutability of the references they take.
+// Visit additionally carries a lifetime so that visitors can keep references to
+// the nodes they visit.
+macro_rules! make_visitor {
+  ($(#[$attr:meta])* $trait:ident, [$($lt:lifetime)?], [$($mutability:tt)?]) => {
+    $(#[$attr])*
+    pub trait $trait<$($lt)?> {
+      fn visit_file(&mut self, f: &$($lt)? $($mutability)? File) {
+        walk_file(self, f)
+      }
+      fn visit_ident(&mut self, i: &$($lt)? $($mutability)? Ident) {
+        walk_ident(self, i)
+      }
+      fn visit_num(&mut self, n: &$($lt)? $($mutability)? Num) {
+        walk_num(self, n)
+      }
+      fn visit_path(&mut self, p: &$($lt)? $($mutability)? Path) {
+        walk_path(self, p)
+      }
+      fn visit_decl_name(&mut self, n: &$($lt)? $($mutability)? DeclName) {
+        walk_decl_name(self, n)
+      }
+      fn visit_stmt(&mut self, s: &$($lt)? $($mutability)? Stmt) {
+        walk_stmt(self, s)
+      }
+      fn visit_decl(&mut self, d: &$($lt)? $($mutability)? Decl) {
+        walk_decl(self, d)
+      }
+      fn visit_region(&mut self, r: &$($lt)? $($mutability)? Region) {
+        walk_region(self, r)
+      }
+      fn visit_link_dir(&mut self, d: &$($lt)? $($mutability)? LinkDir) {
+        walk_link_dir(self, d)
+      }
+      fn visit_link(&mut self, l: &$($lt)? $($mutability)? Link) {
+        walk_link(self, l)
+      }
+      fn visit_item(&mut self, i: &$($lt)? $($mutability)? Item) {
+        walk_item(self, i)
+      }
+      fn visit_items(&mut self, i: &$($lt)? $($mutability)? Items) {
+        walk_items(self, i)
+      }
+      fn visit_location(&mut self, l: &$($lt)? $($mutability)? Location) {
+        walk_location(self, l)
+      }
+      fn visit_locations(&mut self, l: &$($lt)? $($mutability)? Locations) {
+        walk_locations(self, l)
+      }
+      fn visit_param(&mut self, p: &$($lt)? $($mutability)? Param) {
+        walk_param(self, p)
+      }
+      fn visit_fn_decl(&mut self, f: &$($lt)? $($mutability)? FnDecl) {
+        walk_fn_decl(self, f)
+      }
+      fn visit_enum(&mut self, e: &$($lt)? $($mutability)? Enum) {
+        walk_enum(self, e)
+      }
+      fn visit_config(&mut self, c: &$($lt)? $($mutability)? Config) {
+        walk_config(self, c)
+      }
+      fn visit_config_enum(&mut self, c: &$($lt)? $($mutability)? ConfigEnum) {
+        walk_config_enum(self, c)
+      }
+      fn visit_typed_config(&mut self, c: &$($lt)? $($mutability)? TypedConfig) {
+        walk_typed_config(self, c)
+      }
+      fn visit_configs(&mut self, c: &$($lt)? $($mutability)? Configs) {
+        walk_configs(self, c)
+      }
+      fn visit_configset(&mut self, c: &$($lt)? $($mutability)? Configset) {
+        walk_configset(self, c)
+      }
+      fn visit_random(&mut self, r: &$($lt)? $($mutability)? Random) {
+        walk_random(self, r)
+      }
+      fn visit_prop(&mut self, p: &$($lt)? $($mutability)? Prop) {
+        walk_prop(self, p)
+      }
+      fn visit_requires(&mut self, r: &$($lt)? $($mutability)? Requires) {
+        walk_requires(self, r)
+      }
+      fn visit_visible(&mut self, v: &$($lt)? $($mutability)? Visible) {
+        walk_visible(self, v)
+      }
+      fn visit_unlock(&mut self, u: &$($lt)? $($mutability)? Unlock) {
+        walk_unlock(self, u)
+      }
+      fn visit_tag(&mut self, t: &$($lt)? $($mutability)? Tag) {
+        walk_tag(self, t)
+      }
+      fn visit_alias(&mut self, a: &$($lt)? $($mutability)? Alias) {
+        walk_alias(self, a)
+      }
+      fn visit_provides(&mut self, p: &$($lt)? $($mutability)? Provides) {
+        walk_provides(self, p)
+      }
+      fn visit_progressive(&mut self, p: &$($lt)? $($mutability)? Progressive) {
+        walk_progressive(self, p)
+      }
+      fn visit_val(&mut self, v: &$($lt)? $($mutability)? Val) {
+        walk_val(self, v)
+      }
+      fn visit_max(&mut self, m: &$($lt)? $($mutability)? Max) {
+        walk_max(self, m)
+      }
+      fn visit_avail(&mut self, a: &$($lt)? $($mutability)? Avail) {
+        walk_avail(self, a)
+      }
+      fn visit_grants(&mut self, g: &$($lt)? $($mutability)? Grants) {
+        walk_grants(self, g)
+      }
+      fn visit_start_with(&mut self, s: &$($lt)? $($mutability)? StartWith) {
+        walk_start_with(self, s)
+      }
+      fn visit_start_in(&mut self, s: &$($lt)? $($mutability)? StartIn) {
+        walk_start_in(self, s)
+      }
+      fn visit_exclusive(&mut self, x: &$($lt)? $($mutability)? Exclusive) {
+        walk_exclusive(self, x)
+      }
+      fn visit_disallow(&mut self, d: &$($lt)? $($mutability)? Disallow) {
+        walk_disallow(self, d)
+      }
+      fn visit_expr(&mut self, e: &$($lt)? $($mutability)? Expr) {
+        walk_expr(self, e)
+      }
+      fn visit_bin_op(&mut self, o: &$($lt)? $($mutability)? BinOp) {
+        walk_bin_op(self, o)
+      }
+      fn visit_builtin(&mut self, b: &$($lt)? $($mutability)? Builtin) {
+        walk_builtin(self, b)
+      }
+      fn visit_match_arm(&mut self, a: &$($lt)? $($mutability)? MatchArm) {
+        walk_match_arm(self, a)
+      }
+      fn visit_ty(&mut self, t: &$($lt)? $($mutability)? Ty) {
+        walk_ty(self, t)
+      }
+    }
+
+    pub fn walk_file<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      v: &mut V,
+      f: &$($lt)? $($mutability)? File,
+    ) {
+      for s in &$($mutability)? f.stmts {
+        v.visit_stmt(s);
+      }
+    }
+
+    pub fn walk_ident<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      _v: &mut V,
+      _i: &$($lt)? $($mutability)? Ident,
+    ) {
+    }
+
+    pub fn walk_num<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      _v: &mut V,
+      _n: &$($lt)? $($mutability)? Num,
+    ) {
+    }
+
+    pub fn walk_path<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      v: &mut V,
+      p: &$($lt)? $($mutability)? Path,
+    ) {
+      for i in p {
+        v.visit_ident(i);
+      }
+    }
+
+    pub fn walk_decl_name<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      v: &mut V,
+      n: &$($lt)? $($mutability)? DeclName,
+    ) {
+      v.visit_ident(&$($mutability)? n.ident);
+    }
+
+    pub fn walk_stmt<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      v: &mut V,
+      s: &$($lt)? $($mutability)? Stmt,
+    ) {
+      match s {
+        Stmt::Decl(d) | Stmt::Modify(d) | Stmt::Override(d) | Stmt::Delete(d) => {
+          v.visit_decl(d)
+        }
+        Stmt::Prop(p) => v.visit_prop(p),
+        Stmt::Cond(tags, cond, then, otherwise) => {
+          for (_, t) in tags {
+            v.visit_ident(t);
+          }
+          v.visit_expr(cond);
+          for s in then {
+            v.visit_stmt(s);
+          }
+          for s in otherwise {
+            v.visit_stmt(s);
+          }
+        }
+      }
+    }
+
+    pub fn walk_decl<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      v: &mut V,
+      d: &$($lt)? $($mutability)? Decl,
+    ) {
+      match d {
+        Decl::Region(r) => v.visit_region(r),
+        Decl::Link(l) => v.visit_link(l),
+        Decl::Item(i) => v.visit_item(i),
+        Decl::Items(i) => v.visit_items(i),
+        Decl::Location(l) => v.visit_location(l),
+        Decl::Locations(l) => v.visit_locations(l),
+        Decl::Fn(f) => v.visit_fn_decl(f),
+        Decl::Enum(e) => v.visit_enum(e),
+        Decl::Config(c) => v.visit_config(c),
+        Decl::ConfigEnum(c) => v.visit_config_enum(c),
+        Decl::Configs(c) => v.visit_configs(c),
+        Decl::Configset(c) => v.visit_configset(c),
+        Decl::Random(r) => v.visit_random(r),
+      }
+    }
+
+    pub fn walk_region<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      v: &mut V,
+      r: &$($lt)? $($mutability)? Region,
+    ) {
+      v.visit_decl_name(&$($mutability)? r.name);
+      for s in &$($mutability)? r.stmts {
+        v.visit_stmt(s);
+      }
+    }
+
+    pub fn walk_link_dir<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      _v: &mut V,
+      _d: &$($lt)? $($mutability)? LinkDir,
+    ) {
+    }
+
+    pub fn walk_link<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      v: &mut V,
+      l: &$($lt)? $($mutability)? Link,
+    ) {
+      if let Some(n) = &$($mutability)? l.name {
+        v.visit_decl_name(n);
+      }
+      v.visit_link_dir(&$($mutability)? l.dir);
+      match &$($mutability)? l.regions {
+        ModVec::New(regions) => {
+          for r in regions {
+            v.visit_path(r);
+          }
+        }
+        ModVec::Mod(regions) => {
+          for (_, r) in regions {
+            v.visit_path(r);
+          }
+        }
+      }
+      for s in &$($mutability)? l.stmts {
+        v.visit_stmt(s);
+      }
+    }
+
+    pub fn walk_item<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      v: &mut V,
+      i: &$($lt)? $($mutability)? Item,
+    ) {
+      v.visit_decl_name(&$($mutability)? i.name);
+      for s in &$($mutability)? i.stmts {
+        v.visit_stmt(s);
+      }
+    }
+
+    pub fn walk_items<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      v: &mut V,
+      i: &$($lt)? $($mutability)? Items,
+    ) {
+      match &$($mutability)? i.tags {
+        ModVec::New(tags) => {
+          for t in tags {
+            v.visit_ident(t);
+          }
+        }
+        ModVec::Mod(tags) => {
+          for (_, t) in tags {
+            v.visit_ident(t);
+          }
+        }
+      }
+      for item in &$($mutability)? i.items {
+        v.visit_item(item);
+      }
+      for nested in &$($mutability)? i.nested {
+        v.visit_items(nested);
+      }
+    }
+
+    pub fn walk_location<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      v: &mut V,
+      l: &$($lt)? $($mutability)? Location,
+    ) {
+      v.visit_decl_name(&$($mutability)? l.name);
+      for s in &$($mutability)? l.stmts {
+        v.visit_stmt(s);
+      }
+    }
+
+    pub fn walk_locations<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      v: &mut V,
+      l: &$($lt)? $($mutability)? Locations,
+    ) {
+      for d in &$($mutability)? l.decls {
+        v.visit_location(d);
+      }
+    }
+
+    pub fn walk_param<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      v: &mut V,
+      p: &$($lt)? $($mutability)? Param,
+    ) {
+      v.visit_ident(&$($mutability)? p.name);
+      if let Some(t) = &$($mutability)? p.ty {
+        v.visit_ty(t);
+      }
+    }
+
+    pub fn walk_fn_decl<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      v: &mut V,
+      f: &$($lt)? $($mutability)? FnDecl,
+    ) {
+      v.visit_decl_name(&$($mutability)? f.name);
+      for p in &$($mutability)? f.params {
+        v.visit_param(p);
+      }
+      if let Some(t) = &$($mutability)? f.ret_ty {
+        v.visit_ty(t);
+      }
+      v.visit_expr(&$($mutability)? f.body);
+    }
+
+    pub fn walk_enum<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      v: &mut V,
+      e: &$($lt)? $($mutability)? Enum,
+    ) {
+      v.visit_decl_name(&$($mutability)? e.name);
+      for n in &$($mutability)? e.variants {
+        v.visit_decl_name(n);
+      }
+    }
+
+    pub fn walk_config<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      v: &mut V,
+      c: &$($lt)? $($mutability)? Config,
+    ) {
+      v.visit_decl_name(&$($mutability)? c.name);
+      v.visit_ty(&$($mutability)? c.ty);
+      if let Some(e) = &$($mutability)? c.default {
+        v.visit_expr(e);
+      }
+      for s in &$($mutability)? c.stmts {
+        v.visit_stmt(s);
+      }
+    }
+
+    pub fn walk_config_enum<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      v: &mut V,
+      c: &$($lt)? $($mutability)? ConfigEnum,
+    ) {
+      v.visit_decl_name(&$($mutability)? c.name);
+      for n in &$($mutability)? c.variants {
+        v.visit_decl_name(n);
+      }
+      if let Some(e) = &$($mutability)? c.default {
+        v.visit_expr(e);
+      }
+      for s in &$($mutability)? c.stmts {
+        v.visit_stmt(s);
+      }
+    }
+
+    pub fn walk_typed_config<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      v: &mut V,
+      c: &$($lt)? $($mutability)? TypedConfig,
+    ) {
+      v.visit_decl_name(&$($mutability)? c.name);
+      if let Some(e) = &$($mutability)? c.default {
+        v.visit_expr(e);
+      }
+      for s in &$($mutability)? c.stmts {
+        v.visit_stmt(s);
+      }
+    }
+
+    pub fn walk_configs<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      v: &mut V,
+      c: &$($lt)? $($mutability)? Configs,
+    ) {
+      v.visit_ty(&$($mutability)? c.ty);
+      for c in &$($mutability)? c.configs {
+        v.visit_typed_config(c);
+      }
+    }
+
+    pub fn walk_configset<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      v: &mut V,
+      c: &$($lt)? $($mutability)? Configset,
+    ) {
+      v.visit_decl_name(&$($mutability)? c.name);
+      for (p, e) in &$($mutability)? c.vals {
+        v.visit_path(p);
+        v.visit_expr(e);
+      }
+      for p in &$($mutability)? c.configsets {
+        v.visit_path(p);
+      }
+    }
+
+    pub fn walk_random<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      v: &mut V,
+      r: &$($lt)? $($mutability)? Random,
+    ) {
+      v.visit_decl_name(&$($mutability)? r.name);
+      match &$($mutability)? r.vals {
+        ModVec::New(vals) => {
+          for e in vals {
+            v.visit_expr(e);
+          }
+        }
+        ModVec::Mod(vals) => {
+          for (_, e) in vals {
+            v.visit_expr(e);
+          }
+        }
+      }
+    }
+
+    pub fn walk_prop<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      v: &mut V,
+      p: &$($lt)? $($mutability)? Prop,
+    ) {
+      match p {
+        Prop::Requires(r) => v.visit_requires(r),
+        Prop::Visible(vis) => v.visit_visible(vis),
+        Prop::Unlock(u) => v.visit_unlock(u),
+        Prop::Tag(t) => v.visit_tag(t),
+        Prop::Alias(a) => v.visit_alias(a),
+        Prop::Provides(p) => v.visit_provides(p),
+        Prop::Progressive(p) => v.visit_progressive(p),
+        Prop::Val(val) => v.visit_val(val),
+        Prop::Max(m) => v.visit_max(m),
+        Prop::Consumable => {}
+        Prop::Avail(a) => v.visit_avail(a),
+        Prop::Grants(g) => v.visit_grants(g),
+        Prop::StartWith(s) => v.visit_start_with(s),
+        Prop::StartIn(s) => v.visit_start_in(s),
+        Prop::Exclusive(x) => v.visit_exclusive(x),
+        Prop::Disallow(d) => v.visit_disallow(d),
+      }
+    }
+
+    pub fn walk_requires<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      v: &mut V,
+      r: &$($lt)? $($mutability)? Requires,
+    ) {
+      v.visit_expr(&$($mutability)? r.cond);
+    }
+
+    pub fn walk_visible<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      v: &mut V,
+      vis: &$($lt)? $($mutability)? Visible,
+    ) {
+      v.visit_expr(&$($mutability)? vis.cond);
+    }
+
+    pub fn walk_unlock<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      v: &mut V,
+      u: &$($lt)? $($mutability)? Unlock,
+    ) {
+      v.visit_path(&$($mutability)? u.item);
+    }
+
+    pub fn walk_tag<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      v: &mut V,
+      t: &$($lt)? $($mutability)? Tag,
+    ) {
+      match &$($mutability)? t.tags {
+        ModVec::New(tags) => {
+          for t in tags {
+            v.visit_ident(t);
+          }
+        }
+        ModVec::Mod(tags) => {
+          for (_, t) in tags {
+            v.visit_ident(t);
+          }
+        }
+      }
+    }
+
+    pub fn walk_alias<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      v: &mut V,
+      a: &$($lt)? $($mutability)? Alias,
+    ) {
+      match &$($mutability)? a.names {
+        ModVec::New(names) => {
+          for n in names {
+            v.visit_ident(n);
+          }
+        }
+        ModVec::Mod(names) => {
+          for (_, n) in names {
+            v.visit_ident(n);
+          }
+        }
+      }
+    }
+
+    pub fn walk_provides<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      v: &mut V,
+      p: &$($lt)? $($mutability)? Provides,
+    ) {
+      match &$($mutability)? p.items {
+        ModVec::New(items) => {
+          for i in items {
+            v.visit_path(i);
+          }
+        }
+        ModVec::Mod(items) => {
+          for (_, i) in items {
+            v.visit_path(i);
+          }
+        }
+      }
+    }
+
+    pub fn walk_progressive<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      v: &mut V,
+      p: &$($lt)? $($mutability)? Progressive,
+    ) {
+      match &$($mutability)? p.items {
+        ModVec::New(items) => {
+          for i in items {
+            v.visit_path(i);
+          }
+        }
+        ModVec::Mod(items) => {
+          for (_, i) in items {
+            v.visit_path(i);
+          }
+        }
+      }
+    }
+
+    pub fn walk_val<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      v: &mut V,
+      val: &$($lt)? $($mutability)? Val,
+    ) {
+      v.visit_ident(&$($mutability)? val.name);
+      if let Some(t) = &$($mutability)? val.ty {
+        v.visit_ty(t);
+      }
+      v.visit_expr(&$($mutability)? val.val);
+    }
+
+    pub fn walk_max<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      v: &mut V,
+      m: &$($lt)? $($mutability)? Max,
+    ) {
+      v.visit_expr(&$($mutability)? m.expr);
+    }
+
+    pub fn walk_avail<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      v: &mut V,
+      a: &$($lt)? $($mutability)? Avail,
+    ) {
+      match &$($mutability)? a.items {
+        ModVec::New(items) => {
+          for (_, p, n) in items {
+            v.visit_path(p);
+            if let Some(n) = n {
+              v.visit_num(n);
+            }
+          }
+        }
+        ModVec::Mod(items) => {
+          for (_, (_, p, n)) in items {
+            v.visit_path(p);
+            if let Some(n) = n {
+              v.visit_num(n);
+            }
+          }
+        }
+      }
+    }
+
+    pub fn walk_grants<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      v: &mut V,
+      g: &$($lt)? $($mutability)? Grants,
+    ) {
+      match &$($mutability)? g.items {
+        ModVec::New(items) => {
+          for (_, p) in items {
+            v.visit_path(p);
+          }
+        }
+        ModVec::Mod(items) => {
+          for (_, (_, p)) in items {
+            v.visit_path(p);
+          }
+        }
+      }
+    }
+
+    pub fn walk_start_with<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      v: &mut V,
+      s: &$($lt)? $($mutability)? StartWith,
+    ) {
+      for p in &$($mutability)? s.items {
+        v.visit_path(p);
+      }
+    }
+
+    pub fn walk_start_in<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      v: &mut V,
+      s: &$($lt)? $($mutability)? StartIn,
+    ) {
+      v.visit_path(&$($mutability)? s.region);
+    }
+
+    pub fn walk_exclusive<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      v: &mut V,
+      x: &$($lt)? $($mutability)? Exclusive,
+    ) {
+      for (_, t) in &$($mutability)? x.tags {
+        v.visit_ident(t);
+      }
+    }
+
+    pub fn walk_disallow<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      v: &mut V,
+      d: &$($lt)? $($mutability)? Disallow,
+    ) {
+      v.visit_ident(&$($mutability)? d.value);
+    }
+
+    pub fn walk_expr<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      v: &mut V,
+      e: &$($lt)? $($mutability)? Expr,
+    ) {
+      match e {
+        Expr::Grouped(e) | Expr::Not(e) => v.visit_expr(e),
+        Expr::Num(n) => v.visit_num(n),
+        Expr::Bool(_) => {}
+        Expr::List(es) | Expr::And(es) | Expr::Or(es) => {
+          for e in es {
+            v.visit_expr(e);
+          }
+        }
+        Expr::Name(p) => v.visit_path(p),
+        Expr::Call(f, args) => {
+          v.visit_expr(f);
+          for a in args {
+            v.visit_expr(a);
+          }
+        }
+        Expr::Builtin(b, args) => {
+          v.visit_builtin(b);
+          for a in args {
+            v.visit_expr(a);
+          }
+        }
+        Expr::Bin(l, op, r) => {
+          v.visit_expr(l);
+          v.visit_bin_op(op);
+          v.visit_expr(r);
+        }
+        Expr::If(cond, then, otherwise) => {
+          v.visit_expr(cond);
+          v.visit_expr(then);
+          v.visit_expr(otherwise);
+        }
+        Expr::Match(e, arms) => {
+          v.visit_expr(e);
+          for a in arms {
+            v.visit_match_arm(a);
+          }
+        }
+      }
+    }
+
+    pub fn walk_bin_op<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      _v: &mut V,
+      _o: &$($lt)? $($mutability)? BinOp,
+    ) {
+    }
+
+    pub fn walk_builtin<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      _v: &mut V,
+      _b: &$($lt)? $($mutability)? Builtin,
+    ) {
+    }
+
+    pub fn walk_match_arm<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      v: &mut V,
+      a: &$($lt)? $($mutability)? MatchArm,
+    ) {
+      v.visit_path(&$($mutability)? a.pat);
+      v.visit_expr(&$($mutability)? a.expr);
+    }
+
+    pub fn walk_ty<$($lt,)? V: $trait<$($lt)?> + ?Sized>(
+      v: &mut V,
+      t: &$($lt)? $($mutability)? Ty,
+    ) {
+      match t {
+        Ty::Num | Ty::Bool | Ty::Item => {}
+        Ty::Fn(params, ret) => {
+          for p in params {
+            v.visit_ty(p);
+          }
+          v.visit_ty(ret);
+        }
+        Ty::List(t) => v.visit_ty(t),
+        Ty::Name(p) => v.visit_path(p),
+      }
+    }
+  };
+}
+
+make_visitor!(
+  /// A visitor over shared references to the AST.
+  Visit,
+  ['ast],
+  []
+);
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use std::collections::HashSet;
+
+  // Collects every name referenced from an expression, without caring about
+  // any other part of the tree.
+  #[derive(Default)]
+  struct Names<'ast>(HashSet<&'ast str>);
+
+  impl<'ast> Visit<'ast> for Names<'ast> {
+    fn visit_expr(&mut self, e: &'ast Expr) {
+      if let Expr::Name(p) = e {
+        self.0.extend(p.iter().map(|i| &*i.0));
+      }
+      walk_expr(self, e);
+    }
+  }
+
+  #[derive(Default)]
+  struct CountItems(usize);
+
+  impl<'ast> Visit<'ast> for CountItems {
+    fn visit_item(&mut self, _: &'ast Item) {
+      self.0 += 1;
+    }
+  }
+
+  #[test]
+  fn visits_nested_nodes() {
+    let f = parse_file(
+      "items Tag { A; items Nested { B; C } }
+       item D
+       region R { if X == 1 { item E } else { item F } }
+       fn G(x) = match x { V => Y and Z.W, }",
+    )
+    .unwrap();
+
+    let mut items = CountItems::default();
+    items.visit_file(&f);
+    assert_eq!(6, items.0);
+
+    let mut names = Names::default();
+    names.visit_file(&f);
+    let expected: HashSet<_> = vec!["X", "x", "Y", "Z", "W"].into_iter().collect();
+    assert_eq!(expected, names.0);
+  }
+}
@@ -0,0 +1,49 @@
+//! In-place mutating traversal of the AST.
+//!
+//! [VisitMut] is identical to [Visit](super::visit::Visit), except that it
+//! takes mutable references to each node, so that visitors can edit the tree as
+//! they go. As with `Visit`, each default method calls the matching `walk_*`
+//! function in this module to continue into the node's children.
+
+use super::*;
+
+make_visitor!(
+  /// A visitor over mutable references to the AST.
+  VisitMut,
+  [],
+  [mut]
+);
+
+#[cfg(test)]
+mod test {
+  use super::*;
+
+  // Negates every boolean literal.
+  struct Negate;
+
+  impl VisitMut for Negate {
+    fn visit_expr(&mut self, e: &mut Expr) {
+      if let Expr::Bool(b) = e {
+        *b = !*b;
+      }
+      walk_expr(self, e);
+    }
+  }
+
+  #[test]
+  fn mutates_in_place() {
+    let mut f = parse_file("region R { requires true; location L { visible false } }").unwrap();
+    Negate.visit_file(&mut f);
+    let mut bools = Vec::new();
+    struct Collect<'a>(&'a mut Vec<bool>);
+    impl<'a> VisitMut for Collect<'a> {
+      fn visit_expr(&mut self, e: &mut Expr) {
+        if let Expr::Bool(b) = e {
+          self.0.push(*b);
+        }
+      }
+    }
+    Collect(&mut bools).visit_file(&mut f);
+    assert_eq!(vec![false, true], bools);
+  }
+}
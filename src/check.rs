@@ -0,0 +1,672 @@
+//! Type checking of resolved expressions.
+//!
+//! Types are inferred by unification, so function parameters, return types and
+//! item values need not be annotated. Where a value is used, an int can stand
+//! in for a num, and an item or a list of items for a bool, which tests whether
+//! the player has it.
+//!
+//! A value written through a tag, as in `Tag.Val`, is the list of that value
+//! on every item with the tag, so all those items must agree on its type.
+
+use crate::ast::{BinOp, Builtin, Span};
+use crate::{CompileError, ConfigTy, Diagnostics, EntityId, Expr, Id, Ident, Program, Ty};
+use indexmap::IndexMap;
+use std::collections::HashMap;
+
+/// Check the types of all the expressions in a program, and fill in the types
+/// which were left to be inferred.
+pub(crate) fn check(program: &mut Program, diags: &mut Diagnostics) {
+  let mut c = Checker::new(program);
+  c.check_all();
+  // Inference failures are not reported after other errors, since the
+  // expression which would have determined a type may be the one missing.
+  let quiet = !diags.errors.is_empty() || !c.errors.is_empty();
+  let (fns, vals) = c.finish_all(quiet);
+  diags.errors.extend(c.errors);
+
+  for (id, params, ret) in fns {
+    let f = &mut program.functions[id.0];
+    for (p, t) in f.params.iter_mut().zip(params) {
+      p.ty = t;
+    }
+    f.ret_ty = ret;
+  }
+  for (id, name, t) in vals {
+    program.items[id.0].vals.get_mut(&name).unwrap().ty = t;
+  }
+}
+
+// A type which may not be completely known yet.
+#[derive(Clone, Debug)]
+enum T {
+  Int,
+  Num,
+  Bool,
+  Item,
+  Fn(Vec<T>, Box<T>),
+  List(Box<T>),
+  Enum(Id),
+  Var(usize),
+}
+
+impl From<&Ty> for T {
+  fn from(ty: &Ty) -> T {
+    match ty {
+      Ty::Int => T::Int,
+      Ty::Num => T::Num,
+      Ty::Bool => T::Bool,
+      Ty::Item => T::Item,
+      Ty::Fn(params, ret) => T::Fn(
+        params.iter().map(T::from).collect(),
+        Box::new((&**ret).into()),
+      ),
+      Ty::List(t) => T::List(Box::new((&**t).into())),
+      Ty::Enum(e) => T::Enum(*e),
+    }
+  }
+}
+
+type Sig = (Vec<T>, T);
+
+struct Checker<'a> {
+  program: &'a Program,
+  // The binding of each type variable, once it is known.
+  vars: Vec<Option<T>>,
+  sigs: HashMap<Id, Sig>,
+  randoms: HashMap<Id, T>,
+  // The types of item values which were not declared.
+  vals: IndexMap<(Id, Ident), T>,
+  // The type of each value of the items with each tag, by tag and value name.
+  tag_vals: HashMap<(Ident, Ident), T>,
+  // The parameters of the function being checked.
+  params: Vec<T>,
+  // The name of the declaration being checked, for errors.
+  span: Option<Span>,
+  errors: Vec<CompileError>,
+}
+
+impl<'a> Checker<'a> {
+  fn new(program: &'a Program) -> Checker<'a> {
+    Checker {
+      program,
+      vars: Vec::new(),
+      sigs: HashMap::new(),
+      randoms: HashMap::new(),
+      vals: IndexMap::new(),
+      tag_vals: HashMap::new(),
+      params: Vec::new(),
+      span: None,
+      errors: Vec::new(),
+    }
+  }
+
+  fn check_all(&mut self) {
+    let p = self.program;
+    for (id, f) in p.functions.iter() {
+      let params = f
+        .params
+        .iter()
+        .map(|param| self.declared(param.ty.as_ref()))
+        .collect();
+      let ret = self.declared(f.ret_ty.as_ref());
+      self.sigs.insert(Id(id), (params, ret));
+    }
+
+    // Randoms are checked first since their values are normally literals,
+    // and they can then be used anywhere else with a known type.
+    for (id, r) in p.randoms.iter() {
+      self.span = r.name.span;
+      let mut ty = self.fresh();
+      for v in &r.vals {
+        let t = self.expr(v);
+        ty = self.join(&ty, &t);
+      }
+      self.randoms.insert(Id(id), ty);
+    }
+
+    // Item values are checked next for the same reason, and because tag values
+    // need their types to be consistent across the tagged items.
+    for (id, i) in p.items.iter() {
+      for (name, v) in &i.vals {
+        if v.ty.is_none() {
+          let t = self.fresh();
+          self.vals.insert((Id(id), *name), t);
+        }
+      }
+    }
+    for (id, i) in p.items.iter() {
+      self.span = i.name.span;
+      for (name, v) in &i.vals {
+        let t = self.expr(&v.val);
+        let ty = self.val_ty(Id(id), *name);
+        self.expect(&t, &ty);
+      }
+    }
+    for (id, i) in p.items.iter() {
+      self.span = i.name.span;
+      for tag in &i.tags {
+        for name in i.vals.keys() {
+          let ty = self.val_ty(Id(id), *name);
+          let joined = match self.tag_vals.get(&(*tag, *name)).cloned() {
+            None => ty,
+            Some(other) => match self.try_join(&other, &ty) {
+              Some(t) => t,
+              None => {
+                self.errors.push(CompileError::TagValMismatch {
+                  tag: p.path_str(&[*tag]),
+                  val: p.path_str(&[*name]),
+                  expected: self.ty_str(&other),
+                  found: self.ty_str(&ty),
+                  span: self.span,
+                });
+                other
+              }
+            },
+          };
+          self.tag_vals.insert((*tag, *name), joined);
+        }
+      }
+    }
+
+    for (id, f) in p.functions.iter() {
+      self.span = f.name.span;
+      let (params, ret) = self.sigs[&Id(id)].clone();
+      self.params = params;
+      if let Some(body) = &f.body {
+        let t = self.expr(body);
+        self.expect(&t, &ret);
+      }
+    }
+    self.params.clear();
+
+    for c in p.configs.values() {
+      self.span = c.name.span;
+      if let Some(default) = &c.default {
+        let t = self.expr(default);
+        self.expect(&t, &config_ty(&c.ty));
+      }
+    }
+
+    for c in p.configsets.values() {
+      self.span = c.name.span;
+      for (config, val) in &c.vals {
+        let t = self.expr(val);
+        self.expect(&t, &config_ty(&p.configs[config.0].ty));
+      }
+    }
+
+    self.span = None;
+    self.condition(p.props.requires.as_ref());
+    self.condition(p.props.visible.as_ref());
+    for r in p.regions.values() {
+      self.span = r.name.span;
+      self.condition(r.props.requires.as_ref());
+      self.condition(r.props.visible.as_ref());
+    }
+    for i in p.items.values() {
+      self.span = i.name.span;
+      if let Some(max) = &i.max {
+        let t = self.expr(max);
+        self.expect(&t, &T::Int);
+      }
+    }
+    for l in p.locations.values() {
+      self.span = l.name.span;
+      self.condition(l.requires.as_ref());
+      self.condition(l.visible.as_ref());
+    }
+    for l in p.links.values() {
+      self.span = l.name.as_ref().and_then(|n| n.span);
+      self.condition(l.requires.as_ref());
+    }
+    for c in p.conds.values() {
+      self.span = p.scope_span(c.parent);
+      self.condition(Some(&c.cond));
+    }
+  }
+
+  // Convert the inferred types to their final form. Types which could not be
+  // inferred are None, and reported unless quiet is set.
+  #[allow(clippy::type_complexity)]
+  fn finish_all(
+    &mut self,
+    quiet: bool,
+  ) -> (
+    Vec<(Id, Vec<Option<Ty>>, Option<Ty>)>,
+    Vec<(Id, Ident, Option<Ty>)>,
+  ) {
+    let p = self.program;
+    let mut missing = Vec::new();
+    let mut fns = Vec::new();
+    for (id, f) in p.functions.iter() {
+      let (params, ret) = &self.sigs[&Id(id)];
+      let params = params
+        .iter()
+        .zip(&f.params)
+        .map(|(t, param)| {
+          let t = self.finish(t);
+          if t.is_none() {
+            let name = p.path_str(&[f.name.ident, param.name]);
+            missing.push((name, f.name.span));
+          }
+          t
+        })
+        .collect();
+      let ret = self.finish(ret);
+      if ret.is_none() {
+        missing.push((p.path_str(&[f.name.ident]), f.name.span));
+      }
+      fns.push((Id(id), params, ret));
+    }
+    let mut vals = Vec::new();
+    for ((id, name), t) in &self.vals {
+      let t = self.finish(t);
+      if t.is_none() {
+        let item = &p.items[id.0].name;
+        missing.push((p.path_str(&[item.ident, *name]), item.span));
+      }
+      vals.push((*id, *name, t));
+    }
+    if !quiet {
+      for (name, span) in missing {
+        self.errors.push(CompileError::CannotInfer { name, span });
+      }
+    }
+    (fns, vals)
+  }
+
+  fn declared(&mut self, ty: Option<&Ty>) -> T {
+    match ty {
+      Some(ty) => ty.into(),
+      None => self.fresh(),
+    }
+  }
+
+  // The type of an item's value, declared or being inferred.
+  fn val_ty(&self, item: Id, name: Ident) -> T {
+    match &self.program.items[item.0].vals[&name].ty {
+      Some(ty) => ty.into(),
+      None => self.vals[&(item, name)].clone(),
+    }
+  }
+
+  fn fresh(&mut self) -> T {
+    self.vars.push(None);
+    T::Var(self.vars.len() - 1)
+  }
+
+  // Follow variable bindings at the top level of a type.
+  fn shallow(&self, t: &T) -> T {
+    let mut t = t.clone();
+    while let T::Var(v) = t {
+      match &self.vars[v] {
+        Some(bound) => t = bound.clone(),
+        None => break,
+      }
+    }
+    t
+  }
+
+  fn occurs(&self, v: usize, t: &T) -> bool {
+    match self.shallow(t) {
+      T::Var(w) => v == w,
+      T::Fn(params, ret) => params.iter().any(|p| self.occurs(v, p)) || self.occurs(v, &ret),
+      T::List(t) => self.occurs(v, &t),
+      _ => false,
+    }
+  }
+
+  fn unify(&mut self, a: &T, b: &T) -> bool {
+    match (self.shallow(a), self.shallow(b)) {
+      (T::Var(v), T::Var(w)) if v == w => true,
+      (T::Var(v), t) | (t, T::Var(v)) => {
+        if self.occurs(v, &t) {
+          return false;
+        }
+        self.vars[v] = Some(t);
+        true
+      }
+      (T::Fn(ps, r), T::Fn(qs, s)) => {
+        ps.len() == qs.len()
+          && ps.iter().zip(&qs).all(|(p, q)| self.unify(p, q))
+          && self.unify(&r, &s)
+      }
+      (T::List(a), T::List(b)) => self.unify(&a, &b),
+      (T::Enum(a), T::Enum(b)) => a == b,
+      (T::Int, T::Int) | (T::Num, T::Num) | (T::Bool, T::Bool) | (T::Item, T::Item) => true,
+      _ => false,
+    }
+  }
+
+  // Whether a value of type `from` can be used where `to` is expected.
+  fn coerce(&mut self, from: &T, to: &T) -> bool {
+    match (self.shallow(from), self.shallow(to)) {
+      (T::Int, T::Num) | (T::Item, T::Bool) => true,
+      (T::List(t), T::Bool) => self.unify(&t, &T::Item),
+      (T::List(a), T::List(b)) => self.coerce(&a, &b),
+      (a, b) => self.unify(&a, &b),
+    }
+  }
+
+  fn expect(&mut self, found: &T, expected: &T) {
+    if !self.coerce(found, expected) {
+      let expected = self.ty_str(expected);
+      self.mismatch(expected, found);
+    }
+  }
+
+  fn mismatch(&mut self, expected: String, found: &T) {
+    self.errors.push(CompileError::TypeMismatch {
+      expected,
+      found: self.ty_str(found),
+      span: self.span,
+    });
+  }
+
+  // The type which values of both types can be used as, for the branches of
+  // a conditional or the elements of a list.
+  fn join(&mut self, a: &T, b: &T) -> T {
+    match self.try_join(a, b) {
+      Some(t) => t,
+      None => {
+        self.mismatch(self.ty_str(a), b);
+        a.clone()
+      }
+    }
+  }
+
+  fn try_join(&mut self, a: &T, b: &T) -> Option<T> {
+    match (self.shallow(a), self.shallow(b)) {
+      (T::Int, T::Num) | (T::Num, T::Int) => Some(T::Num),
+      (a, b) if self.unify(&a, &b) => Some(a),
+      _ => None,
+    }
+  }
+
+  // Check that a type is numeric. If it is not known yet, it is taken to be
+  // num, which every number can be used as.
+  fn numeric(&mut self, t: &T) -> T {
+    match self.shallow(t) {
+      t @ T::Int | t @ T::Num => t,
+      T::Var(v) => {
+        self.vars[v] = Some(T::Num);
+        T::Num
+      }
+      t => {
+        self.mismatch("num".into(), &t);
+        T::Num
+      }
+    }
+  }
+
+  fn condition(&mut self, cond: Option<&Expr>) {
+    if let Some(cond) = cond {
+      let t = self.expr(cond);
+      self.expect(&t, &T::Bool);
+    }
+  }
+
+  fn expr(&mut self, e: &Expr) -> T {
+    match e {
+      Expr::Num(n) if n.is_integer() => T::Int,
+      Expr::Num(_) => T::Num,
+      Expr::Bool(_) => T::Bool,
+      Expr::List(es) => {
+        let mut elem = self.fresh();
+        for e in es {
+          let t = self.expr(e);
+          elem = self.join(&elem, &t);
+        }
+        T::List(Box::new(elem))
+      }
+      Expr::Entity(e) => self.entity(*e),
+      Expr::Param(i) => self.params[*i].clone(),
+      Expr::TagVals(tag, val) => match self.tag_vals.get(&(*tag, *val)) {
+        Some(t) => T::List(Box::new(t.clone())),
+        None => {
+          let p = self.program;
+          self.errors.push(CompileError::NoSuchVal {
+            tag: p.path_str(&[*tag]),
+            val: p.path_str(&[*val]),
+            span: self.span,
+          });
+          self.fresh()
+        }
+      },
+      Expr::Call(f, args) => {
+        let f = self.expr(f);
+        let args = args.iter().map(|a| self.expr(a)).collect::<Vec<_>>();
+        self.call(&f, &args)
+      }
+      Expr::Builtin(b, args) => {
+        let args = args.iter().map(|a| self.expr(a)).collect::<Vec<_>>();
+        self.builtin(b, &args)
+      }
+      Expr::Not(e) => {
+        let t = self.expr(e);
+        self.expect(&t, &T::Bool);
+        T::Bool
+      }
+      Expr::Bin(l, op, r) => {
+        let l = self.expr(l);
+        let r = self.expr(r);
+        match op {
+          BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Mod => {
+            match (self.numeric(&l), self.numeric(&r)) {
+              (T::Int, T::Int) => T::Int,
+              _ => T::Num,
+            }
+          }
+          BinOp::Div => {
+            self.numeric(&l);
+            self.numeric(&r);
+            T::Num
+          }
+          BinOp::Eq | BinOp::NEq => {
+            self.join(&l, &r);
+            T::Bool
+          }
+          BinOp::LT | BinOp::LE | BinOp::GT | BinOp::GE => {
+            self.numeric(&l);
+            self.numeric(&r);
+            T::Bool
+          }
+        }
+      }
+      Expr::And(es) | Expr::Or(es) => {
+        for e in es {
+          let t = self.expr(e);
+          self.expect(&t, &T::Bool);
+        }
+        T::Bool
+      }
+      Expr::If(c, t, f) => {
+        let c = self.expr(c);
+        self.expect(&c, &T::Bool);
+        let t = self.expr(t);
+        let f = self.expr(f);
+        self.join(&t, &f)
+      }
+      Expr::Match(e, arms) => {
+        let t = self.expr(e);
+        let mut result = self.fresh();
+        for arm in arms {
+          let enum_id = self.program.variants[arm.variant.0].enum_id;
+          self.expect(&t, &T::Enum(enum_id));
+          let a = self.expr(&arm.expr);
+          result = self.join(&result, &a);
+        }
+        result
+      }
+    }
+  }
+
+  fn entity(&mut self, e: EntityId) -> T {
+    let p = self.program;
+    match e {
+      EntityId::Item(_) => T::Item,
+      // A tag stands for all the items with that tag.
+      EntityId::Tag(_) => T::List(Box::new(T::Item)),
+      EntityId::Config(c) => config_ty(&p.configs[c.0].ty),
+      EntityId::Variant(v) => T::Enum(p.variants[v.0].enum_id),
+      // Functions without parameters are used by name alone, so they stand
+      // for their result.
+      EntityId::Function(f) => match self.sigs[&f].clone() {
+        (params, ret) if params.is_empty() => ret,
+        (params, ret) => T::Fn(params, Box::new(ret)),
+      },
+      EntityId::Random(r) => self.randoms[&r].clone(),
+      EntityId::Region(_)
+      | EntityId::Location(_)
+      | EntityId::Link(_)
+      | EntityId::Enum(_)
+      | EntityId::Configset(_)
+      | EntityId::Cond(_) => {
+        self.errors.push(CompileError::NotAValue {
+          name: entity_name(p, e),
+          span: self.span,
+        });
+        self.fresh()
+      }
+    }
+  }
+
+  fn call(&mut self, f: &T, args: &[T]) -> T {
+    let (params, ret) = match self.shallow(f) {
+      T::Fn(params, ret) => (params, *ret),
+      T::Var(v) => {
+        let params = args.iter().map(|_| self.fresh()).collect::<Vec<_>>();
+        let ret = self.fresh();
+        self.vars[v] = Some(T::Fn(params.clone(), Box::new(ret.clone())));
+        (params, ret)
+      }
+      t => {
+        self.errors.push(CompileError::NotAFunction {
+          found: self.ty_str(&t),
+          span: self.span,
+        });
+        return self.fresh();
+      }
+    };
+    // A function taking a single list can be passed its elements directly.
+    if let [param] = &params[..] {
+      if let T::List(elem) = self.shallow(param) {
+        if args.len() != 1 {
+          for a in args {
+            self.expect(a, &elem);
+          }
+          return ret;
+        }
+      }
+    }
+    if params.len() != args.len() {
+      self.errors.push(CompileError::WrongArgCount {
+        expected: params.len(),
+        found: args.len(),
+        span: self.span,
+      });
+    } else {
+      for (a, p) in args.iter().zip(&params) {
+        self.expect(a, p);
+      }
+    }
+    ret
+  }
+
+  fn builtin(&mut self, b: &Builtin, args: &[T]) -> T {
+    // Builtins operate on a single list. Given several arguments, they operate
+    // on all of them together, with any lists among them flattened.
+    let elem = match args {
+      [a] => match self.shallow(a) {
+        T::List(t) => *t,
+        T::Var(v) => {
+          let t = self.fresh();
+          self.vars[v] = Some(T::List(Box::new(t.clone())));
+          t
+        }
+        t => t,
+      },
+      _ => {
+        let mut elem = self.fresh();
+        for a in args {
+          let a = match self.shallow(a) {
+            T::List(t) => *t,
+            t => t,
+          };
+          elem = self.join(&elem, &a);
+        }
+        elem
+      }
+    };
+    match b {
+      Builtin::Count => {
+        self.expect(&elem, &T::Item);
+        T::Int
+      }
+      Builtin::Max | Builtin::Min | Builtin::Sum => self.numeric(&elem),
+    }
+  }
+
+  fn finish(&self, t: &T) -> Option<Ty> {
+    Some(match self.shallow(t) {
+      T::Int => Ty::Int,
+      T::Num => Ty::Num,
+      T::Bool => Ty::Bool,
+      T::Item => Ty::Item,
+      T::Fn(params, ret) => Ty::Fn(
+        params
+          .iter()
+          .map(|p| self.finish(p))
+          .collect::<Option<_>>()?,
+        Box::new(self.finish(&ret)?),
+      ),
+      T::List(t) => Ty::List(Box::new(self.finish(&t)?)),
+      T::Enum(e) => Ty::Enum(e),
+      T::Var(_) => return None,
+    })
+  }
+
+  fn ty_str(&self, t: &T) -> String {
+    match self.shallow(t) {
+      T::Int => "int".into(),
+      T::Num => "num".into(),
+      T::Bool => "bool".into(),
+      T::Item => "item".into(),
+      T::Fn(params, ret) => format!(
+        "fn ({}) -> {}",
+        params
+          .iter()
+          .map(|p| self.ty_str(p))
+          .collect::<Vec<_>>()
+          .join(", "),
+        self.ty_str(&ret)
+      ),
+      T::List(t) => format!("[{}]", self.ty_str(&t)),
+      T::Enum(e) => self.program.path_str(&[self.program.enums[e.0].name.ident]),
+      T::Var(_) => "_".into(),
+    }
+  }
+}
+
+fn config_ty(ty: &ConfigTy) -> T {
+  match ty {
+    ConfigTy::Declared(ty) => ty.into(),
+    ConfigTy::Enum(e) => T::Enum(*e),
+  }
+}
+
+// The name of an entity which is not a value, for messages.
+fn entity_name(p: &Program, e: EntityId) -> String {
+  let name = match e {
+    EntityId::Region(r) => &p.regions[r.0].name,
+    EntityId::Location(l) => &p.locations[l.0].name,
+    EntityId::Link(l) => match &p.links[l.0].name {
+      Some(n) => n,
+      None => return "link".into(),
+    },
+    EntityId::Enum(e) => &p.enums[e.0].name,
+    EntityId::Configset(c) => &p.configsets[c.0].name,
+    _ => return format!("{:?}", e),
+  };
+  p.path_str(&[name.ident])
+}
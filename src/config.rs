@@ -0,0 +1,1124 @@
+//! Configuring programs with values for their configs.
+//!
+//! Values are given by config path and checked against a program only when it
+//! is configured, so the same values can be used with several programs. The
+//! defaults of configs left unset are evaluated as constant expressions, which
+//! may refer to the values of other configs.
+//!
+//! Values can also be loaded from RSON, JSON and TOML documents mapping config
+//! paths to values, as in `{"Modes.State": "Open", "Skills": "MinorGlitches"}`.
+//! Nested tables extend the path, so TOML's `[Modes]` table with `State =
+//! "Open"` is the same as `"Modes.State" = "Open"`. Numbers may be given as
+//! numbers or as strings such as `"2/3"` or `"-1.5"`; other strings name enum
+//! variants, or configsets when the path is a region.
+
+use crate::ast::{self, BinOp, Builtin};
+use crate::check;
+use crate::deps;
+use crate::{
+  CompileError, ConfigError, ConfigErrors, ConfigTy, Diagnostics, EntityId, Expr, FromAST, Id,
+  Ident, Path, Program, ScopeId, Ty,
+};
+use anyhow::{anyhow, Error};
+use indexmap::{IndexMap, IndexSet};
+use serde::de::{self, Deserialize, Deserializer, MapAccess, Unexpected, Visitor};
+use serde_json::json;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+
+// An entry in a document of config values: a value for the path so far, or a
+// table of further path segments.
+enum Entry {
+  Value(ConfigValue),
+  Table(Vec<(String, Entry)>),
+}
+
+impl Entry {
+  fn flatten<E: de::Error>(self, path: &str, vals: &mut ConfigValues) -> Result<(), E> {
+    match self {
+      Entry::Value(v) => {
+        if vals.vals.insert(path.into(), v).is_some() {
+          return Err(E::custom(format!("{} is given more than once", path)));
+        }
+      }
+      Entry::Table(entries) => {
+        for (seg, e) in entries {
+          if seg.is_empty() || seg.split('.').any(str::is_empty) {
+            return Err(E::invalid_value(Unexpected::Str(&seg), &"a config path"));
+          }
+          let path = if path.is_empty() {
+            seg
+          } else {
+            format!("{}.{}", path, seg)
+          };
+          e.flatten(&path, vals)?;
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+// Parse a number written as an integer, a decimal or a ratio, like the
+// numeric literals of Rado but also allowing `n/d`.
+fn parse_num(s: &str) -> Option<ast::Num> {
+  let (neg, digits) = match s.strip_prefix('-') {
+    Some(rest) => (true, rest),
+    None => (false, s),
+  };
+  let n = if digits.contains('/') {
+    digits.parse::<ast::Num>().ok()?
+  } else {
+    let (w, f) = match digits.find('.') {
+      Some(i) => (&digits[..i], &digits[i + 1..]),
+      None => (digits, ""),
+    };
+    let all_digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    if !all_digits(w) || !(f.is_empty() || all_digits(f)) || digits.ends_with('.') {
+      return None;
+    }
+    format!("{}{}/1{}", w, f, "0".repeat(f.len()))
+      .parse()
+      .ok()?
+  };
+  Some(if neg { -n } else { n })
+}
+
+struct EntryVisitor;
+
+impl<'de> Visitor<'de> for EntryVisitor {
+  type Value = Entry;
+
+  fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("a bool, a number, a variant name or a table of config values")
+  }
+
+  fn visit_bool<E: de::Error>(self, b: bool) -> Result<Entry, E> {
+    Ok(Entry::Value(ConfigValue::Bool(b)))
+  }
+
+  fn visit_i64<E: de::Error>(self, n: i64) -> Result<Entry, E> {
+    Ok(Entry::Value(n.into()))
+  }
+
+  fn visit_u64<E: de::Error>(self, n: u64) -> Result<Entry, E> {
+    Ok(Entry::Value(ast::Num::from_integer(n.into()).into()))
+  }
+
+  fn visit_f64<E: de::Error>(self, n: f64) -> Result<Entry, E> {
+    // Floats print as the shortest decimal which reads back as the same float,
+    // which is the number that was written.
+    match Some(n)
+      .filter(|n| n.is_finite())
+      .and_then(|n| parse_num(&n.to_string()))
+    {
+      Some(n) => Ok(Entry::Value(n.into())),
+      None => Err(E::invalid_value(Unexpected::Float(n), &self)),
+    }
+  }
+
+  fn visit_str<E: de::Error>(self, s: &str) -> Result<Entry, E> {
+    if s.starts_with(|c: char| c == '-' || c.is_ascii_digit()) {
+      match parse_num(s) {
+        Some(n) => Ok(Entry::Value(n.into())),
+        None => Err(E::invalid_value(
+          Unexpected::Str(s),
+          &"a number such as 3, -1.5 or 2/3",
+        )),
+      }
+    } else {
+      Ok(Entry::Value(s.into()))
+    }
+  }
+
+  fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Entry, A::Error> {
+    let mut entries = Vec::new();
+    while let Some(entry) = map.next_entry()? {
+      entries.push(entry);
+    }
+    Ok(Entry::Table(entries))
+  }
+}
+
+impl<'de> Deserialize<'de> for Entry {
+  fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Entry, D::Error> {
+    d.deserialize_any(EntryVisitor)
+  }
+}
+
+impl<'de> Deserialize<'de> for ConfigValues {
+  fn deserialize<D: Deserializer<'de>>(d: D) -> Result<ConfigValues, D::Error> {
+    let mut vals = ConfigValues::new();
+    match Entry::deserialize(d)? {
+      Entry::Table(entries) => Entry::Table(entries).flatten("", &mut vals)?,
+      Entry::Value(_) => return Err(de::Error::custom("expected a table of config values")),
+    }
+    Ok(vals)
+  }
+}
+
+/// The value of a config.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Value {
+  Bool(bool),
+  Num(ast::Num),
+  /// A variant of the config's enum.
+  Variant(Id),
+}
+
+/// A value given for a config, before it is checked against a program.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum ConfigValue {
+  Bool(bool),
+  Num(ast::Num),
+  /// A variant of the config's enum, by its name alone, as in `Open` for
+  /// `Modes.State`.
+  Variant(String),
+}
+
+impl From<bool> for ConfigValue {
+  fn from(b: bool) -> ConfigValue {
+    ConfigValue::Bool(b)
+  }
+}
+
+impl From<ast::Num> for ConfigValue {
+  fn from(n: ast::Num) -> ConfigValue {
+    ConfigValue::Num(n)
+  }
+}
+
+impl From<i64> for ConfigValue {
+  fn from(n: i64) -> ConfigValue {
+    ConfigValue::Num(ast::Num::from_integer(n.into()))
+  }
+}
+
+impl From<&str> for ConfigValue {
+  fn from(variant: &str) -> ConfigValue {
+    ConfigValue::Variant(variant.into())
+  }
+}
+
+/// Values chosen for the configs of a program, by path, and configsets to take
+/// further values from.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct ConfigValues {
+  vals: IndexMap<String, ConfigValue>,
+  configsets: Vec<String>,
+}
+
+impl ConfigValues {
+  pub fn new() -> ConfigValues {
+    ConfigValues::default()
+  }
+
+  /// Load values from an RSON document. See the [module docs](self) for the
+  /// format.
+  pub fn from_rson(s: &str) -> Result<ConfigValues, Error> {
+    rson_rs::de::from_str(s).map_err(|e| anyhow!("error loading config values from RSON: {}", e))
+  }
+
+  /// Load values from a JSON document. See the [module docs](self) for the
+  /// format.
+  pub fn from_json(s: &str) -> Result<ConfigValues, Error> {
+    serde_json::from_str(s).map_err(|e| anyhow!("error loading config values from JSON: {}", e))
+  }
+
+  /// Load values from a TOML document. See the [module docs](self) for the
+  /// format.
+  pub fn from_toml(s: &str) -> Result<ConfigValues, Error> {
+    toml::from_str(s).map_err(|e| anyhow!("error loading config values from TOML: {}", e))
+  }
+
+  /// Set the value of the config with a path such as `Modes.State`, replacing
+  /// any value given before. A variant name given for a region, as in
+  /// `Skills` and `MinorGlitches`, applies the configset of that name in the
+  /// region instead.
+  pub fn set(&mut self, path: impl Into<String>, val: impl Into<ConfigValue>) -> &mut ConfigValues {
+    self.vals.insert(path.into(), val.into());
+    self
+  }
+
+  /// The value given for a config path, if any.
+  pub fn get(&self, path: &str) -> Option<&ConfigValue> {
+    self.vals.get(path)
+  }
+
+  /// All the values given, in the order they were first set.
+  pub fn iter(&self) -> impl Iterator<Item = (&str, &ConfigValue)> {
+    self.vals.iter().map(|(p, v)| (&p[..], v))
+  }
+
+  /// Take the values of the configset with a path such as
+  /// `Skills.MinorGlitches`. Values set directly take precedence over those
+  /// from configsets, but two configsets must not disagree.
+  pub fn apply(&mut self, configset: impl Into<String>) -> &mut ConfigValues {
+    self.configsets.push(configset.into());
+    self
+  }
+
+  /// The paths of the configsets applied, in the order they were applied.
+  pub fn configsets(&self) -> impl Iterator<Item = &str> {
+    self.configsets.iter().map(|c| &c[..])
+  }
+}
+
+/// A program together with a value for each of its configs.
+///
+/// Configuring a program does not change it, so a single program can be
+/// configured any number of times, and in different ways at once. When the
+/// configuration takes the branches of conditional blocks, they are applied to
+/// a copy of the program instead.
+pub struct ConfiguredProgram<'a> {
+  program: Cow<'a, Program>,
+  values: Vec<Value>,
+}
+
+impl<'a> ConfiguredProgram<'a> {
+  /// The program which was configured, with the declarations from the taken
+  /// branches of its conditional blocks.
+  pub fn program(&self) -> &Program {
+    &self.program
+  }
+
+  /// The value of a config.
+  pub fn value(&self, config: Id) -> &Value {
+    &self.values[config.0]
+  }
+
+  /// The values of all the configs, in the order they were declared.
+  pub fn values(&self) -> impl Iterator<Item = (Id, &Value)> {
+    self.values.iter().enumerate().map(|(id, v)| (Id(id), v))
+  }
+
+  /// Make this independent of the program it was configured from, copying
+  /// that program if no branches were taken, so that it can outlive it.
+  pub fn into_owned(self) -> ConfiguredProgram<'static> {
+    ConfiguredProgram {
+      program: Cow::Owned(self.program.into_owned()),
+      values: self.values,
+    }
+  }
+}
+
+impl Program {
+  /// Configure the program with the given values, after those of the given
+  /// configsets. Configs without a value take their default. The branch of
+  /// each conditional block chosen by its condition is then applied to the
+  /// enclosing scope, as are those of the conditional blocks it contains.
+  /// Every problem with the values is reported, including names which are not
+  /// configs and configs left without any value.
+  pub fn configure(&self, vals: &ConfigValues) -> Result<ConfiguredProgram<'_>, ConfigErrors> {
+    let mut c = Configurer {
+      program: self,
+      slots: self.configs.ids().map(|_| Slot::Unset).collect(),
+      errors: Vec::new(),
+    };
+    // Variant names given for regions name configsets in them.
+    let mut configsets = vals.configsets().map(String::from).collect::<Vec<_>>();
+    let mut direct = Vec::new();
+    for (path, val) in vals.iter() {
+      match (val, c.lookup(path)) {
+        (ConfigValue::Variant(name), Some(EntityId::Region(_))) => {
+          configsets.push(format!("{}.{}", path, name))
+        }
+        _ => direct.push((path, val)),
+      }
+    }
+    let mut sources = vec![None; c.slots.len()];
+    for path in &configsets {
+      let id = match c.lookup(path) {
+        Some(EntityId::Configset(id)) => id,
+        _ => {
+          let path = path.clone();
+          c.errors.push(ConfigError::UnknownConfigset { path });
+          continue;
+        }
+      };
+      for (config, v) in &self.configsets[id.0].expansion {
+        match (&c.slots[config.0], sources[config.0]) {
+          (Slot::Done(old), Some(first)) if old != v => {
+            c.errors.push(ConfigError::ConfigsetConflict {
+              config: self.path_of(EntityId::Config(*config)),
+              first: String::from(first),
+              second: path.clone(),
+            })
+          }
+          _ => {
+            c.slots[config.0] = Slot::Done(v.clone());
+            sources[config.0] = Some(&path[..]);
+          }
+        }
+      }
+    }
+    for (path, val) in direct {
+      match c.bind(path, val) {
+        Ok((id, v)) => c.slots[id.0] = Slot::Done(v),
+        Err(e) => c.errors.push(e),
+      }
+    }
+    let values = self
+      .configs
+      .ids()
+      .map(|id| c.config(Id(id)).ok())
+      .collect::<Vec<_>>();
+    for (id, v) in values.iter().enumerate() {
+      if let Some(Err(e)) = v.as_ref().map(|v| self.check_allowed(Id(id), v)) {
+        c.errors.push(e);
+      }
+    }
+    if !c.errors.is_empty() {
+      return Err(ConfigErrors { errors: c.errors });
+    }
+    let values = values.into_iter().map(Option::unwrap).collect::<Vec<_>>();
+    let program = if self.conds.is_empty() {
+      Cow::Borrowed(self)
+    } else {
+      Cow::Owned(self.apply_conds(&values)?)
+    };
+    Ok(ConfiguredProgram { program, values })
+  }
+
+  // Apply the taken branches of the conditional blocks to a copy of the
+  // program. Blocks declared by those branches are applied in turn, once they
+  // have been checked, so overrides in more deeply nested blocks are applied
+  // after, and supersede, those around them.
+  fn apply_conds(&self, values: &[Value]) -> Result<Program, ConfigErrors> {
+    let mut b = FromAST::configuring(self.clone());
+    let mut errors = Vec::new();
+    let mut next = 0;
+    // The taken blocks, each as its conditional block and whether it is the
+    // main block rather than the else block.
+    let mut active = Vec::new();
+    while next < b.conds.len() && errors.is_empty() {
+      let end = b.conds.len();
+      // Every taken branch of the round is declared before any is built, so
+      // that they can refer to each other whichever comes first.
+      let start = active.len();
+      for id in next..end {
+        // Blocks inside deleted regions have no effect.
+        if b.is_deleted(EntityId::Cond(Id(id))) {
+          continue;
+        }
+        let mut c = Configurer {
+          program: &b.program,
+          slots: values.iter().cloned().map(Slot::Done).collect(),
+          errors: Vec::new(),
+        };
+        let cond = &b.conds[id];
+        let context = format!("the condition of {}", b.path_of(EntityId::Cond(Id(id))));
+        match c.eval(&cond.cond, &[], &context, 0) {
+          Ok(v) => {
+            // An absent else block is never active.
+            let then = bool(v);
+            if then || !cond.otherwise.is_empty() {
+              active.push((Id(id), then));
+            }
+          }
+          Err(e) => errors.extend(e),
+        }
+      }
+      for &(id, then) in &active[start..] {
+        let (scope, stmts) = enter(&mut b, id, then);
+        b.populate_scope(scope, &stmts);
+      }
+      for &(id, then) in &active[start..] {
+        let (scope, stmts) = enter(&mut b, id, then);
+        b.build_scope(scope, stmts);
+      }
+      check::check(&mut b.program, &mut b.diags);
+      b.check_signatures();
+      if b.diags.errors.is_empty() {
+        b.check_conds(end);
+      }
+      let compile_errors = b.diags.errors.drain(..);
+      errors.extend(compile_errors.map(|e| ConfigError::Conditional { error: Box::new(e) }));
+      next = end;
+    }
+    // Deleted entities may be restored by more deeply nested blocks, so only
+    // the references left at the end are errors.
+    if errors.is_empty() {
+      b.check_deleted();
+      let loops = deps::check(&b.program, &b.declarers);
+      b.diags.errors.extend(loops);
+      let compile_errors = b.diags.errors.drain(..);
+      errors.extend(compile_errors.map(|e| ConfigError::Conditional { error: Box::new(e) }));
+      errors.extend(b.check_cond_tags(&active));
+    }
+    if errors.is_empty() {
+      b.program.fill_stable_ids();
+      Ok(b.program)
+    } else {
+      Err(ConfigErrors { errors })
+    }
+  }
+
+  // Check the active blocks against the rules for conditional tags: no two of
+  // their tags may be mutually exclusive, and every block whose tags are all
+  // on an active block must be active too. Blocks with no tags of their own
+  // are exempt from the latter, even if they inherit some.
+  fn check_cond_tags(&self, active: &[(Id, bool)]) -> Vec<ConfigError> {
+    let mut errors = Vec::new();
+    let mut union = IndexSet::new();
+    for &(id, then) in active {
+      union.extend(self.conds[id.0].block_tags(then));
+    }
+    for (i, &a) in union.iter().enumerate() {
+      for &b in union.iter().skip(i + 1) {
+        if self.mutually_exclusive(a, b) {
+          errors.push(ConfigError::ExclusiveTags {
+            first: self.cond_tag_str(a),
+            second: self.cond_tag_str(b),
+          });
+        }
+      }
+    }
+
+    // An else block which is absent is never active, and has no effect.
+    let blocks = self
+      .conds()
+      .flat_map(|(id, c)| {
+        let otherwise = !c.otherwise.is_empty();
+        std::iter::once((id, true)).chain(otherwise.then_some((id, false)))
+      })
+      .filter(|(id, _)| !self.conds[id.0].tags.is_empty());
+    for (id, then) in blocks {
+      if active.contains(&(id, then)) {
+        continue;
+      }
+      let tags = self.conds[id.0].block_tags(then);
+      let superset = active.iter().find(|&&(a, a_then)| {
+        let a_tags = self.conds[a.0].block_tags(a_then);
+        tags.iter().all(|t| a_tags.contains(t))
+      });
+      if let Some(&(a, a_then)) = superset {
+        errors.push(ConfigError::InactiveBlock {
+          block: self.block_str(id, then),
+          active: self.block_str(a, a_then),
+        });
+      }
+    }
+    errors
+  }
+
+  fn block_str(&self, id: Id, then: bool) -> String {
+    let path = self.path_of(EntityId::Cond(id));
+    if then {
+      format!("the block of {}", path)
+    } else {
+      format!("the else block of {}", path)
+    }
+  }
+}
+
+// Prepare to compile one of the blocks of a conditional block, returning the
+// scope it applies to and its statements.
+fn enter(b: &mut FromAST, id: Id, then: bool) -> (ScopeId, Vec<ast::Stmt>) {
+  let c = &b.conds[id.0];
+  let stmts = if then { &c.then } else { &c.otherwise };
+  let (scope, stmts) = (c.parent, stmts.clone());
+  let (depth, tags) = (c.depth + 1, c.block_tags(then));
+  b.depth = depth;
+  b.cond_tags = tags;
+  b.block = Some(id);
+  (scope, stmts)
+}
+
+/// Whether an expression is constant, so that it can be evaluated once the
+/// program is configured. It may refer only to configs, enum variants and
+/// functions whose bodies are constant. Operands which would not be evaluated
+/// must be constant too, since overrides could change which are.
+pub(crate) fn is_constant(program: &Program, e: &Expr) -> bool {
+  Constness {
+    program,
+    fns: HashMap::new(),
+  }
+  .expr(e)
+}
+
+struct Constness<'a> {
+  program: &'a Program,
+  // Whether each function reached so far is constant. Functions are taken to
+  // be constant while their bodies are being checked, so that recursion does
+  // not make them otherwise.
+  fns: HashMap<Id, bool>,
+}
+
+impl<'a> Constness<'a> {
+  fn expr(&mut self, e: &Expr) -> bool {
+    match e {
+      Expr::Num(_) | Expr::Bool(_) | Expr::Param(_) => true,
+      Expr::Entity(EntityId::Config(_)) | Expr::Entity(EntityId::Variant(_)) => true,
+      Expr::Entity(EntityId::Function(f)) => self.function(*f),
+      Expr::Entity(_) | Expr::TagVals(..) | Expr::Builtin(Builtin::Count, _) => false,
+      Expr::List(es) | Expr::Builtin(_, es) | Expr::And(es) | Expr::Or(es) => {
+        es.iter().all(|e| self.expr(e))
+      }
+      Expr::Call(f, args) => self.expr(f) && args.iter().all(|e| self.expr(e)),
+      Expr::Not(e) => self.expr(e),
+      Expr::Bin(l, _, r) => self.expr(l) && self.expr(r),
+      Expr::If(c, t, e) => self.expr(c) && self.expr(t) && self.expr(e),
+      Expr::Match(e, arms) => self.expr(e) && arms.iter().all(|a| self.expr(&a.expr)),
+    }
+  }
+
+  fn function(&mut self, f: Id) -> bool {
+    if let Some(constant) = self.fns.get(&f) {
+      return *constant;
+    }
+    self.fns.insert(f, true);
+    let p = self.program;
+    let constant = p.functions[f.0].body.as_ref().is_none_or(|b| self.expr(b));
+    self.fns.insert(f, constant);
+    constant
+  }
+}
+
+/// Expand every configset into the config values it sets, reporting cycles
+/// between configsets and configs given conflicting values.
+pub(crate) fn expand_configsets(program: &mut Program, diags: &mut Diagnostics) {
+  let mut x = Expander {
+    program,
+    states: program
+      .configsets
+      .ids()
+      .map(|_| Expansion::Unvisited)
+      .collect(),
+    errors: Vec::new(),
+  };
+  for id in program.configsets.ids() {
+    x.expand(Id(id));
+  }
+  let Expander { states, errors, .. } = x;
+  diags.errors.extend(errors);
+  for (id, state) in states.into_iter().enumerate() {
+    if let Expansion::Done(vals) = state {
+      program.configsets[id].expansion = vals.into_iter().collect();
+    }
+  }
+}
+
+enum Expansion {
+  Unvisited,
+  // The configset is being expanded, so including it again is a cycle.
+  Expanding,
+  Done(IndexMap<Id, Value>),
+  // An error has already been reported.
+  Failed,
+}
+
+struct Expander<'a> {
+  program: &'a Program,
+  states: Vec<Expansion>,
+  errors: Vec<CompileError>,
+}
+
+impl<'a> Expander<'a> {
+  fn expand(&mut self, id: Id) -> Option<IndexMap<Id, Value>> {
+    let p = self.program;
+    let c = &p.configsets[id.0];
+    let span = c.name.span;
+    let path = p.path_of(EntityId::Configset(id));
+    match &self.states[id.0] {
+      Expansion::Unvisited => {}
+      Expansion::Expanding => {
+        self
+          .errors
+          .push(CompileError::ConfigsetCycle { name: path, span });
+        return None;
+      }
+      Expansion::Done(vals) => return Some(vals.clone()),
+      Expansion::Failed => return None,
+    }
+    self.states[id.0] = Expansion::Expanding;
+
+    let mut vals = IndexMap::new();
+    let mut ok = true;
+    let mut set = |errors: &mut Vec<CompileError>, config: Id, v: Value| match vals.get(&config) {
+      Some(old) if *old != v => {
+        errors.push(CompileError::ConfigsetConflict {
+          configset: path.clone(),
+          config: p.path_of(EntityId::Config(config)),
+          span,
+        });
+        false
+      }
+      _ => {
+        vals.insert(config, v);
+        true
+      }
+    };
+    for (config, e) in &c.vals {
+      let context = format!(
+        "the value of {} in {}",
+        p.path_of(EntityId::Config(*config)),
+        path
+      );
+      match Configurer::constant(p).eval(e, &[], &context, 0) {
+        Ok(v) => match p.check_allowed(*config, &v) {
+          Ok(()) => ok &= set(&mut self.errors, *config, v),
+          Err(error) => {
+            self
+              .errors
+              .push(CompileError::ConfigsetValue { error, span });
+            ok = false;
+          }
+        },
+        Err(error) => {
+          let error = error.map(|error| CompileError::ConfigsetValue { error, span });
+          self.errors.extend(error);
+          ok = false;
+        }
+      }
+    }
+    for included in &c.configsets {
+      match self.expand(*included) {
+        Some(included) => {
+          for (config, v) in included {
+            ok &= set(&mut self.errors, config, v);
+          }
+        }
+        None => ok = false,
+      }
+    }
+
+    self.states[id.0] = if ok {
+      Expansion::Done(vals.clone())
+    } else {
+      Expansion::Failed
+    };
+    Some(vals).filter(|_| ok)
+  }
+}
+
+impl Program {
+  /// A JSON Schema document describing the documents of config values for
+  /// this program, so that settings can be presented without knowing the
+  /// program. It describes flat documents, in which every key is a full path,
+  /// and which give every config without a default a value.
+  /// [ConfigValues::from_json] also accepts nested tables, which extend the
+  /// path, so such documents must be flattened before they are validated.
+  ///
+  /// Each config is a property keyed by its path, with its type, its default
+  /// if that is a constant, and for enums the variants which may be chosen.
+  /// Variants the config disallows are left out of those, and listed under
+  /// `x-disallowed` instead.
+  /// Each region with configsets is a property choosing one of them. Names are
+  /// titled by their display names, so `UncleAssured` is titled "Uncle
+  /// Assured". The values of every configset are listed under `x-presets`,
+  /// keyed by the configset's path.
+  pub fn config_schema(&self) -> serde_json::Value {
+    let mut props = serde_json::Map::new();
+    let mut required = Vec::new();
+    for (id, c) in self.configs() {
+      let path = self.path_of(EntityId::Config(id));
+      let mut schema = match &config_ty(&c.ty) {
+        Ty::Bool => json!({ "type": "boolean" }),
+        Ty::Int | Ty::Num => json!({
+          "anyOf": [
+            { "type": "number" },
+            { "type": "string", "pattern": "^-?[0-9]+(\\.[0-9]+|/[0-9]+)?$" },
+          ]
+        }),
+        Ty::Enum(e) => {
+          let (disallowed, allowed): (Vec<_>, Vec<_>) = self.enums[e.0]
+            .variants
+            .iter()
+            .copied()
+            .partition(|v| c.disallowed.contains(v));
+          let choice = |v: &Id| self.choice(EntityId::Variant(*v), self.variants[v.0].name.ident);
+          let mut schema = json!({ "oneOf": allowed.iter().map(choice).collect::<Vec<_>>() });
+          if !disallowed.is_empty() {
+            schema["x-disallowed"] = disallowed.iter().map(choice).collect();
+          }
+          schema
+        }
+        // Configs of other types cannot be given values.
+        _ => json!({}),
+      };
+      schema["title"] = self.display_name(EntityId::Config(id)).into();
+      match &c.default {
+        Some(e) => {
+          let context = format!("the default of {}", path);
+          if let Ok(v) = Configurer::constant(self).eval(e, &[], &context, 0) {
+            schema["default"] = self.value_json(&v);
+          }
+        }
+        None => required.push(path.clone()),
+      }
+      props.insert(path, schema);
+    }
+
+    let mut presets = serde_json::Map::new();
+    let mut choices = IndexMap::<Id, Vec<_>>::new();
+    for (id, c) in self.configsets() {
+      let vals = c
+        .expansion
+        .iter()
+        .map(|(config, v)| (self.path_of(EntityId::Config(*config)), self.value_json(v)))
+        .collect::<serde_json::Map<_, _>>();
+      presets.insert(self.path_of(EntityId::Configset(id)), vals.into());
+      if let ScopeId::Region(r) = c.parent {
+        choices
+          .entry(r)
+          .or_default()
+          .push(self.choice(EntityId::Configset(id), c.name.ident));
+      }
+    }
+    for (r, choices) in choices {
+      let region = EntityId::Region(r);
+      props.insert(
+        self.path_of(region),
+        json!({ "title": self.display_name(region), "oneOf": choices }),
+      );
+    }
+
+    json!({
+      "$schema": "http://json-schema.org/draft-07/schema#",
+      "type": "object",
+      "properties": props,
+      "required": required,
+      "additionalProperties": false,
+      "x-presets": presets,
+    })
+  }
+
+  // Check that a value is not one the config disallows.
+  fn check_allowed(&self, config: Id, v: &Value) -> Result<(), ConfigError> {
+    match v {
+      Value::Variant(var) if self.configs[config.0].disallowed.contains(var) => {
+        Err(ConfigError::Disallowed {
+          path: self.path_of(EntityId::Config(config)),
+          variant: self.resolve(self.variants[var.0].name.ident).to_string(),
+        })
+      }
+      _ => Ok(()),
+    }
+  }
+
+  // A choice of a named entity, as for a variant or configset.
+  fn choice(&self, e: EntityId, name: Ident) -> serde_json::Value {
+    json!({ "const": self.resolve(name), "title": self.display_name(e) })
+  }
+
+  // A value as it is written in a document of config values.
+  fn value_json(&self, v: &Value) -> serde_json::Value {
+    match v {
+      Value::Bool(b) => (*b).into(),
+      Value::Num(n) => match n.to_integer().to_string().parse::<i64>() {
+        Ok(i) if n.is_integer() => i.into(),
+        _ => n.to_string().into(),
+      },
+      Value::Variant(v) => self.resolve(self.variants[v.0].name.ident).into(),
+    }
+  }
+}
+
+// How far evaluation of function calls can nest, so that recursive functions
+// fail rather than overflow the stack.
+const MAX_DEPTH: usize = 64;
+
+enum Slot {
+  Unset,
+  // The default is being evaluated, so needing the value again is a cycle.
+  Evaluating,
+  Done(Value),
+  // An error has already been reported.
+  Failed,
+}
+
+// The result of evaluation. An error of None has already been reported, by the
+// evaluation of another config's default.
+type Eval = Result<Value, Option<ConfigError>>;
+
+struct Configurer<'a> {
+  program: &'a Program,
+  slots: Vec<Slot>,
+  errors: Vec<ConfigError>,
+}
+
+impl<'a> Configurer<'a> {
+  // An evaluator for expressions which must not depend on configs.
+  fn constant(program: &'a Program) -> Configurer<'a> {
+    Configurer {
+      program,
+      slots: Vec::new(),
+      errors: Vec::new(),
+    }
+  }
+
+  // Find the entity with a path given as a string.
+  fn lookup(&self, path: &str) -> Option<EntityId> {
+    let p = self.program;
+    let segs = path
+      .split('.')
+      .map(|s| p.idents.get(s).map(Ident))
+      .collect::<Option<Vec<_>>>()?;
+    p.lookup_entity(p, &Path(segs)).ok()
+  }
+
+  // Resolve a path to a config and check a value given for it.
+  fn bind(&self, path: &str, val: &ConfigValue) -> Result<(Id, Value), ConfigError> {
+    let p = self.program;
+    let id = match self.lookup(path) {
+      Some(EntityId::Config(c)) => c,
+      _ => return Err(ConfigError::UnknownConfig { path: path.into() }),
+    };
+    let ty = config_ty(&p.configs[id.0].ty);
+    let v = match (&ty, val) {
+      (Ty::Bool, ConfigValue::Bool(b)) => Value::Bool(*b),
+      (Ty::Num, ConfigValue::Num(n)) => Value::Num(n.clone()),
+      (Ty::Int, ConfigValue::Num(n)) if n.is_integer() => Value::Num(n.clone()),
+      (Ty::Enum(e), ConfigValue::Variant(name)) => {
+        let variant = p.enums[e.0]
+          .variants
+          .iter()
+          .find(|v| p.resolve(p.variants[v.0].name.ident) == name);
+        match variant {
+          Some(v) => Value::Variant(*v),
+          None => {
+            return Err(ConfigError::UnknownVariant {
+              path: path.into(),
+              variant: name.clone(),
+            })
+          }
+        }
+      }
+      _ => {
+        return Err(ConfigError::TypeMismatch {
+          path: path.into(),
+          expected: ty_str(p, &ty),
+          found: match val {
+            ConfigValue::Bool(_) => "bool".into(),
+            ConfigValue::Num(_) => "num".into(),
+            ConfigValue::Variant(v) => format!("variant {}", v),
+          },
+        })
+      }
+    };
+    Ok((id, v))
+  }
+
+  // The value of a config, evaluating its default if it was not given one.
+  fn config(&mut self, id: Id) -> Eval {
+    match &self.slots[id.0] {
+      Slot::Done(v) => return Ok(v.clone()),
+      Slot::Failed => return Err(None),
+      Slot::Evaluating => {
+        return Err(Some(ConfigError::CyclicDefault {
+          path: self.program.path_of(EntityId::Config(id)),
+        }))
+      }
+      Slot::Unset => {}
+    }
+    let p = self.program;
+    let path = p.path_of(EntityId::Config(id));
+    let result = match &p.configs[id.0].default {
+      Some(default) => {
+        self.slots[id.0] = Slot::Evaluating;
+        let context = format!("the default of {}", path);
+        self.eval(default, &[], &context, 0)
+      }
+      None => Err(Some(ConfigError::Missing { path })),
+    };
+    match result {
+      Ok(v) => {
+        self.slots[id.0] = Slot::Done(v.clone());
+        Ok(v)
+      }
+      Err(e) => {
+        self.slots[id.0] = Slot::Failed;
+        self.errors.extend(e);
+        Err(None)
+      }
+    }
+  }
+
+  fn eval(&mut self, e: &Expr, args: &[Value], context: &str, depth: usize) -> Eval {
+    let p = self.program;
+    let not_constant = || {
+      Err(Some(ConfigError::NotConstant {
+        context: context.into(),
+      }))
+    };
+    Ok(match e {
+      Expr::Num(n) => Value::Num(n.clone()),
+      Expr::Bool(b) => Value::Bool(*b),
+      Expr::Param(i) => args[*i].clone(),
+      Expr::Entity(EntityId::Variant(v)) => Value::Variant(*v),
+      // Without any slots, the values of configs are not known at all.
+      Expr::Entity(EntityId::Config(c)) if !self.slots.is_empty() => return self.config(*c),
+      Expr::Entity(EntityId::Function(f)) if p.functions[f.0].params.is_empty() => {
+        return self.call(*f, &[], context, depth)
+      }
+      Expr::Call(f, call_args) => {
+        let f = match &**f {
+          Expr::Entity(EntityId::Function(f)) => *f,
+          _ => return not_constant(),
+        };
+        let call_args = call_args
+          .iter()
+          .map(|a| self.eval(a, args, context, depth))
+          .collect::<Result<Vec<_>, _>>()?;
+        return self.call(f, &call_args, context, depth);
+      }
+      Expr::Builtin(b, es) => {
+        let mut ns = Vec::new();
+        for e in es {
+          ns.push(num(self.eval(e, args, context, depth)?));
+        }
+        match b {
+          Builtin::Count => return not_constant(),
+          Builtin::Sum => Value::Num(ns.into_iter().fold(zero(), |a, b| a + b)),
+          Builtin::Max => match ns.into_iter().max() {
+            Some(n) => Value::Num(n),
+            None => return not_constant(),
+          },
+          Builtin::Min => match ns.into_iter().min() {
+            Some(n) => Value::Num(n),
+            None => return not_constant(),
+          },
+        }
+      }
+      Expr::Not(e) => Value::Bool(!bool(self.eval(e, args, context, depth)?)),
+      Expr::Bin(l, op, r) => {
+        let l = self.eval(l, args, context, depth)?;
+        let r = self.eval(r, args, context, depth)?;
+        match op {
+          BinOp::Eq => Value::Bool(l == r),
+          BinOp::NEq => Value::Bool(l != r),
+          _ => {
+            let (l, r) = (num(l), num(r));
+            match op {
+              BinOp::Add => Value::Num(l + r),
+              BinOp::Sub => Value::Num(l - r),
+              BinOp::Mul => Value::Num(l * r),
+              BinOp::Div | BinOp::Mod if r == zero() => {
+                return Err(Some(ConfigError::DivideByZero {
+                  context: context.into(),
+                }))
+              }
+              BinOp::Div => Value::Num(l / r),
+              // The result of mod has the sign of the divisor.
+              BinOp::Mod => {
+                let m = l % r.clone();
+                Value::Num(if m != zero() && (m < zero()) != (r < zero()) {
+                  m + r
+                } else {
+                  m
+                })
+              }
+              BinOp::LT => Value::Bool(l < r),
+              BinOp::LE => Value::Bool(l <= r),
+              BinOp::GT => Value::Bool(l > r),
+              BinOp::GE => Value::Bool(l >= r),
+              BinOp::Eq | BinOp::NEq => unreachable!(),
+            }
+          }
+        }
+      }
+      Expr::And(es) => {
+        for e in es {
+          if !bool(self.eval(e, args, context, depth)?) {
+            return Ok(Value::Bool(false));
+          }
+        }
+        Value::Bool(true)
+      }
+      Expr::Or(es) => {
+        for e in es {
+          if bool(self.eval(e, args, context, depth)?) {
+            return Ok(Value::Bool(true));
+          }
+        }
+        Value::Bool(false)
+      }
+      Expr::If(c, t, f) => {
+        let branch = if bool(self.eval(c, args, context, depth)?) {
+          t
+        } else {
+          f
+        };
+        return self.eval(branch, args, context, depth);
+      }
+      Expr::Match(e, arms) => {
+        let v = match self.eval(e, args, context, depth)? {
+          Value::Variant(v) => v,
+          _ => unreachable!("match on a value which is not an enum"),
+        };
+        match arms.iter().find(|arm| arm.variant == v) {
+          Some(arm) => return self.eval(&arm.expr, args, context, depth),
+          None => {
+            return Err(Some(ConfigError::NoMatchingArm {
+              context: context.into(),
+              variant: p.path_of(EntityId::Variant(v)),
+            }))
+          }
+        }
+      }
+      // Items, tags and randoms are not known until the game is played or
+      // randomized, and lists and functions cannot be config values.
+      Expr::List(_) | Expr::Entity(_) | Expr::TagVals(..) => return not_constant(),
+    })
+  }
+
+  fn call(&mut self, f: Id, args: &[Value], context: &str, depth: usize) -> Eval {
+    if depth >= MAX_DEPTH {
+      return Err(Some(ConfigError::RecursionLimit {
+        context: context.into(),
+      }));
+    }
+    // Bodies are missing only from programs which failed to compile.
+    let body = self.program.functions[f.0].body.as_ref().unwrap();
+    self.eval(body, args, context, depth + 1)
+  }
+}
+
+fn zero() -> ast::Num {
+  ast::Num::from_integer(0.into())
+}
+
+// Type checking guarantees that these conversions succeed.
+fn num(v: Value) -> ast::Num {
+  match v {
+    Value::Num(n) => n,
+    v => unreachable!("expected a number, found {:?}", v),
+  }
+}
+
+fn bool(v: Value) -> bool {
+  match v {
+    Value::Bool(b) => b,
+    v => unreachable!("expected a bool, found {:?}", v),
+  }
+}
+
+fn config_ty(ty: &ConfigTy) -> Ty {
+  match ty {
+    ConfigTy::Declared(ty) => ty.clone(),
+    ConfigTy::Enum(e) => Ty::Enum(*e),
+  }
+}
+
+// Format a type as it would be written, for messages.
+fn ty_str(p: &Program, ty: &Ty) -> String {
+  match ty {
+    Ty::Int => "int".into(),
+    Ty::Num => "num".into(),
+    Ty::Bool => "bool".into(),
+    Ty::Item => "item".into(),
+    Ty::Fn(params, ret) => format!(
+      "fn ({}) -> {}",
+      params
+        .iter()
+        .map(|t| ty_str(p, t))
+        .collect::<Vec<_>>()
+        .join(", "),
+      ty_str(p, ret)
+    ),
+    Ty::List(t) => format!("[{}]", ty_str(p, t)),
+    Ty::Enum(e) => p.path_str(&[p.enums[e.0].name.ident]),
+  }
+}
@@ -0,0 +1,226 @@
+//! Detection of dependency loops between entities.
+//!
+//! An entity depends on the entities its governing expression refers to, which
+//! is the condition of a conditional block or the body of a function. Configs
+//! cannot be overridden in conditional blocks, so their defaults are left to
+//! be checked for loops as they are evaluated. An entity also depends on
+//! whatever the entities it is subject to depend on, which are the regions
+//! containing it and the conditional block whose branch declared it, and on
+//! every conditional block which overrides anything it depends on. A loop
+//! means that whether a block is taken could depend on the block itself, so a
+//! program may not have one except among functions, which may be recursive.
+
+use crate::ast::{Decl, Stmt};
+use crate::{contained, entity_refs, CompileError, EntityId, Id, Ident, Program, ScopeId};
+use indexmap::IndexSet;
+use std::collections::{HashMap, VecDeque};
+
+/// Report every dependency loop in a program, given the conditional block
+/// whose branch declared each of the entities declared by one.
+pub(crate) fn check(program: &Program, declarers: &HashMap<EntityId, Id>) -> Vec<CompileError> {
+  let graph = Graph::new(program, declarers);
+  let mut errors = Vec::new();
+  for component in graph.components() {
+    // Report each loop once, through the first entity on it which is not a
+    // function, if there is one.
+    let looped = component.len() > 1 || graph.edges[component[0]].contains(&component[0]);
+    let first = component
+      .iter()
+      .copied()
+      .find(|&v| !matches!(graph.nodes[v], EntityId::Function(_)));
+    if let (true, Some(v)) = (looped, first) {
+      let chain = graph.shortest_loop(v, &component);
+      let chain: Vec<_> = chain
+        .iter()
+        .map(|&v| program.path_of(graph.nodes[v]))
+        .collect();
+      let e = graph.nodes[v];
+      let span = match e {
+        EntityId::Cond(c) => program.scope_span(program.conds[c.0].parent),
+        e => program.get_entity_name_span(e),
+      };
+      errors.push(CompileError::DependencyLoop {
+        path: program.path_of(e),
+        chain: chain.join(" -> "),
+        span,
+      });
+    }
+  }
+  errors
+}
+
+struct Graph {
+  nodes: IndexSet<EntityId>,
+  // For each node, the nodes it depends on directly.
+  edges: Vec<Vec<usize>>,
+}
+
+impl Graph {
+  fn new(program: &Program, declarers: &HashMap<EntityId, Id>) -> Graph {
+    let nodes: IndexSet<_> = program.entities().collect();
+    let contents = program.region_contents();
+    let mut modifiers: HashMap<EntityId, Vec<EntityId>> = HashMap::new();
+    for (id, c) in program.conds() {
+      for stmts in [&c.then, &c.otherwise] {
+        let mut targets = Vec::new();
+        overridden(program, &contents, c.parent, stmts, &mut targets);
+        for t in targets {
+          modifiers.entry(t).or_default().push(EntityId::Cond(id));
+        }
+      }
+    }
+    let mut edges = Vec::new();
+    for &e in &nodes {
+      let mut refs = Vec::new();
+      let mut subjects = vec![e];
+      while let Some(s) = subjects.pop() {
+        for x in governing(program, s) {
+          entity_refs(x, &mut refs);
+        }
+        if let ScopeId::Region(r) = program.get_entity(s).unwrap().parent() {
+          subjects.push(EntityId::Region(r));
+        }
+        if let Some(&c) = declarers.get(&s) {
+          subjects.push(EntityId::Cond(c));
+        }
+      }
+      refs.extend(modifiers.get(&e).into_iter().flatten());
+      let mut targets: Vec<_> = refs.iter().filter_map(|r| nodes.get_index_of(r)).collect();
+      targets.sort_unstable();
+      targets.dedup();
+      edges.push(targets);
+    }
+    Graph { nodes, edges }
+  }
+
+  // The strongly connected components of the graph, by Kosaraju's algorithm,
+  // each with its nodes in order.
+  fn components(&self) -> Vec<Vec<usize>> {
+    let n = self.nodes.len();
+    let mut order = Vec::with_capacity(n);
+    let mut seen = vec![false; n];
+    for s in 0..n {
+      if seen[s] {
+        continue;
+      }
+      seen[s] = true;
+      let mut stack = vec![(s, 0)];
+      while let Some((v, i)) = stack.pop() {
+        match self.edges[v].get(i) {
+          Some(&w) => {
+            stack.push((v, i + 1));
+            if !seen[w] {
+              seen[w] = true;
+              stack.push((w, 0));
+            }
+          }
+          None => order.push(v),
+        }
+      }
+    }
+    let mut reverse = vec![Vec::new(); n];
+    for (v, ws) in self.edges.iter().enumerate() {
+      for &w in ws {
+        reverse[w].push(v);
+      }
+    }
+    let mut component = vec![None; n];
+    let mut components = Vec::new();
+    for &s in order.iter().rev() {
+      if component[s].is_some() {
+        continue;
+      }
+      let c = components.len();
+      component[s] = Some(c);
+      let mut members = vec![s];
+      let mut stack = vec![s];
+      while let Some(v) = stack.pop() {
+        for &w in &reverse[v] {
+          if component[w].is_none() {
+            component[w] = Some(c);
+            members.push(w);
+            stack.push(w);
+          }
+        }
+      }
+      members.sort_unstable();
+      components.push(members);
+    }
+    components.sort_unstable_by_key(|c| c[0]);
+    components
+  }
+
+  // The shortest loop from a node back to itself within its component, which
+  // must have one, starting and ending with the node.
+  fn shortest_loop(&self, start: usize, component: &[usize]) -> Vec<usize> {
+    let mut prev = HashMap::new();
+    let mut queue = VecDeque::from([start]);
+    while let Some(v) = queue.pop_front() {
+      for &w in &self.edges[v] {
+        if w == start {
+          let mut chain = vec![start, v];
+          while let Some(&p) = prev.get(chain.last().unwrap()) {
+            chain.push(p);
+          }
+          chain.reverse();
+          return chain;
+        }
+        if component.binary_search(&w).is_ok() && !prev.contains_key(&w) {
+          prev.insert(w, v);
+          queue.push_back(w);
+        }
+      }
+    }
+    unreachable!("{:?} is not on a loop", self.nodes[start])
+  }
+}
+
+// The expressions on which an entity's meaning depends.
+fn governing(program: &Program, e: EntityId) -> Vec<&crate::Expr> {
+  match e {
+    EntityId::Cond(c) => vec![&program.conds[c.0].cond],
+    EntityId::Function(f) => program.functions[f.0].body.iter().collect(),
+    _ => Vec::new(),
+  }
+}
+
+// Collect the entities which the overrides among some statements apply to,
+// not counting those in nested conditional blocks. Replacing or deleting a
+// region applies to everything it contains as well.
+fn overridden(
+  program: &Program,
+  contents: &HashMap<Id, Vec<EntityId>>,
+  scope: ScopeId,
+  stmts: &[Stmt],
+  targets: &mut Vec<EntityId>,
+) {
+  for s in stmts {
+    let (d, whole) = match s {
+      Stmt::Modify(d) => (d, false),
+      Stmt::Override(d) | Stmt::Delete(d) => (d, true),
+      Stmt::Decl(_) | Stmt::Prop(_) | Stmt::Cond(..) => continue,
+    };
+    let target = d.name().and_then(|n| {
+      let i = program.idents.get(&n.ident.0)?;
+      program.lookup(program.get_scope(scope).unwrap(), Ident(i))
+    });
+    let target = match target {
+      Some(t) => t,
+      None => continue,
+    };
+    targets.push(target);
+    if let (EntityId::Region(r), Decl::Region(region)) = (target, d) {
+      if whole {
+        contained(contents, r, targets);
+      } else {
+        overridden(
+          program,
+          contents,
+          ScopeId::Region(r),
+          &region.stmts,
+          targets,
+        );
+      }
+    }
+  }
+}
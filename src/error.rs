@@ -0,0 +1,323 @@
+//! Errors reported while compiling a Rado program.
+//!
+//! Spans, where present, point at the name of the declaration the error was
+//! found in. ASTs which were not parsed from source have no spans.
+
+use crate::ast::Span;
+use crate::{EntityId, EntityKind};
+use std::fmt;
+use thiserror::Error;
+
+/// The kind of declaration whose block a statement appears in.
+#[derive(Copy, Clone, Hash, PartialEq, Eq, Debug)]
+pub enum StmtContext {
+  Global,
+  Region,
+  Item,
+  Location,
+  Link,
+  Config,
+}
+
+impl fmt::Display for StmtContext {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      StmtContext::Global => "the global scope",
+      StmtContext::Region => "regions",
+      StmtContext::Item => "items",
+      StmtContext::Location => "locations",
+      StmtContext::Link => "links",
+      StmtContext::Config => "configs",
+    })
+  }
+}
+
+/// An error in the meaning of a Rado program.
+#[derive(Clone, Debug, Error)]
+pub enum CompileError {
+  #[error("{name} is already declared in the same scope")]
+  DuplicateName {
+    name: String,
+    existing: EntityId,
+    span: Option<Span>,
+  },
+  #[error("{name} shadows a declaration in an enclosing scope")]
+  ShadowedName {
+    name: String,
+    existing: EntityId,
+    span: Option<Span>,
+  },
+  #[error("tag {name} has the same name as another entity")]
+  TagCollision { name: String, existing: EntityId },
+  #[error("{keyword} statement not allowed in {context}")]
+  PropNotAllowed {
+    keyword: &'static str,
+    context: StmtContext,
+    span: Option<Span>,
+  },
+  #[error("only property statements are allowed in {context}")]
+  DeclNotAllowed {
+    context: StmtContext,
+    span: Option<Span>,
+  },
+  #[error("duplicate {keyword} statement")]
+  DuplicateProp {
+    keyword: &'static str,
+    span: Option<Span>,
+  },
+  #[error("duplicate val {name}")]
+  DuplicateVal { name: String, span: Option<Span> },
+  #[error("duplicate parameter {name}")]
+  DuplicateParam { name: String, span: Option<Span> },
+  #[error("modifier list used outside a modifying declaration")]
+  UnexpectedModifierList { span: Option<Span> },
+  #[error(
+    "overriding, modifying and deleting declarations are only allowed in conditional blocks"
+  )]
+  OverrideOutsideConditional { span: Option<Span> },
+  #[error("only declarations with a single name can be overridden")]
+  UnnamedOverride { span: Option<Span> },
+  #[error("{path} is not a {kind}")]
+  OverrideMismatch {
+    path: String,
+    kind: EntityKind,
+    span: Option<Span>,
+  },
+  #[error("cannot {action} {path}")]
+  CannotOverride {
+    action: &'static str,
+    path: String,
+    span: Option<Span>,
+  },
+  #[error("conflicting overrides of {path}")]
+  ConflictingOverrides { path: String, span: Option<Span> },
+  #[error("{path} has been deleted")]
+  Deleted { path: String, span: Option<Span> },
+  #[error("the replacement for {path} changes its signature")]
+  SignatureChanged { path: String, span: Option<Span> },
+  #[error("configs and configsets cannot be declared or overridden in conditional blocks")]
+  ConfigInConditional { span: Option<Span> },
+  #[error("the condition of a conditional block must be a constant expression")]
+  NonConstantCondition { span: Option<Span> },
+  #[error("a conditional block cannot have both of the exclusive tags {first} and {second}")]
+  ExclusiveTags {
+    first: String,
+    second: String,
+    span: Option<Span>,
+  },
+  #[error("start in statement is only allowed in the global scope")]
+  StartInOutsideGlobal { span: Option<Span> },
+  #[error("links are only allowed in regions")]
+  LinkOutsideRegion { span: Option<Span> },
+  #[error("empty path")]
+  EmptyPath,
+  #[error("{path} not found")]
+  NotFound { path: String, span: Option<Span> },
+  #[error("{path} is not a scope")]
+  NotAScope {
+    path: String,
+    entity: EntityId,
+    span: Option<Span>,
+  },
+  #[error("{path} is not an enum variant")]
+  NotAVariant {
+    path: String,
+    entity: EntityId,
+    span: Option<Span>,
+  },
+  #[error("{path} is not a config")]
+  NotAConfig {
+    path: String,
+    entity: EntityId,
+    span: Option<Span>,
+  },
+  #[error("{path} is not a configset")]
+  NotAConfigset {
+    path: String,
+    entity: EntityId,
+    span: Option<Span>,
+  },
+  #[error("{path} is not a type")]
+  NotAType {
+    path: String,
+    entity: EntityId,
+    span: Option<Span>,
+  },
+  #[error("expected {expected}, found {found}")]
+  TypeMismatch {
+    expected: String,
+    found: String,
+    span: Option<Span>,
+  },
+  #[error("{name} is not a value")]
+  NotAValue { name: String, span: Option<Span> },
+  #[error("{found} is not a function")]
+  NotAFunction { found: String, span: Option<Span> },
+  #[error("{found} is not a region")]
+  NotARegion { found: String, span: Option<Span> },
+  #[error("expected {expected} arguments, found {found}")]
+  WrongArgCount {
+    expected: usize,
+    found: usize,
+    span: Option<Span>,
+  },
+  #[error("no item tagged {tag} has a val {val}")]
+  NoSuchVal {
+    tag: String,
+    val: String,
+    span: Option<Span>,
+  },
+  #[error("val {val} has type {found} here, but {expected} on other items tagged {tag}")]
+  TagValMismatch {
+    tag: String,
+    val: String,
+    expected: String,
+    found: String,
+    span: Option<Span>,
+  },
+  #[error("{config} is not of an enum type, so it has no values to disallow")]
+  DisallowNotEnum { config: String, span: Option<Span> },
+  #[error("{variant} is not a variant of {config}")]
+  UnknownVariant {
+    config: String,
+    variant: String,
+    span: Option<Span>,
+  },
+  #[error("every value of {config} is disallowed")]
+  AllDisallowed { config: String, span: Option<Span> },
+  #[error("cannot infer the type of {name}")]
+  CannotInfer { name: String, span: Option<Span> },
+  #[error("configset {name} includes itself")]
+  ConfigsetCycle { name: String, span: Option<Span> },
+  #[error("configset {configset} gives {config} conflicting values")]
+  ConfigsetConflict {
+    configset: String,
+    config: String,
+    span: Option<Span>,
+  },
+  #[error("{error}")]
+  ConfigsetValue {
+    error: ConfigError,
+    span: Option<Span>,
+  },
+  #[error("{path} depends on itself: {chain}")]
+  DependencyLoop {
+    path: String,
+    chain: String,
+    span: Option<Span>,
+  },
+}
+
+impl CompileError {
+  // Lookups are done without knowing where the path appeared, so the span is
+  // filled in afterwards.
+  pub(crate) fn with_span(self, span: Option<Span>) -> CompileError {
+    match self {
+      CompileError::NotFound { path, .. } => CompileError::NotFound { path, span },
+      CompileError::NotAScope { path, entity, .. } => {
+        CompileError::NotAScope { path, entity, span }
+      }
+      e => e,
+    }
+  }
+}
+
+/// An error in the values chosen to configure a program, or in evaluating the
+/// defaults of the configs left unset.
+///
+/// Errors in evaluation say which expression they were found in, as in "the
+/// default of Modes.State".
+#[derive(Clone, Debug, Error)]
+pub enum ConfigError {
+  #[error("{path} is not a config")]
+  UnknownConfig { path: String },
+  #[error("{variant} is not a variant of {path}")]
+  UnknownVariant { path: String, variant: String },
+  #[error("expected {expected} for {path}, found {found}")]
+  TypeMismatch {
+    path: String,
+    expected: String,
+    found: String,
+  },
+  #[error("{path} is not a configset")]
+  UnknownConfigset { path: String },
+  #[error("configsets {first} and {second} give {config} different values")]
+  ConfigsetConflict {
+    config: String,
+    first: String,
+    second: String,
+  },
+  #[error("no value given for {path}, which has no default")]
+  Missing { path: String },
+  #[error("{variant} is disallowed for {path}")]
+  Disallowed { path: String, variant: String },
+  #[error("the default of {path} depends on itself")]
+  CyclicDefault { path: String },
+  #[error("{context} is not a constant expression")]
+  NotConstant { context: String },
+  #[error("{context} divides by zero")]
+  DivideByZero { context: String },
+  #[error("{context} has no match arm for {variant}")]
+  NoMatchingArm { context: String, variant: String },
+  #[error("{context} recurses too deeply")]
+  RecursionLimit { context: String },
+  /// An error in the declarations of a conditional block, found once the
+  /// configuration chose to apply them.
+  #[error("in a conditional block: {error}")]
+  Conditional { error: Box<CompileError> },
+  #[error("the exclusive conditional tags {first} and {second} are both active")]
+  ExclusiveTags { first: String, second: String },
+  #[error("{block} is inactive, although {active}, which has all of its tags, is active")]
+  InactiveBlock { block: String, active: String },
+}
+
+/// All the errors found while configuring a program.
+#[derive(Clone, Debug, Default)]
+pub struct ConfigErrors {
+  pub errors: Vec<ConfigError>,
+}
+
+impl fmt::Display for ConfigErrors {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    for e in &self.errors {
+      writeln!(f, "error: {}", e)?;
+    }
+    Ok(())
+  }
+}
+
+impl std::error::Error for ConfigErrors {}
+
+/// A problem in a Rado program which does not prevent it from compiling.
+#[derive(Clone, Debug, Error)]
+pub enum CompileWarning {
+  #[error("conditional block has no effect")]
+  EmptyConditional { span: Option<Span> },
+  #[error("{item} is already tagged {tag}")]
+  RedundantTag {
+    item: String,
+    tag: String,
+    span: Option<Span>,
+  },
+}
+
+/// All the errors and warnings found while compiling a program.
+#[derive(Clone, Debug, Default)]
+pub struct Diagnostics {
+  pub errors: Vec<CompileError>,
+  pub warnings: Vec<CompileWarning>,
+}
+
+impl fmt::Display for Diagnostics {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    for e in &self.errors {
+      writeln!(f, "error: {}", e)?;
+    }
+    for w in &self.warnings {
+      writeln!(f, "warning: {}", w)?;
+    }
+    Ok(())
+  }
+}
+
+impl std::error::Error for Diagnostics {}
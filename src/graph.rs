@@ -0,0 +1,152 @@
+//! The graph of how regions connect to each other through links.
+//!
+//! A link is declared in a region and leads to or from the regions it names,
+//! or both ways for `link with`. Each way it can be travelled is an edge, so
+//! two regions may be joined by several edges. Reaching a region means meeting
+//! its requirement and those of the regions enclosing it, so an edge requires
+//! those of its link and of the regions it enters. The item unlocking a link or
+//! region is required in the same way.
+
+use crate::{EntityId, Expr, Grant, Id, LinkDir, Path, Program, ScopeId};
+use std::collections::HashMap;
+
+/// A directed multigraph of the regions of a program, with an edge for each
+/// way that each link can be travelled.
+pub struct RegionGraph {
+  edges: Vec<Edge>,
+  // The edges leaving and entering each region, in the order of the links.
+  outgoing: HashMap<Id, Vec<usize>>,
+  incoming: HashMap<Id, Vec<usize>>,
+}
+
+/// One way of travelling a link from one region to another.
+#[derive(Clone, Debug)]
+pub struct Edge {
+  pub link: Id,
+  pub from: Id,
+  pub to: Id,
+  /// The requirement to travel the edge, or None if there is none. This is
+  /// the requirement of its destination and of each region enclosing that
+  /// but not the source, outermost first, and then that of its link. Each
+  /// region's or link's requirement is followed by the item unlocking it.
+  pub requires: Option<Expr>,
+  /// The items granted or removed on travelling the edge, which are those of
+  /// its link.
+  pub grants: Vec<Grant>,
+}
+
+impl RegionGraph {
+  pub(crate) fn new(program: &Program) -> RegionGraph {
+    let mut graph = RegionGraph {
+      edges: Vec::new(),
+      outgoing: HashMap::new(),
+      incoming: HashMap::new(),
+    };
+    for (id, l) in program.links() {
+      let home = match l.parent {
+        ScopeId::Region(r) => r,
+        ScopeId::Global => continue,
+      };
+      for &r in &l.targets {
+        if program.is_deleted(EntityId::Region(r)) {
+          continue;
+        }
+        let (to, from) = (l.dir != LinkDir::From, l.dir != LinkDir::To);
+        if to {
+          graph.add(program, id, home, r);
+        }
+        if from {
+          graph.add(program, id, r, home);
+        }
+      }
+    }
+    graph
+  }
+
+  fn add(&mut self, program: &Program, link: Id, from: Id, to: Id) {
+    let i = self.edges.len();
+    self.edges.push(Edge {
+      link,
+      from,
+      to,
+      requires: requirement(program, link, from, to),
+      grants: program.links[link.0].grants.clone(),
+    });
+    self.outgoing.entry(from).or_default().push(i);
+    self.incoming.entry(to).or_default().push(i);
+  }
+
+  /// All the edges, in the order their links were declared.
+  pub fn edges(&self) -> &[Edge] {
+    &self.edges
+  }
+
+  /// The edges leading out of a region.
+  pub fn edges_from(&self, r: Id) -> impl Iterator<Item = &Edge> + '_ {
+    let ids = self.outgoing.get(&r).map_or(&[][..], |v| &v[..]);
+    ids.iter().map(move |&i| &self.edges[i])
+  }
+
+  /// The edges leading into a region.
+  pub fn edges_to(&self, r: Id) -> impl Iterator<Item = &Edge> + '_ {
+    let ids = self.incoming.get(&r).map_or(&[][..], |v| &v[..]);
+    ids.iter().map(move |&i| &self.edges[i])
+  }
+
+  /// The edges leading from one region directly to another.
+  pub fn edges_between(&self, from: Id, to: Id) -> impl Iterator<Item = &Edge> + '_ {
+    self.edges_from(from).filter(move |e| e.to == to)
+  }
+
+  /// The regions which can be reached directly from a region, each once, in
+  /// the order of their first edges.
+  pub fn neighbours(&self, r: Id) -> Vec<Id> {
+    let mut found = Vec::new();
+    for e in self.edges_from(r) {
+      if !found.contains(&e.to) {
+        found.push(e.to);
+      }
+    }
+    found
+  }
+}
+
+// The requirement to travel a link from one region to another. The regions
+// enclosing the source have already been entered, and so add nothing.
+fn requirement(program: &Program, link: Id, from: Id, to: Id) -> Option<Expr> {
+  let entered = enclosing(program, from);
+  let mut conds = Vec::new();
+  for r in enclosing(program, to).into_iter().rev() {
+    if entered.contains(&r) {
+      continue;
+    }
+    let props = &program.regions[r.0].props;
+    conds.extend(props.requires.clone());
+    conds.extend(unlock(program, ScopeId::Region(r), props.unlock.as_ref()));
+  }
+  let l = &program.links[link.0];
+  conds.extend(l.requires.clone());
+  conds.extend(unlock(program, l.parent, l.unlock.as_ref()));
+  match conds.len() {
+    0 => None,
+    1 => conds.pop(),
+    _ => Some(Expr::And(conds)),
+  }
+}
+
+// The requirement to have the item unlocking something declared in a scope.
+// Unlocks were checked when compiling, so one which can't be found was already
+// reported and is left out.
+fn unlock(program: &Program, scope: ScopeId, item: Option<&Path>) -> Option<Expr> {
+  let scope = program.get_scope(scope)?;
+  program.lookup_entity(scope, item?).ok().map(Expr::Entity)
+}
+
+// A region and the regions enclosing it, innermost first.
+fn enclosing(program: &Program, r: Id) -> Vec<Id> {
+  let mut regions = vec![r];
+  while let ScopeId::Region(p) = program.regions[regions.last().unwrap().0].parent {
+    regions.push(p);
+  }
+  regions
+}
@@ -1,19 +1,34 @@
 #![warn(clippy::all)]
 
+mod arena;
 pub mod ast;
+mod check;
+mod config;
+mod deps;
+pub mod error;
+mod graph;
 pub(crate) mod token;
 
-use anyhow::{anyhow, Error};
-use ast::{Decl, ModVec, Prop, Stmt};
-use id_map::IdMap;
+use arena::Arena;
+use ast::{Decl, ModVec, Prop, Span, Stmt};
+pub use config::{ConfigValue, ConfigValues, ConfiguredProgram, Value};
+pub use error::{
+  CompileError, CompileWarning, ConfigError, ConfigErrors, Diagnostics, StmtContext,
+};
+pub use graph::{Edge, RegionGraph};
+use indexmap::map::Entry;
+use indexmap::{IndexMap, IndexSet};
 use mixed_ref::MixedRef;
-use std::collections::{HashMap, HashSet};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
 use string_interner::DefaultStringInterner;
 
 /// An identifier in a Rado program.
 ///
-/// Identifiers are interned within a single Program, so they cannot be directly
-/// converted to strings.
+/// Identifiers are interned within a single Program, so they can only be
+/// converted to strings by the program they came from, using
+/// Program::resolve.
 #[derive(Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Debug)]
 pub struct Ident(string_interner::Sym);
 
@@ -25,6 +40,46 @@ pub struct Ident(string_interner::Sym);
 pub struct Name {
   pub ident: Ident,
   pub human: Option<String>,
+  /// Where the name was declared, if known.
+  pub span: Option<Span>,
+}
+
+/// Derive a human-readable name from an `UpperCamelCase` identifier by
+/// splitting it into words, as in "Master Sword" for `MasterSword`.
+///
+/// Runs of capitals are kept together as acronyms, so that `HUDIcon` becomes
+/// "HUD Icon". Numbers are separate words, as in "Crystal 5", except that they
+/// stay attached to an acronym, as in "L2 Sword". Underscores also separate
+/// words.
+pub fn human_name(ident: &str) -> String {
+  let chars = ident.chars().collect::<Vec<_>>();
+  let mut words: Vec<String> = Vec::new();
+  let mut word = String::new();
+  for (i, &c) in chars.iter().enumerate() {
+    if c == '_' {
+      words.extend(Some(std::mem::take(&mut word)).filter(|w| !w.is_empty()));
+      continue;
+    }
+    if let Some(prev) = word.chars().last() {
+      let next = chars.get(i + 1);
+      let acronym = word.chars().all(|c| c.is_uppercase() || c.is_numeric());
+      let split = if c.is_uppercase() {
+        prev.is_lowercase()
+          || prev.is_numeric()
+          || (prev.is_uppercase() && next.is_some_and(|n| n.is_lowercase()))
+      } else if c.is_numeric() {
+        !prev.is_numeric() && !acronym
+      } else {
+        prev.is_numeric() && !acronym
+      };
+      if split {
+        words.push(std::mem::take(&mut word));
+      }
+    }
+    word.push(c);
+  }
+  words.extend(Some(word).filter(|w| !w.is_empty()));
+  words.join(" ")
 }
 
 /// A path is a series of names to be used to lookup an entity or value. Paths
@@ -34,9 +89,9 @@ pub struct Path(Vec<Ident>);
 
 impl Path {
   /// Construct a new path. Returns an error if `segments` is empty.
-  pub fn new(segments: Vec<Ident>) -> Result<Path, Error> {
+  pub fn new(segments: Vec<Ident>) -> Result<Path, CompileError> {
     if segments.is_empty() {
-      Err(anyhow!("trying to construct empty path"))
+      Err(CompileError::EmptyPath)
     } else {
       Ok(Path(segments))
     }
@@ -48,10 +103,10 @@ impl Path {
 /// Ids are only unique within a given type; two entities of different types may
 /// have the same Id. Use EntityId instead to get uniqueness.
 #[derive(Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Debug)]
-pub struct Id(id_map::Id);
+pub struct Id(usize);
 
-impl From<Id> for id_map::Id {
-  fn from(i: Id) -> id_map::Id {
+impl From<Id> for usize {
+  fn from(i: Id) -> usize {
     i.0
   }
 }
@@ -66,6 +121,109 @@ pub enum EntityId {
   /// they use an identifier directly as the id. The corresponding Tag struct
   /// type is used only for implementing the Entity trait.
   Tag(Ident),
+  Location(Id),
+  Link(Id),
+  Function(Id),
+  Enum(Id),
+  Variant(Id),
+  Config(Id),
+  Configset(Id),
+  Random(Id),
+  /// Conditional blocks are anonymous, so they can never be found by lookup.
+  Cond(Id),
+}
+
+/// An id for an entity which is derived from its kind and full path, rather
+/// than from the order it was declared in.
+///
+/// Unlike EntityId, a stable id stays the same when other entities are added
+/// or removed, and across versions of the program, so long as the entity is
+/// not renamed or moved. It is therefore suitable for saving or sending to
+/// other programs. Anonymous links are identified by their direction and the
+/// regions they name, and only by their position among those of their scope
+/// which are otherwise the same.
+///
+/// Conditional blocks have nothing to identify them but their position among
+/// those of their scope, as in Program::path_of, so their stable ids change
+/// when a block is added or removed before them.
+#[derive(Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Debug, Serialize, Deserialize)]
+pub struct StableId(pub u64);
+
+impl StableId {
+  // A 64-bit FNV-1a hash, which unlike the standard library's hashers is
+  // guaranteed never to change.
+  fn of(kind: EntityKind, path: &str) -> StableId {
+    let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in format!("{}:{}", kind.stable_tag(), path).bytes() {
+      h ^= u64::from(b);
+      h = h.wrapping_mul(0x0100_0000_01b3);
+    }
+    StableId(h)
+  }
+}
+
+impl fmt::Display for StableId {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{:016x}", self.0)
+  }
+}
+
+/// The kind of an entity, corresponding to the variants of EntityId.
+#[derive(Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum EntityKind {
+  Region,
+  Item,
+  Tag,
+  Location,
+  Link,
+  Function,
+  Enum,
+  Variant,
+  Config,
+  Configset,
+  Random,
+  Cond,
+}
+
+impl EntityKind {
+  // The tag for the kind in the input to stable ids. Unlike the names it is
+  // displayed and debugged with, these must never change, or every stable id
+  // saved so far would be lost.
+  fn stable_tag(self) -> &'static str {
+    match self {
+      EntityKind::Region => "Region",
+      EntityKind::Item => "Item",
+      EntityKind::Tag => "Tag",
+      EntityKind::Location => "Location",
+      EntityKind::Link => "Link",
+      EntityKind::Function => "Function",
+      EntityKind::Enum => "Enum",
+      EntityKind::Variant => "Variant",
+      EntityKind::Config => "Config",
+      EntityKind::Configset => "Configset",
+      EntityKind::Random => "Random",
+      EntityKind::Cond => "Cond",
+    }
+  }
+}
+
+impl fmt::Display for EntityKind {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(match self {
+      EntityKind::Region => "region",
+      EntityKind::Item => "item",
+      EntityKind::Tag => "tag",
+      EntityKind::Location => "location",
+      EntityKind::Link => "link",
+      EntityKind::Function => "function",
+      EntityKind::Enum => "enum",
+      EntityKind::Variant => "enum variant",
+      EntityKind::Config => "config",
+      EntityKind::Configset => "configset",
+      EntityKind::Random => "random",
+      EntityKind::Cond => "conditional block",
+    })
+  }
 }
 
 macro_rules! unwrap_entity_id {
@@ -85,15 +243,112 @@ macro_rules! unwrap_entity_id {
 }
 
 impl EntityId {
+  /// The kind of entity this identifies.
+  pub fn kind(&self) -> EntityKind {
+    match self {
+      EntityId::Region(_) => EntityKind::Region,
+      EntityId::Item(_) => EntityKind::Item,
+      EntityId::Tag(_) => EntityKind::Tag,
+      EntityId::Location(_) => EntityKind::Location,
+      EntityId::Link(_) => EntityKind::Link,
+      EntityId::Function(_) => EntityKind::Function,
+      EntityId::Enum(_) => EntityKind::Enum,
+      EntityId::Variant(_) => EntityKind::Variant,
+      EntityId::Config(_) => EntityKind::Config,
+      EntityId::Configset(_) => EntityKind::Configset,
+      EntityId::Random(_) => EntityKind::Random,
+      EntityId::Cond(_) => EntityKind::Cond,
+    }
+  }
+
   unwrap_entity_id!(unwrap_region, Region, Id);
   unwrap_entity_id!(unwrap_item, Item, Id);
   unwrap_entity_id!(unwrap_tag, Tag, Ident);
+  unwrap_entity_id!(unwrap_location, Location, Id);
+  unwrap_entity_id!(unwrap_link, Link, Id);
+  unwrap_entity_id!(unwrap_function, Function, Id);
+  unwrap_entity_id!(unwrap_enum, Enum, Id);
+  unwrap_entity_id!(unwrap_variant, Variant, Id);
+  unwrap_entity_id!(unwrap_config, Config, Id);
+  unwrap_entity_id!(unwrap_configset, Configset, Id);
+  unwrap_entity_id!(unwrap_random, Random, Id);
+  unwrap_entity_id!(unwrap_cond, Cond, Id);
+}
+
+macro_rules! entity_downcast {
+  ($name:ident, $ty:ident) => {
+    #[doc = concat!("Downcast to a ", stringify!($ty), ", if this is one.")]
+    fn $name(&self) -> Option<&$ty> {
+      None
+    }
+  };
 }
 
 /// A trait that abstracts over the various entities in Rado.
-pub trait Entity {
+pub trait Entity: Send + Sync {
   /// Retrieve the entity's parent scope.
   fn parent(&self) -> ScopeId;
+  /// The kind of the entity.
+  fn kind(&self) -> EntityKind;
+  /// The entity's name. Anonymous links and conditional blocks have none. Use
+  /// Program::display_name for a name that can always be shown.
+  fn name(&self) -> Option<&Name>;
+  /// The entity's tags. Only items can have tags.
+  fn tags<'a>(&'a self) -> Box<dyn Iterator<Item = Ident> + 'a> {
+    Box::new(std::iter::empty())
+  }
+
+  entity_downcast!(as_region, Region);
+  entity_downcast!(as_item, Item);
+  entity_downcast!(as_tag, Tag);
+  entity_downcast!(as_location, Location);
+  entity_downcast!(as_link, Link);
+  entity_downcast!(as_function, Function);
+  entity_downcast!(as_enum, Enum);
+  entity_downcast!(as_variant, Variant);
+  entity_downcast!(as_config, Config);
+  entity_downcast!(as_configset, Configset);
+  entity_downcast!(as_random, Random);
+  entity_downcast!(as_cond, Cond);
+}
+
+// The name of an entity, for the entities which may be anonymous as well as
+// those which may not.
+trait EntityName {
+  fn entity_name(&self) -> Option<&Name>;
+}
+
+impl EntityName for Name {
+  fn entity_name(&self) -> Option<&Name> {
+    Some(self)
+  }
+}
+
+impl EntityName for Option<Name> {
+  fn entity_name(&self) -> Option<&Name> {
+    self.as_ref()
+  }
+}
+
+macro_rules! impl_entity {
+  ($($ty:ident => $as:ident),*) => {
+    $(
+      impl Entity for $ty {
+        fn parent(&self) -> ScopeId {
+          self.parent
+        }
+        fn kind(&self) -> EntityKind {
+          EntityKind::$ty
+        }
+        fn name(&self) -> Option<&Name> {
+          self.name.entity_name()
+        }
+        fn $as(&self) -> Option<&$ty> {
+          Some(self)
+        }
+      }
+    )*
+  };
 }
 
 /// An identifier for a scope in a Rado program.
@@ -110,26 +365,71 @@ pub struct PrivateHack(());
 
 /// A trait that abstracts over the various scopes in Rado. Almost all
 /// implementations are also entities, but Program implements the global scope.
-pub trait Scope {
+pub trait Scope: Send + Sync {
   /// Retrieve the scope's parent. Only the global scope does not have a parent.
   fn parent(&self) -> Option<ScopeId>;
   /// Lookup a single identifier in this scope. To do lookup across a scope
   /// tree, which is more usual, use methods of Program.
   fn lookup_ident(&self, i: Ident) -> Option<EntityId>;
-  /// Get an iterator over all children of this scope.
+  /// Get an iterator over all children of this scope, in the order they were
+  /// declared. Aliases appear as additional children.
   fn children<'a>(&'a self) -> Box<dyn Iterator<Item = (&'a Ident, &'a EntityId)> + 'a>;
 
-  /// Private method.
-  fn insert_child(&mut self, i: Ident, e: EntityId, _: PrivateHack);
+  /// Private method. Fails with the existing entity if the identifier is
+  /// already in use, leaving the scope unchanged.
+  fn insert_child(&mut self, i: Ident, e: EntityId, _: PrivateHack) -> Result<(), EntityId>;
+}
+
+fn insert_new(map: &mut IndexMap<Ident, EntityId>, i: Ident, e: EntityId) -> Result<(), EntityId> {
+  match map.entry(i) {
+    Entry::Occupied(o) => Err(*o.get()),
+    Entry::Vacant(v) => {
+      v.insert(e);
+      Ok(())
+    }
+  }
 }
 
 /// A Rado program represents the abstract state of a Rado program prior to it
 /// being configured or used.
+///
+/// Everything in a program is kept in the order it was declared, so compiling
+/// the same source always gives the same ids, iteration order and diagnostics.
+///
+/// A compiled program cannot be modified, and is Send and Sync, so it can be
+/// shared between threads behind an Arc. Anything which varies from one use of
+/// a program to the next is kept outside it.
+#[derive(Clone)]
 pub struct Program {
-  items: IdMap<Item>,
-  regions: IdMap<Region>,
-  global_decls: HashMap<Ident, EntityId>,
+  items: Arena<Item>,
+  regions: Arena<Region>,
+  locations: Arena<Location>,
+  links: Arena<Link>,
+  functions: Arena<Function>,
+  enums: Arena<Enum>,
+  variants: Arena<Variant>,
+  configs: Arena<Config>,
+  configsets: Arena<Configset>,
+  randoms: Arena<Random>,
+  conds: Arena<Cond>,
+  global_decls: IndexMap<Ident, EntityId>,
+  // Additional names given by alias statements, which are bound in the same
+  // scope as the primary name.
+  aliases: HashMap<EntityId, Vec<Ident>>,
+  // Entities deleted by overrides. They stay in their arenas and scopes as
+  // placeholders, so that their names remain bound.
+  deleted: HashSet<EntityId>,
+  // The sets of conditional tags given by exclusive statements.
+  exclusive: Vec<Vec<CondTag>>,
+  // The global scope is also the global region, and can have some of the same
+  // properties as other regions.
+  props: RegionProps,
+  start_in: Option<Path>,
   idents: DefaultStringInterner,
+  // Filled in once the program is complete, since paths may not be known
+  // until then.
+  stable_ids: HashMap<StableId, EntityId>,
+  warnings: Vec<CompileWarning>,
 }
 
 impl Scope for Program {
@@ -139,10 +439,8 @@ impl Scope for Program {
   fn lookup_ident(&self, i: Ident) -> Option<EntityId> {
     self.global_decls.get(&i).copied()
   }
-  fn insert_child(&mut self, i: Ident, e: EntityId, _: PrivateHack) {
-    if self.global_decls.insert(i, e).is_some() {
-      panic!("overwrote existing entity when inserting new one");
-    }
+  fn insert_child(&mut self, i: Ident, e: EntityId, _: PrivateHack) -> Result<(), EntityId> {
+    insert_new(&mut self.global_decls, i, e)
   }
   fn children<'a>(&'a self) -> Box<dyn Iterator<Item = (&'a Ident, &'a EntityId)> + 'a> {
     Box::new(self.global_decls.iter())
@@ -153,18 +451,428 @@ impl Default for Program {
   /// Construct a completely empty program.
   fn default() -> Program {
     Program {
-      items: IdMap::new(),
-      regions: IdMap::new(),
-      global_decls: HashMap::new(),
+      items: Arena::new(),
+      regions: Arena::new(),
+      locations: Arena::new(),
+      links: Arena::new(),
+      functions: Arena::new(),
+      enums: Arena::new(),
+      variants: Arena::new(),
+      configs: Arena::new(),
+      configsets: Arena::new(),
+      randoms: Arena::new(),
+      conds: Arena::new(),
+      global_decls: IndexMap::new(),
+      aliases: HashMap::new(),
+      deleted: HashSet::new(),
+      exclusive: Vec::new(),
+      props: RegionProps::default(),
+      start_in: None,
       idents: DefaultStringInterner::new(),
+      stable_ids: HashMap::new(),
+      warnings: Vec::new(),
     }
   }
 }
 
+macro_rules! entity_iter {
+  ($name:ident, $ty:ident, $kind:literal) => {
+    #[doc = concat!("Iterate over all the ", $kind, ", in the order they were declared.")]
+    pub fn $name(&self) -> impl Iterator<Item = (Id, &$ty)> + '_ {
+      self
+        .$name
+        .iter()
+        .map(|(id, e)| (Id(id), e))
+        .filter(move |(id, _)| !self.deleted.contains(&EntityId::$ty(*id)))
+    }
+  };
+}
+
+macro_rules! borrow_entity {
+  ($map:expr, $id:expr) => {
+    $map
+      .get($id.0)
+      .map(|e| MixedRef::Borrowed(e as &dyn Entity))
+  };
+}
+
 impl Program {
-  /// Construct a program from an AST.
-  pub fn from_ast(file: ast::File) -> Result<Program, Error> {
-    FromAST(Program::default()).build(file)
+  /// Construct a program from an AST. Compilation carries on past errors where
+  /// it can, so that all of them are reported together.
+  pub fn from_ast(file: ast::File) -> Result<Program, Diagnostics> {
+    FromAST::new().build(file)
+  }
+
+  /// The string an identifier stands for.
+  ///
+  /// # Panics
+  ///
+  /// Panics if the identifier comes from a different program.
+  pub fn resolve(&self, i: Ident) -> &str {
+    self
+      .idents
+      .resolve(i.0)
+      .expect("identifier from another program")
+  }
+
+  /// The full path of an entity from the global scope, such as
+  /// `LightWorld.HyruleCastle.Basement.Upper`. Anonymous links and conditional
+  /// blocks are numbered among those of their scope, as in `Region.<link 2>`,
+  /// since they cannot be named. Deleted entities keep the paths they had.
+  ///
+  /// # Panics
+  ///
+  /// Panics if the entity comes from a different program.
+  pub fn path_of(&self, e: EntityId) -> String {
+    let parent = self
+      .get_entity(e)
+      .expect("entity from another program")
+      .parent();
+    let mut segs = self.scope_path(parent);
+    let last = match self.name_of(e) {
+      Some(n) => self.resolve(n).to_string(),
+      None => {
+        let (kind, n) = match e {
+          EntityId::Link(l) => (
+            "link",
+            self
+              .links
+              .iter()
+              .filter(|(id, k)| *id <= l.0 && k.parent == parent && k.name.is_none())
+              .count(),
+          ),
+          EntityId::Cond(c) => (
+            "cond",
+            self
+              .conds
+              .iter()
+              .filter(|(id, k)| *id <= c.0 && k.parent == parent)
+              .count(),
+          ),
+          _ => unreachable!(),
+        };
+        format!("<{} {}>", kind, n)
+      }
+    };
+    segs.push(last);
+    segs.join(".")
+  }
+
+  // The expressions governing an entity, apart from those in its contents.
+  fn exprs_of(&self, e: EntityId) -> Vec<&Expr> {
+    match e {
+      EntityId::Region(r) => {
+        let p = &self.regions[r.0].props;
+        p.requires.iter().chain(&p.visible).collect()
+      }
+      EntityId::Item(i) => {
+        let i = &self.items[i.0];
+        i.vals.values().map(|v| &v.val).chain(&i.max).collect()
+      }
+      EntityId::Location(l) => {
+        let l = &self.locations[l.0];
+        l.requires.iter().chain(&l.visible).collect()
+      }
+      EntityId::Link(l) => self.links[l.0].requires.iter().collect(),
+      EntityId::Function(f) => self.functions[f.0].body.iter().collect(),
+      EntityId::Config(c) => self.configs[c.0].default.iter().collect(),
+      EntityId::Configset(c) => self.configsets[c.0].vals.iter().map(|(_, e)| e).collect(),
+      EntityId::Random(r) => self.randoms[r.0].vals.iter().collect(),
+      EntityId::Cond(c) => vec![&self.conds[c.0].cond],
+      EntityId::Tag(_) | EntityId::Enum(_) | EntityId::Variant(_) => Vec::new(),
+    }
+  }
+
+  // Record the stable id of every entity, once all their paths are known.
+  fn fill_stable_ids(&mut self) {
+    let stable_ids = self.entities().map(|e| (self.stable_id(e), e)).collect();
+    self.stable_ids = stable_ids;
+  }
+
+  /// The stable id of an entity.
+  pub fn stable_id(&self, e: EntityId) -> StableId {
+    StableId::of(e.kind(), &self.stable_path(e))
+  }
+
+  // The path a stable id is derived from. This is the entity's full path,
+  // except that an anonymous link is named by what it links, as in
+  // `Region.<link to A, B>`, and numbered only among the anonymous links of
+  // its scope which link the same, so that it keeps its id when others are
+  // added or removed.
+  fn stable_path(&self, e: EntityId) -> String {
+    let (l, link) = match e {
+      EntityId::Link(l) if self.links[l.0].name.is_none() => (l, &self.links[l.0]),
+      e => return self.path_of(e),
+    };
+    let same = |k: &Link| {
+      k.parent == link.parent && k.name.is_none() && k.dir == link.dir && k.regions == link.regions
+    };
+    let n = self
+      .links
+      .iter()
+      .filter(|(id, k)| *id <= l.0 && same(k))
+      .count();
+    let regions = link
+      .regions
+      .iter()
+      .map(|p| self.path_str(&p.0))
+      .collect::<Vec<_>>()
+      .join(", ");
+    let mut segs = self.scope_path(link.parent);
+    segs.push(match n {
+      1 => format!("<link {} {}>", link.dir.keyword(), regions),
+      n => format!("<link {} {} {}>", link.dir.keyword(), regions, n),
+    });
+    segs.join(".")
+  }
+
+  /// Find the entity with a stable id, if there is one in this program.
+  pub fn entity_by_stable_id(&self, id: StableId) -> Option<EntityId> {
+    self.stable_ids.get(&id).copied()
+  }
+
+  // The identifiers of the enclosing regions of a scope, outermost first.
+  fn scope_path(&self, scope: ScopeId) -> Vec<String> {
+    let mut segs = Vec::new();
+    let mut cur = scope;
+    while let ScopeId::Region(r) = cur {
+      let r = &self.regions[r.0];
+      segs.push(self.resolve(r.name.ident).to_string());
+      cur = r.parent;
+    }
+    segs.reverse();
+    segs
+  }
+
+  // The primary identifier of an entity, unless it is anonymous.
+  fn name_of(&self, e: EntityId) -> Option<Ident> {
+    Some(match e {
+      EntityId::Region(r) => self.regions[r.0].name.ident,
+      EntityId::Item(i) => self.items[i.0].name.ident,
+      EntityId::Tag(t) => t,
+      EntityId::Location(l) => self.locations[l.0].name.ident,
+      EntityId::Link(l) => self.links[l.0].name.as_ref()?.ident,
+      EntityId::Function(f) => self.functions[f.0].name.ident,
+      EntityId::Enum(e) => self.enums[e.0].name.ident,
+      EntityId::Variant(v) => self.variants[v.0].name.ident,
+      EntityId::Config(c) => self.configs[c.0].name.ident,
+      EntityId::Configset(c) => self.configsets[c.0].name.ident,
+      EntityId::Random(r) => self.randoms[r.0].name.ident,
+      EntityId::Cond(_) => return None,
+    })
+  }
+
+  entity_iter!(regions, Region, "regions");
+  entity_iter!(items, Item, "items");
+  entity_iter!(locations, Location, "locations");
+  entity_iter!(links, Link, "links");
+  entity_iter!(functions, Function, "functions");
+  entity_iter!(enums, Enum, "enums");
+  entity_iter!(variants, Variant, "enum variants");
+  entity_iter!(configs, Config, "configs");
+  entity_iter!(configsets, Configset, "configsets");
+  entity_iter!(randoms, Random, "randoms");
+  entity_iter!(conds, Cond, "conditional blocks");
+
+  /// Iterate over all the tags, in the order they were first used.
+  pub fn tags(&self) -> impl Iterator<Item = Ident> + '_ {
+    self.global_decls.values().filter_map(|e| match e {
+      EntityId::Tag(t) => Some(*t),
+      _ => None,
+    })
+  }
+
+  /// Iterate over every entity in the program, except those which have been
+  /// deleted.
+  pub fn entities(&self) -> impl Iterator<Item = EntityId> + '_ {
+    std::iter::empty()
+      .chain(self.regions.ids().map(|id| EntityId::Region(Id(id))))
+      .chain(self.items.ids().map(|id| EntityId::Item(Id(id))))
+      .chain(self.tags().map(EntityId::Tag))
+      .chain(self.locations.ids().map(|id| EntityId::Location(Id(id))))
+      .chain(self.links.ids().map(|id| EntityId::Link(Id(id))))
+      .chain(self.functions.ids().map(|id| EntityId::Function(Id(id))))
+      .chain(self.enums.ids().map(|id| EntityId::Enum(Id(id))))
+      .chain(self.variants.ids().map(|id| EntityId::Variant(Id(id))))
+      .chain(self.configs.ids().map(|id| EntityId::Config(Id(id))))
+      .chain(self.configsets.ids().map(|id| EntityId::Configset(Id(id))))
+      .chain(self.randoms.ids().map(|id| EntityId::Random(Id(id))))
+      .chain(self.conds.ids().map(|id| EntityId::Cond(Id(id))))
+      .filter(move |e| !self.deleted.contains(e))
+  }
+
+  // The entities directly inside each region, apart from deleted ones, in the
+  // order of entities(). Anonymous links and conditional blocks are not in the
+  // scopes of their regions, so this is the way to find everything in one.
+  pub(crate) fn region_contents(&self) -> HashMap<Id, Vec<EntityId>> {
+    let mut contents: HashMap<Id, Vec<EntityId>> = HashMap::new();
+    for e in self.entities() {
+      let parent = self.get_entity(e).expect("entity of this program").parent();
+      if let ScopeId::Region(r) = parent {
+        contents.entry(r).or_default().push(e);
+      }
+    }
+    contents
+  }
+
+  /// Whether an entity has been deleted by an override. A deleted entity is
+  /// left in place so that its names stay bound, but the program is otherwise
+  /// as if it had never been declared.
+  pub fn is_deleted(&self, e: EntityId) -> bool {
+    self.deleted.contains(&e)
+  }
+
+  /// The sets of conditional tags declared mutually exclusive by exclusive
+  /// statements.
+  pub fn exclusive_sets(&self) -> &[Vec<CondTag>] {
+    &self.exclusive
+  }
+
+  /// Whether two conditional tags can never both be active, either because
+  /// one is the negation of the other or because an exclusive statement lists
+  /// them both.
+  pub fn mutually_exclusive(&self, a: CondTag, b: CondTag) -> bool {
+    (a.tag == b.tag && a.negated != b.negated)
+      || (a != b
+        && self
+          .exclusive
+          .iter()
+          .any(|x| x.contains(&a) && x.contains(&b)))
+  }
+
+  // A conditional tag as it is written, as in "not FlyingEnemies".
+  fn cond_tag_str(&self, t: CondTag) -> String {
+    let name = self.resolve(t.tag);
+    if t.negated {
+      format!("not {}", name)
+    } else {
+      name.to_string()
+    }
+  }
+
+  /// Walk the scope tree depth-first from the global scope, visiting every
+  /// entity once, including anonymous ones and regardless of aliases. Each
+  /// region is followed directly by its contents. Within a scope, entities are
+  /// ordered by kind and then by declaration.
+  pub fn walk(&self) -> impl Iterator<Item = EntityId> {
+    let mut children = HashMap::<ScopeId, Vec<EntityId>>::new();
+    for e in self.entities() {
+      let parent = self.get_entity(e).unwrap().parent();
+      children.entry(parent).or_default().push(e);
+    }
+    // The entities still to be visited, with the next one on top.
+    let mut stack = children.remove(&ScopeId::Global).unwrap_or_default();
+    stack.reverse();
+    let mut order = Vec::new();
+    while let Some(e) = stack.pop() {
+      order.push(e);
+      if let EntityId::Region(r) = e {
+        if let Some(contents) = children.remove(&ScopeId::Region(r)) {
+          stack.extend(contents.into_iter().rev());
+        }
+      }
+    }
+    order.into_iter()
+  }
+
+  /// The warnings found while compiling the program.
+  pub fn warnings(&self) -> &[CompileWarning] {
+    &self.warnings
+  }
+
+  /// The aliases of an entity, in the order they were declared.
+  pub fn aliases_of(&self, e: EntityId) -> &[Ident] {
+    self.aliases.get(&e).map_or(&[], |a| &a[..])
+  }
+
+  /// The entities with a tag, in the order they were declared.
+  ///
+  /// The language lets any declaration be tagged with a `#[...]` prefix, as
+  /// long as all the entities sharing a tag have the same kind and type. Only
+  /// the `tag` statements of items and the tags of `items` declarations are
+  /// supported so far, and tagging anything else is an error, so that rule
+  /// holds without being checked. The prefix is only parsed on conditional
+  /// blocks, whose tags are a separate namespace.
+  pub fn entities_with_tag(&self, tag: Ident) -> impl Iterator<Item = EntityId> + '_ {
+    self
+      .items
+      .iter()
+      .filter(move |(_, i)| i.tags.contains(&tag))
+      .map(|(id, _)| EntityId::Item(Id(id)))
+      .filter(move |e| !self.deleted.contains(e))
+  }
+
+  /// The properties of the global scope, which every region inherits.
+  pub fn global_props(&self) -> &RegionProps {
+    &self.props
+  }
+
+  /// The requirement to reach an entity. Requirements are inherited, so this
+  /// is the conjunction of the entity's own requirement, if it is a region,
+  /// location or link, with those of every region enclosing it and of the
+  /// global scope, outermost first. None if nothing is required.
+  pub fn effective_requirement(&self, e: EntityId) -> Option<Expr> {
+    let own = match e {
+      EntityId::Region(r) => self.regions[r.0].props.requires.as_ref(),
+      EntityId::Location(l) => self.locations[l.0].requires.as_ref(),
+      EntityId::Link(l) => self.links[l.0].requires.as_ref(),
+      _ => None,
+    };
+    let mut conds: Vec<_> = self
+      .enclosing_props(e)
+      .filter_map(|p| p.requires.clone())
+      .collect();
+    conds.reverse();
+    conds.extend(own.cloned());
+    match conds.len() {
+      0 => None,
+      1 => conds.pop(),
+      _ => Some(Expr::And(conds)),
+    }
+  }
+
+  /// The condition for a location to be visible, or for the locations in a
+  /// region to be. A location or region which does not set its own visibility
+  /// takes that of the nearest enclosing region which does, or else of the
+  /// global scope. None if nothing sets it.
+  pub fn effective_visibility(&self, e: EntityId) -> Option<&Expr> {
+    let own = match e {
+      EntityId::Region(r) => self.regions[r.0].props.visible.as_ref(),
+      EntityId::Location(l) => self.locations[l.0].visible.as_ref(),
+      _ => None,
+    };
+    own.or_else(|| self.enclosing_props(e).find_map(|p| p.visible.as_ref()))
+  }
+
+  /// The items available for pickup in a region. Being in a region means
+  /// being in every region enclosing it, so these include the items available
+  /// in those regions and in the global scope, outermost first.
+  pub fn effective_avail(&self, r: Id) -> Vec<&Avail> {
+    let region = EntityId::Region(r);
+    let mut props: Vec<_> = self.enclosing_props(region).collect();
+    props.reverse();
+    props.push(&self.regions[r.0].props);
+    props.iter().flat_map(|p| &p.avail).collect()
+  }
+
+  /// The graph of how the regions connect through links. Everything about
+  /// reaching a region is expressed by its edges.
+  pub fn region_graph(&self) -> RegionGraph {
+    RegionGraph::new(self)
+  }
+
+  // The properties of the regions enclosing an entity, innermost first, and
+  // then of the global scope.
+  fn enclosing_props(&self, e: EntityId) -> impl Iterator<Item = &RegionProps> + '_ {
+    let mut scope = Some(self.get_entity(e).unwrap().parent());
+    std::iter::from_fn(move || {
+      let s = scope?;
+      scope = self.get_scope(s).unwrap().parent();
+      Some(match s {
+        ScopeId::Global => &self.props,
+        ScopeId::Region(r) => &self.regions[r.0].props,
+      })
+    })
   }
 
   /// Lookup a single identifier in a scope. Lookup proceeds by traversing
@@ -179,36 +887,137 @@ impl Program {
   /// Lookup an entity by full path. Lookup is done by looking up the first
   /// identifier in the scope with lookup_ident, then each successive
   /// identifier in the path is looked up in the scope found previously.
-  pub fn lookup_entity(&self, scope: &dyn Scope, path: &Path) -> Result<EntityId, Error> {
+  pub fn lookup_entity(&self, scope: &dyn Scope, path: &Path) -> Result<EntityId, CompileError> {
     let mut segs = path.0.iter();
-    let mut cur = self
-      .lookup(scope, *segs.next().unwrap())
-      .ok_or_else(|| anyhow!("first identifier in path not found in lookup"))?;
-    for next in segs {
-      let child: &dyn Scope;
-      match cur {
-        EntityId::Region(r) => child = self.regions.get(r.0).unwrap(),
-        _ => return Err(anyhow!("tried to lookup entity in non-scope")),
-      }
+    let mut cur =
+      self
+        .lookup(scope, *segs.next().unwrap())
+        .ok_or_else(|| CompileError::NotFound {
+          path: self.path_str(&path.0[..1]),
+          span: None,
+        })?;
+    for (i, next) in segs.enumerate() {
+      let child: &dyn Scope = match cur {
+        EntityId::Region(r) => self.regions.get(r.0).unwrap(),
+        _ => {
+          return Err(CompileError::NotAScope {
+            path: self.path_str(&path.0[..i + 1]),
+            entity: cur,
+            span: None,
+          })
+        }
+      };
       cur = child
         .lookup_ident(*next)
-        .ok_or_else(|| anyhow!("next segment not found in child scope"))?;
+        .ok_or_else(|| CompileError::NotFound {
+          path: self.path_str(&path.0[..i + 2]),
+          span: None,
+        })?;
     }
     Ok(cur)
   }
 
-  /// Find the entity with the provided id.
-  pub fn get_entity(&self, e: EntityId) -> Option<MixedRef<dyn Entity>> {
+  // Format identifiers as a dotted path, for messages.
+  fn path_str(&self, idents: &[Ident]) -> String {
+    idents
+      .iter()
+      .map(|i| self.idents.resolve(i.0).unwrap_or("<unknown>"))
+      .collect::<Vec<_>>()
+      .join(".")
+  }
+
+  // The span of a scope's name, for messages.
+  fn scope_span(&self, scope: ScopeId) -> Option<Span> {
+    match scope {
+      ScopeId::Global => None,
+      ScopeId::Region(r) => self.regions[r.0].name.span,
+    }
+  }
+
+  // The span of an entity's name, for messages.
+  fn get_entity_name_span(&self, e: EntityId) -> Option<Span> {
+    match e {
+      EntityId::Region(r) => self.regions[r.0].name.span,
+      EntityId::Item(i) => self.items[i.0].name.span,
+      EntityId::Location(l) => self.locations[l.0].name.span,
+      EntityId::Link(l) => self.links[l.0].name.as_ref().and_then(|n| n.span),
+      EntityId::Function(f) => self.functions[f.0].name.span,
+      EntityId::Enum(e) => self.enums[e.0].name.span,
+      EntityId::Variant(v) => self.variants[v.0].name.span,
+      EntityId::Config(c) => self.configs[c.0].name.span,
+      EntityId::Configset(c) => self.configsets[c.0].name.span,
+      EntityId::Random(r) => self.randoms[r.0].name.span,
+      EntityId::Tag(_) | EntityId::Cond(_) => None,
+    }
+  }
+
+  /// A human-readable name for an entity. This is its human name if one was
+  /// declared, and otherwise is derived from its identifier by human_name.
+  /// Anonymous links are described by their direction and the regions they
+  /// connect.
+  pub fn display_name(&self, e: EntityId) -> String {
+    let name = match e {
+      EntityId::Region(r) => &self.regions[r.0].name,
+      EntityId::Item(i) => &self.items[i.0].name,
+      EntityId::Tag(t) => return human_name(self.resolve(t)),
+      EntityId::Location(l) => &self.locations[l.0].name,
+      EntityId::Link(l) => match &self.links[l.0].name {
+        Some(n) => n,
+        None => return self.describe_link(&self.links[l.0]),
+      },
+      EntityId::Function(f) => &self.functions[f.0].name,
+      EntityId::Enum(e) => &self.enums[e.0].name,
+      EntityId::Variant(v) => &self.variants[v.0].name,
+      EntityId::Config(c) => &self.configs[c.0].name,
+      EntityId::Configset(c) => &self.configsets[c.0].name,
+      EntityId::Random(r) => &self.randoms[r.0].name,
+      EntityId::Cond(_) => return "Conditional block".into(),
+    };
+    match &name.human {
+      Some(h) => h.clone(),
+      None => human_name(self.resolve(name.ident)),
+    }
+  }
+
+  fn describe_link(&self, l: &Link) -> String {
+    let from = match l.parent {
+      ScopeId::Global => "Global".into(),
+      ScopeId::Region(r) => self.display_name(EntityId::Region(r)),
+    };
+    let dir = l.dir.keyword();
+    // The regions need not exist, so they are named from their paths.
+    let to = l
+      .regions
+      .iter()
+      .map(|p| human_name(self.resolve(*p.0.last().unwrap())))
+      .collect::<Vec<_>>()
+      .join(", ");
+    format!("{} {} {}", from, dir, to)
+  }
+
+  /// Find the entity with the provided id, if there is one. A tag id only
+  /// names an entity if its identifier has been declared as a tag.
+  pub fn get_entity(&self, e: EntityId) -> Option<MixedRef<'_, dyn Entity>> {
     match e {
-      EntityId::Region(r) => self
-        .regions
-        .get(r.0)
-        .map(|e| MixedRef::Borrowed(e as &dyn Entity)),
-      EntityId::Item(i) => self
-        .items
-        .get(i.0)
-        .map(|e| MixedRef::Borrowed(e as &dyn Entity)),
-      EntityId::Tag(t) => Some(MixedRef::Owned(Box::new(Tag(t)))),
+      EntityId::Region(r) => borrow_entity!(self.regions, r),
+      EntityId::Item(i) => borrow_entity!(self.items, i),
+      EntityId::Tag(t) if self.global_decls.get(&t) != Some(&e) => None,
+      EntityId::Tag(t) => Some(MixedRef::Owned(Box::new(Tag {
+        name: Name {
+          ident: t,
+          human: None,
+          span: None,
+        },
+      }))),
+      EntityId::Location(l) => borrow_entity!(self.locations, l),
+      EntityId::Link(l) => borrow_entity!(self.links, l),
+      EntityId::Function(f) => borrow_entity!(self.functions, f),
+      EntityId::Enum(e) => borrow_entity!(self.enums, e),
+      EntityId::Variant(v) => borrow_entity!(self.variants, v),
+      EntityId::Config(c) => borrow_entity!(self.configs, c),
+      EntityId::Configset(c) => borrow_entity!(self.configsets, c),
+      EntityId::Random(r) => borrow_entity!(self.randoms, r),
+      EntityId::Cond(c) => borrow_entity!(self.conds, c),
     }
   }
 
@@ -220,8 +1029,9 @@ impl Program {
     }
   }
 
-  /// Find the scope with the provided id, and return a mutable reference.
-  pub fn get_scope_mut(&mut self, s: ScopeId) -> Option<&mut dyn Scope> {
+  // Find the scope with the provided id, and return a mutable reference. This
+  // is only for loading, since a compiled program is never modified.
+  fn get_scope_mut(&mut self, s: ScopeId) -> Option<&mut dyn Scope> {
     match s {
       ScopeId::Global => Some(self),
       ScopeId::Region(r) => self.regions.get_mut(r.0).map(|e| e as &mut dyn Scope),
@@ -230,15 +1040,22 @@ impl Program {
 }
 
 /// A Rado region.
+#[derive(Clone)]
 pub struct Region {
   parent: ScopeId,
   name: Name,
-  children: HashMap<Ident, EntityId>,
+  children: IndexMap<Ident, EntityId>,
+  props: RegionProps,
 }
 
-impl Entity for Region {
-  fn parent(&self) -> ScopeId {
-    self.parent
+impl Region {
+  /// The region's name.
+  pub fn name(&self) -> &Name {
+    &self.name
+  }
+  /// The region's properties.
+  pub fn props(&self) -> &RegionProps {
+    &self.props
   }
 }
 
@@ -249,251 +1066,4375 @@ impl Scope for Region {
   fn lookup_ident(&self, i: Ident) -> Option<EntityId> {
     self.children.get(&i).copied()
   }
-  fn insert_child(&mut self, i: Ident, e: EntityId, _: PrivateHack) {
-    if self.children.insert(i, e).is_some() {
-      panic!("overwrote existing entity when inserting new one");
-    }
+  fn insert_child(&mut self, i: Ident, e: EntityId, _: PrivateHack) -> Result<(), EntityId> {
+    insert_new(&mut self.children, i, e)
   }
   fn children<'a>(&'a self) -> Box<dyn Iterator<Item = (&'a Ident, &'a EntityId)> + 'a> {
     Box::new(self.children.iter())
   }
 }
 
+/// The properties of a region. The global scope has these as well.
+#[derive(Clone, Default)]
+pub struct RegionProps {
+  requires: Option<Expr>,
+  visible: Option<Expr>,
+  unlock: Option<Path>,
+  avail: Vec<Avail>,
+  grants: Vec<Grant>,
+  start_with: Vec<Path>,
+}
+
+impl RegionProps {
+  /// The requirement to enter the region, if any.
+  pub fn requires(&self) -> Option<&Expr> {
+    self.requires.as_ref()
+  }
+  /// The visibility of the locations in the region which do not set their
+  /// own, if set.
+  pub fn visible(&self) -> Option<&Expr> {
+    self.visible.as_ref()
+  }
+  /// The item which unlocks the region, if any.
+  pub fn unlock(&self) -> Option<&Path> {
+    self.unlock.as_ref()
+  }
+  /// The items available for pickup in the region.
+  pub fn avail(&self) -> &[Avail] {
+    &self.avail
+  }
+  /// The items granted on entering the region.
+  pub fn grants(&self) -> &[Grant] {
+    &self.grants
+  }
+  /// The items the player starts with.
+  pub fn start_with(&self) -> &[Path] {
+    &self.start_with
+  }
+}
+
+/// An item made available for pickup in a region.
+#[derive(Clone, Debug)]
+pub struct Avail {
+  pub item: Path,
+  /// If set, the item can be discarded rather than acquired.
+  pub negated: bool,
+  /// How many of the item are available, or None if there are infinitely many.
+  pub count: Option<ast::Num>,
+}
+
+/// An item granted by entering a region or travelling along a link.
+#[derive(Clone, Debug)]
+pub struct Grant {
+  pub item: Path,
+  /// If set, the item is removed rather than granted.
+  pub negated: bool,
+}
+
 /// A item to be randomized.
+#[derive(Clone)]
 pub struct Item {
   parent: ScopeId,
   name: Name,
-  tags: HashSet<Ident>,
+  tags: IndexSet<Ident>,
+  provides: Vec<Path>,
+  progressive: Option<Vec<Path>>,
+  vals: IndexMap<Ident, Val>,
+  max: Option<Expr>,
+  consumable: bool,
 }
 
-impl Entity for Item {
-  fn parent(&self) -> ScopeId {
-    self.parent
+impl Item {
+  /// The item's name.
+  pub fn name(&self) -> &Name {
+    &self.name
+  }
+  /// All of the item's tags, including those given by enclosing `items`
+  /// declarations.
+  pub fn tags(&self) -> &IndexSet<Ident> {
+    &self.tags
+  }
+  /// The items which this item also counts as.
+  pub fn provides(&self) -> &[Path] {
+    &self.provides
+  }
+  /// The sequence of items this item grants in turn, if it is progressive.
+  pub fn progressive(&self) -> Option<&[Path]> {
+    self.progressive.as_deref()
+  }
+  /// The item's values, by name.
+  pub fn vals(&self) -> &IndexMap<Ident, Val> {
+    &self.vals
+  }
+  /// The maximum number of this item, if any.
+  pub fn max(&self) -> Option<&Expr> {
+    self.max.as_ref()
+  }
+  /// Whether the item can be used up.
+  pub fn consumable(&self) -> bool {
+    self.consumable
+  }
+}
+
+/// A value attached to an item. Values have their own namespace.
+#[derive(Clone)]
+pub struct Val {
+  // Filled in by the type checker if not declared.
+  ty: Option<Ty>,
+  val: Expr,
+}
+
+impl Val {
+  /// The value's type, either declared or inferred.
+  pub fn ty(&self) -> &Ty {
+    self.ty.as_ref().unwrap()
+  }
+  /// The value's expression.
+  pub fn val(&self) -> &Expr {
+    &self.val
   }
 }
 
 /// A tag is just an identifier. This struct exists only to give an
 /// implementation of Entity for tags.
-pub struct Tag(pub Ident);
+pub struct Tag {
+  name: Name,
+}
 
 impl Entity for Tag {
   fn parent(&self) -> ScopeId {
     ScopeId::Global
   }
+  fn kind(&self) -> EntityKind {
+    EntityKind::Tag
+  }
+  fn name(&self) -> Option<&Name> {
+    Some(&self.name)
+  }
+  fn as_tag(&self) -> Option<&Tag> {
+    Some(self)
+  }
 }
 
-// Wrapper struct to organize all the code that loads an AST into one place and
-// avoid polluting the Program method namespace.
-struct FromAST(Program);
-
-impl std::ops::Deref for FromAST {
-  type Target = Program;
-  fn deref(&self) -> &Program {
-    &self.0
-  }
+/// A place where an item can be found.
+#[derive(Clone)]
+pub struct Location {
+  parent: ScopeId,
+  name: Name,
+  requires: Option<Expr>,
+  visible: Option<Expr>,
 }
 
-impl std::ops::DerefMut for FromAST {
-  fn deref_mut(&mut self) -> &mut Program {
-    &mut self.0
+impl Location {
+  /// The location's name.
+  pub fn name(&self) -> &Name {
+    &self.name
+  }
+  /// The requirement to reach the location, if any.
+  pub fn requires(&self) -> Option<&Expr> {
+    self.requires.as_ref()
+  }
+  /// The condition for the location's contents to be visible, if any.
+  pub fn visible(&self) -> Option<&Expr> {
+    self.visible.as_ref()
   }
 }
 
-impl FromAST {
-  fn build(mut self, f: ast::File) -> Result<Program, Error> {
-    self.populate_scope(ScopeId::Global, &f.stmts)?;
-    self.build_scope(ScopeId::Global, f.stmts)?;
-    Ok(self.0)
-  }
+/// The direction of a link, relative to the region it is declared in.
+#[derive(Copy, Clone, Hash, PartialEq, Eq, Debug)]
+pub enum LinkDir {
+  To,
+  From,
+  With,
+}
 
-  fn populate_scope(&mut self, scope: ScopeId, stmts: &[ast::Stmt]) -> Result<(), Error> {
-    // First pass: load all the entities, so that name lookup becomes
-    // possible. Tags are the only properties loaded.
-    for s in stmts {
-      match s {
-        Stmt::Decl(Decl::Region(r)) => self.add_region(scope, &r)?,
-        Stmt::Decl(Decl::Item(i)) => self.add_item(scope, &i)?,
-        Stmt::Decl(Decl::Items(i)) => self.add_items(scope, &i)?,
-        _ => unimplemented!(),
-      }
+impl LinkDir {
+  // The keyword for the direction, as written after `link`.
+  fn keyword(self) -> &'static str {
+    match self {
+      LinkDir::To => "to",
+      LinkDir::From => "from",
+      LinkDir::With => "with",
     }
-    Ok(())
   }
+}
 
-  fn add_region(&mut self, parent: ScopeId, region: &ast::Region) -> Result<(), Error> {
-    let n = self.add_name(&region.name);
-    self.validate_name_collisions(parent, n.ident)?;
-
-    let r = Region {
-      parent,
-      name: n,
-      children: HashMap::new(),
-    };
-    let n = r.name.ident;
-    let id = self.regions.insert(r);
-    self
-      .get_scope_mut(parent)
-      .unwrap()
-      .insert_child(n, EntityId::Region(Id(id)), PrivateHack(()));
+/// A connection between regions. Links need not be named.
+#[derive(Clone)]
+pub struct Link {
+  parent: ScopeId,
+  name: Option<Name>,
+  dir: LinkDir,
+  regions: Vec<Path>,
+  targets: Vec<Id>,
+  requires: Option<Expr>,
+  unlock: Option<Path>,
+  grants: Vec<Grant>,
+}
 
-    self.populate_scope(ScopeId::Region(Id(id)), &region.stmts)?;
-    Ok(())
+impl Link {
+  /// The link's name, if it has one.
+  pub fn name(&self) -> Option<&Name> {
+    self.name.as_ref()
+  }
+  /// The link's direction.
+  pub fn dir(&self) -> LinkDir {
+    self.dir
   }
+  /// The regions at the other end of the link.
+  pub fn regions(&self) -> &[Path] {
+    &self.regions
+  }
+  /// The regions at the other end of the link, as found from the scope it is
+  /// declared in. Those which could not be found are left out.
+  pub fn targets(&self) -> &[Id] {
+    &self.targets
+  }
+  /// The requirement to travel the link, if any.
+  pub fn requires(&self) -> Option<&Expr> {
+    self.requires.as_ref()
+  }
+  /// The item which unlocks the link, if any.
+  pub fn unlock(&self) -> Option<&Path> {
+    self.unlock.as_ref()
+  }
+  /// The items granted on travelling the link.
+  pub fn grants(&self) -> &[Grant] {
+    &self.grants
+  }
+}
 
-  fn add_item(&mut self, parent: ScopeId, item: &ast::Item) -> Result<(), Error> {
-    let n = self.add_name(&item.name);
-    self.validate_name_collisions(parent, n.ident)?;
+/// A function which can be used in expressions.
+#[derive(Clone)]
+pub struct Function {
+  parent: ScopeId,
+  name: Name,
+  params: Vec<Param>,
+  // Types which are not declared are filled in by the type checker, and the
+  // body is missing only if its names could not be resolved, so both are
+  // always present in a compiled program.
+  ret_ty: Option<Ty>,
+  body: Option<Expr>,
+}
 
-    // We put properties off until the second pass ordinarily, except that
-    // tags actually declare the tag names, so we process them now.
-    let i = Item {
-      parent,
-      name: n,
-      tags: HashSet::new(),
-    };
-    for s in &item.stmts {
-      if let Stmt::Prop(Prop::Tag(t)) = s {
-        self.add_tag_vec(&t.tags)?;
-      }
-    }
-    let n = i.name.ident;
-    let id = self.items.insert(i);
-    self
-      .get_scope_mut(parent)
-      .unwrap()
-      .insert_child(n, EntityId::Item(Id(id)), PrivateHack(()));
-    Ok(())
+impl Function {
+  /// The function's name.
+  pub fn name(&self) -> &Name {
+    &self.name
   }
-  fn add_items(&mut self, parent: ScopeId, items: &ast::Items) -> Result<(), Error> {
-    self.add_tag_vec(&items.tags)?;
-    for i in &items.items {
-      self.add_item(parent, &i)?;
-    }
-    for i in &items.nested {
-      self.add_items(parent, &i)?;
-    }
-    Ok(())
+  /// The function's parameters.
+  pub fn params(&self) -> &[Param] {
+    &self.params
   }
+  /// The return type, either declared or inferred.
+  pub fn ret_ty(&self) -> &Ty {
+    self.ret_ty.as_ref().unwrap()
+  }
+  /// The function's body.
+  pub fn body(&self) -> &Expr {
+    self.body.as_ref().unwrap()
+  }
+}
+
+/// A parameter of a function.
+#[derive(Clone)]
+pub struct Param {
+  name: Ident,
+  // Filled in by the type checker if not declared.
+  ty: Option<Ty>,
+}
+
+impl Param {
+  /// The parameter's name.
+  pub fn name(&self) -> Ident {
+    self.name
+  }
+  /// The parameter's type, either declared or inferred.
+  pub fn ty(&self) -> &Ty {
+    self.ty.as_ref().unwrap()
+  }
+}
+
+/// An enumeration type.
+#[derive(Clone)]
+pub struct Enum {
+  parent: ScopeId,
+  name: Name,
+  variants: Vec<Id>,
+}
+
+impl Enum {
+  /// The enum's name.
+  pub fn name(&self) -> &Name {
+    &self.name
+  }
+  /// The enum's variants, in declaration order.
+  pub fn variants(&self) -> &[Id] {
+    &self.variants
+  }
+}
+
+/// A single value of an enumeration. Variants are declared in the same scope as
+/// their enum.
+#[derive(Clone)]
+pub struct Variant {
+  parent: ScopeId,
+  name: Name,
+  enum_id: Id,
+}
+
+impl Variant {
+  /// The variant's name.
+  pub fn name(&self) -> &Name {
+    &self.name
+  }
+  /// The enum this is a variant of.
+  pub fn enum_id(&self) -> Id {
+    self.enum_id
+  }
+}
+
+/// A configuration option.
+#[derive(Clone)]
+pub struct Config {
+  parent: ScopeId,
+  name: Name,
+  ty: ConfigTy,
+  default: Option<Expr>,
+  disallowed: Vec<Id>,
+}
+
+impl Config {
+  /// The config's name.
+  pub fn name(&self) -> &Name {
+    &self.name
+  }
+  /// The config's type.
+  pub fn ty(&self) -> &ConfigTy {
+    &self.ty
+  }
+  /// The config's default value, if any.
+  pub fn default(&self) -> Option<&Expr> {
+    self.default.as_ref()
+  }
+  /// The variants the config may not take, in the order they were disallowed.
+  /// Only configs of enum types can have any.
+  pub fn disallowed(&self) -> &[Id] {
+    &self.disallowed
+  }
+}
+
+/// The type of a config.
+#[derive(Clone)]
+pub enum ConfigTy {
+  /// A type written out in the declaration.
+  Declared(Ty),
+  /// The enum declared along with the config by a config-enum declaration.
+  /// That enum shares the config's name and so is not declared in any scope.
+  Enum(Id),
+}
+
+/// A named set of config values.
+#[derive(Clone)]
+pub struct Configset {
+  parent: ScopeId,
+  name: Name,
+  vals: Vec<(Id, Expr)>,
+  configsets: Vec<Id>,
+  // Filled in once the program has been checked.
+  expansion: Vec<(Id, Value)>,
+}
+
+impl Configset {
+  /// The configset's name.
+  pub fn name(&self) -> &Name {
+    &self.name
+  }
+  /// The config values set directly by the configset, by config.
+  pub fn vals(&self) -> &[(Id, Expr)] {
+    &self.vals
+  }
+  /// The other configsets included in this one.
+  pub fn configsets(&self) -> &[Id] {
+    &self.configsets
+  }
+  /// All the config values the configset sets, including those from the
+  /// configsets it includes, directly or not.
+  pub fn expansion(&self) -> &[(Id, Value)] {
+    &self.expansion
+  }
+}
+
+/// A randomized parameter which is not an item or location.
+#[derive(Clone)]
+pub struct Random {
+  parent: ScopeId,
+  name: Name,
+  vals: Vec<Expr>,
+}
+
+impl Random {
+  /// The random's name.
+  pub fn name(&self) -> &Name {
+    &self.name
+  }
+  /// The possible values.
+  pub fn vals(&self) -> &[Expr] {
+    &self.vals
+  }
+}
+
+/// A tag on a conditional block, standing for an assumption which the blocks
+/// with it share.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct CondTag {
+  pub tag: Ident,
+  /// If set, the block makes the opposite assumption.
+  pub negated: bool,
+}
+
+/// A conditional block. Its contents only take effect once the program is
+/// configured and the condition is known, so they are kept unprocessed.
+#[derive(Clone)]
+pub struct Cond {
+  parent: ScopeId,
+  cond: Expr,
+  then: Vec<ast::Stmt>,
+  otherwise: Vec<ast::Stmt>,
+  tags: Vec<CondTag>,
+  // The tags of the block enclosing this one, if any.
+  inherited: Vec<CondTag>,
+  // How many conditional blocks enclose this one, which decides which of two
+  // conflicting overrides supersedes the other.
+  depth: usize,
+}
+
+impl Cond {
+  /// The condition governing the block.
+  pub fn cond(&self) -> &Expr {
+    &self.cond
+  }
+  /// The statements which take effect if the condition is true.
+  pub fn then(&self) -> &[ast::Stmt] {
+    &self.then
+  }
+  /// The statements which take effect if the condition is false.
+  pub fn otherwise(&self) -> &[ast::Stmt] {
+    &self.otherwise
+  }
+  /// The tags given to the block. The else block has each of them negated.
+  pub fn tags(&self) -> &[CondTag] {
+    &self.tags
+  }
+  /// The tags of the main block, or of the else block, including those
+  /// inherited from the blocks enclosing it.
+  pub fn block_tags(&self, then: bool) -> Vec<CondTag> {
+    let own = self.tags.iter().map(|t| CondTag {
+      tag: t.tag,
+      negated: t.negated == then,
+    });
+    let mut tags = self.inherited.clone();
+    for t in own {
+      if !tags.contains(&t) {
+        tags.push(t);
+      }
+    }
+    tags
+  }
+}
+
+/// A resolved expression. This mirrors ast::Expr, except that every name has
+/// been looked up, so that it refers directly to the entity or function
+/// parameter it names.
+#[derive(Clone, Debug)]
+pub enum Expr {
+  Num(ast::Num),
+  Bool(bool),
+  List(Vec<Expr>),
+  /// A declared entity, such as an item, tag, config, function or enum
+  /// variant.
+  Entity(EntityId),
+  /// A parameter of the enclosing function, by position.
+  Param(usize),
+  /// A value of every item with a tag, written `Tag.Val`. Items with the tag
+  /// but without the value are left out.
+  TagVals(Ident, Ident),
+  Call(Box<Expr>, Vec<Expr>),
+  Builtin(ast::Builtin, Vec<Expr>),
+  Not(Box<Expr>),
+  Bin(Box<Expr>, ast::BinOp, Box<Expr>),
+  And(Vec<Expr>),
+  Or(Vec<Expr>),
+  If(Box<Expr>, Box<Expr>, Box<Expr>),
+  Match(Box<Expr>, Vec<MatchArm>),
+}
+
+// Collect the entities an expression refers to, not counting the variants
+// which select match arms.
+fn entity_refs(e: &Expr, refs: &mut Vec<EntityId>) {
+  match e {
+    Expr::Entity(id) => refs.push(*id),
+    Expr::Num(_) | Expr::Bool(_) | Expr::Param(_) | Expr::TagVals(..) => {}
+    Expr::List(es) | Expr::Builtin(_, es) | Expr::And(es) | Expr::Or(es) => {
+      es.iter().for_each(|e| entity_refs(e, refs))
+    }
+    Expr::Call(f, args) => {
+      entity_refs(f, refs);
+      args.iter().for_each(|e| entity_refs(e, refs));
+    }
+    Expr::Not(e) => entity_refs(e, refs),
+    Expr::Bin(l, _, r) => {
+      entity_refs(l, refs);
+      entity_refs(r, refs);
+    }
+    Expr::If(c, t, e) => {
+      entity_refs(c, refs);
+      entity_refs(t, refs);
+      entity_refs(e, refs);
+    }
+    Expr::Match(e, arms) => {
+      entity_refs(e, refs);
+      arms.iter().for_each(|a| entity_refs(&a.expr, refs));
+    }
+  }
+}
+
+/// An arm of a match expression, selected by an enum variant.
+#[derive(Clone, Debug)]
+pub struct MatchArm {
+  pub variant: Id,
+  pub expr: Expr,
+}
+
+/// A resolved type.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Ty {
+  /// Integers, which are a subset of num. There is no syntax for this type;
+  /// it is the type of integer literals and of arithmetic on them.
+  Int,
+  Num,
+  Bool,
+  Item,
+  Fn(Vec<Ty>, Box<Ty>),
+  List(Box<Ty>),
+  Enum(Id),
+}
+
+// Resolves the names in expressions and types appearing in a single scope,
+// collecting any errors. Resolution carries on past an unresolvable name so
+// that every one of them is reported, but the result is then discarded.
+struct Resolver<'a> {
+  program: &'a Program,
+  scope: ScopeId,
+  params: &'a [Param],
+  span: Option<Span>,
+  errors: Vec<CompileError>,
+}
+
+impl<'a> Resolver<'a> {
+  fn new(program: &'a Program, scope: ScopeId, span: Option<Span>) -> Resolver<'a> {
+    Resolver {
+      program,
+      scope,
+      params: &[],
+      span,
+      errors: Vec::new(),
+    }
+  }
+
+  fn lookup(&mut self, path: &ast::Path) -> Option<EntityId> {
+    let p = self.program;
+    // An identifier which was never interned cannot have been declared, so
+    // only the path up to it is looked up.
+    let idents = path
+      .iter()
+      .map_while(|i| p.idents.get(&i.0).map(Ident))
+      .collect::<Vec<_>>();
+    let n = idents.len();
+    let found = if n == 0 {
+      Err(CompileError::NotFound {
+        path: path_str(&path[..1]),
+        span: None,
+      })
+    } else {
+      p.lookup_entity(p.get_scope(self.scope).unwrap(), &Path(idents))
+    };
+    let found = found.and_then(|e| match e {
+      _ if n == path.len() => Ok(e),
+      EntityId::Region(_) => Err(CompileError::NotFound {
+        path: path_str(&path[..=n]),
+        span: None,
+      }),
+      _ => Err(CompileError::NotAScope {
+        path: path_str(&path[..n]),
+        entity: e,
+        span: None,
+      }),
+    });
+    match found {
+      Ok(e) => Some(e),
+      Err(e) => {
+        self.errors.push(e.with_span(self.span));
+        None
+      }
+    }
+  }
+
+  fn expr(&mut self, e: &ast::Expr) -> Option<Expr> {
+    use ast::Expr as E;
+    Some(match e {
+      E::Grouped(e) => return self.expr(e),
+      E::Num(n) => Expr::Num(n.clone()),
+      E::Bool(b) => Expr::Bool(*b),
+      E::List(es) => Expr::List(self.exprs(es)?),
+      E::Name(p) => {
+        if let [name] = &p[..] {
+          let sym = self.program.idents.get(&name.0);
+          let pos = self
+            .params
+            .iter()
+            .position(|param| Some(param.name.0) == sym);
+          if let Some(pos) = pos {
+            return Some(Expr::Param(pos));
+          }
+        }
+        if let [tag, val] = &p[..] {
+          if let Some(t) = self.tag(tag) {
+            return match self.program.idents.get(&val.0) {
+              Some(v) => Some(Expr::TagVals(t, Ident(v))),
+              None => {
+                self.errors.push(CompileError::NoSuchVal {
+                  tag: tag.0.clone(),
+                  val: val.0.clone(),
+                  span: self.span,
+                });
+                None
+              }
+            };
+          }
+        }
+        Expr::Entity(self.lookup(p)?)
+      }
+      E::Call(f, args) => {
+        let f = self.expr(f);
+        let args = self.exprs(args);
+        Expr::Call(Box::new(f?), args?)
+      }
+      E::Builtin(b, args) => Expr::Builtin(b.clone(), self.exprs(args)?),
+      E::Not(e) => Expr::Not(Box::new(self.expr(e)?)),
+      E::Bin(l, op, r) => {
+        let l = self.expr(l);
+        let r = self.expr(r);
+        Expr::Bin(Box::new(l?), op.clone(), Box::new(r?))
+      }
+      E::And(es) => Expr::And(self.exprs(es)?),
+      E::Or(es) => Expr::Or(self.exprs(es)?),
+      E::If(c, t, f) => {
+        let c = self.expr(c);
+        let t = self.expr(t);
+        let f = self.expr(f);
+        Expr::If(Box::new(c?), Box::new(t?), Box::new(f?))
+      }
+      E::Match(e, arms) => {
+        let e = self.expr(e);
+        let arms = arms
+          .iter()
+          .map(|arm| {
+            let variant = self.variant(&arm.pat);
+            let expr = self.expr(&arm.expr);
+            Some(MatchArm {
+              variant: variant?,
+              expr: expr?,
+            })
+          })
+          .collect::<Vec<_>>();
+        Expr::Match(Box::new(e?), arms.into_iter().collect::<Option<_>>()?)
+      }
+    })
+  }
+
+  // The tag an identifier names, if it names one.
+  fn tag(&self, ident: &ast::Ident) -> Option<Ident> {
+    let p = self.program;
+    let i = Ident(p.idents.get(&ident.0)?);
+    match p.lookup(p.get_scope(self.scope).unwrap(), i)? {
+      EntityId::Tag(t) => Some(t),
+      _ => None,
+    }
+  }
+
+  fn exprs(&mut self, es: &[ast::Expr]) -> Option<Vec<Expr>> {
+    // Resolve every expression before giving up, to report all the errors.
+    let es = es.iter().map(|e| self.expr(e)).collect::<Vec<_>>();
+    es.into_iter().collect()
+  }
+
+  fn variant(&mut self, path: &ast::Path) -> Option<Id> {
+    match self.lookup(path)? {
+      EntityId::Variant(v) => Some(v),
+      e => {
+        self.errors.push(CompileError::NotAVariant {
+          path: path_str(path),
+          entity: e,
+          span: self.span,
+        });
+        None
+      }
+    }
+  }
+
+  fn config(&mut self, path: &ast::Path) -> Option<Id> {
+    match self.lookup(path)? {
+      EntityId::Config(c) => Some(c),
+      e => {
+        self.errors.push(CompileError::NotAConfig {
+          path: path_str(path),
+          entity: e,
+          span: self.span,
+        });
+        None
+      }
+    }
+  }
+
+  fn configset(&mut self, path: &ast::Path) -> Option<Id> {
+    match self.lookup(path)? {
+      EntityId::Configset(c) => Some(c),
+      e => {
+        self.errors.push(CompileError::NotAConfigset {
+          path: path_str(path),
+          entity: e,
+          span: self.span,
+        });
+        None
+      }
+    }
+  }
+
+  fn ty(&mut self, ty: &ast::Ty) -> Option<Ty> {
+    Some(match ty {
+      ast::Ty::Num => Ty::Num,
+      ast::Ty::Bool => Ty::Bool,
+      ast::Ty::Item => Ty::Item,
+      ast::Ty::Fn(params, ret) => {
+        let params = params.iter().map(|t| self.ty(t)).collect::<Vec<_>>();
+        let ret = self.ty(ret);
+        Ty::Fn(params.into_iter().collect::<Option<_>>()?, Box::new(ret?))
+      }
+      ast::Ty::List(t) => Ty::List(Box::new(self.ty(t)?)),
+      ast::Ty::Name(p) => match self.lookup(p)? {
+        EntityId::Enum(e) => Ty::Enum(e),
+        // A config-enum's enum can only be named by the config's name.
+        EntityId::Config(c) => match self.program.configs[c.0].ty {
+          ConfigTy::Enum(e) => Ty::Enum(e),
+          ConfigTy::Declared(_) => return self.not_a_type(p, EntityId::Config(c)),
+        },
+        e => return self.not_a_type(p, e),
+      },
+    })
+  }
+
+  fn not_a_type(&mut self, path: &ast::Path, entity: EntityId) -> Option<Ty> {
+    self.errors.push(CompileError::NotAType {
+      path: path_str(path),
+      entity,
+      span: self.span,
+    });
+    None
+  }
+}
+
+// Format an AST path as written, for messages.
+fn path_str(path: &[ast::Ident]) -> String {
+  path.iter().map(|i| &*i.0).collect::<Vec<_>>().join(".")
+}
+
+impl_entity!(
+  Region => as_region,
+  Location => as_location,
+  Link => as_link,
+  Function => as_function,
+  Enum => as_enum,
+  Variant => as_variant,
+  Config => as_config,
+  Configset => as_configset,
+  Random => as_random
+);
+
+impl Entity for Item {
+  fn parent(&self) -> ScopeId {
+    self.parent
+  }
+  fn kind(&self) -> EntityKind {
+    EntityKind::Item
+  }
+  fn name(&self) -> Option<&Name> {
+    Some(&self.name)
+  }
+  fn tags<'a>(&'a self) -> Box<dyn Iterator<Item = Ident> + 'a> {
+    Box::new(self.tags.iter().copied())
+  }
+  fn as_item(&self) -> Option<&Item> {
+    Some(self)
+  }
+}
+
+impl Entity for Cond {
+  fn parent(&self) -> ScopeId {
+    self.parent
+  }
+  fn kind(&self) -> EntityKind {
+    EntityKind::Cond
+  }
+  fn name(&self) -> Option<&Name> {
+    None
+  }
+  fn as_cond(&self) -> Option<&Cond> {
+    Some(self)
+  }
+}
+
+// Unwrap a list which must not be a modifier list because it is not part of a
+// modifying declaration.
+fn new_list<T>(v: &ModVec<T>, span: Option<Span>) -> Result<&[T], CompileError> {
+  match v {
+    ModVec::New(v) => Ok(v),
+    ModVec::Mod(_) => Err(CompileError::UnexpectedModifierList { span }),
+  }
+}
+
+// Set a property which can only be given once per entity.
+fn set_once<T>(
+  slot: &mut Option<T>,
+  val: T,
+  keyword: &'static str,
+  span: Option<Span>,
+) -> Result<(), CompileError> {
+  if slot.is_some() {
+    return Err(CompileError::DuplicateProp { keyword, span });
+  }
+  *slot = Some(val);
+  Ok(())
+}
+
+// Wrapper struct to organize all the code that loads an AST into one place and
+// avoid polluting the Program method namespace.
+struct FromAST {
+  program: Program,
+  diags: Diagnostics,
+  // The entities declared by the first pass whose contents are filled in by
+  // the second, in the order that the second pass reaches them, with None for
+  // those which could not be declared. Looking them up by name instead would
+  // find the wrong entity when a name is declared twice, and links need not
+  // have a name at all.
+  declared: VecDeque<Option<EntityId>>,
+  // Whether the statements being compiled are from the taken branch of a
+  // conditional block, as when a program is configured.
+  conditional: bool,
+  // How many conditional blocks enclose the statements being compiled.
+  depth: usize,
+  // The tags of the block enclosing the statements being compiled.
+  cond_tags: Vec<CondTag>,
+  // The overrides applied to each entity so far, merged into one. Overrides
+  // in more deeply nested blocks are applied later and supersede the others.
+  overrides: HashMap<EntityId, Override>,
+  // The signatures of replaced functions as they were originally declared.
+  signatures: HashMap<Id, (Vec<Option<Ty>>, Option<Ty>)>,
+  // The conditional block whose branch is being compiled, if any.
+  block: Option<Id>,
+  // The conditional block whose branch declared each entity declared by one,
+  // which the entity is subject to.
+  declarers: HashMap<EntityId, Id>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum OverrideKind {
+  Modify,
+  Replace,
+  Delete,
+}
+
+impl OverrideKind {
+  fn action(self) -> &'static str {
+    match self {
+      OverrideKind::Modify => "modify",
+      OverrideKind::Replace => "replace",
+      OverrideKind::Delete => "delete",
+    }
+  }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Override {
+  kind: OverrideKind,
+  depth: usize,
+  // Whether a modification changes the properties of the entity itself,
+  // rather than only declaring or overriding things inside it.
+  props: bool,
+}
+
+impl std::ops::Deref for FromAST {
+  type Target = Program;
+  fn deref(&self) -> &Program {
+    &self.program
+  }
+}
+
+impl std::ops::DerefMut for FromAST {
+  fn deref_mut(&mut self) -> &mut Program {
+    &mut self.program
+  }
+}
+
+impl FromAST {
+  fn new() -> FromAST {
+    FromAST {
+      program: Program::default(),
+      diags: Diagnostics::default(),
+      declared: VecDeque::new(),
+      conditional: false,
+      depth: 0,
+      cond_tags: Vec::new(),
+      overrides: HashMap::new(),
+      signatures: HashMap::new(),
+      block: None,
+      declarers: HashMap::new(),
+    }
+  }
+
+  // Continue compiling a program with the branches of its conditional blocks.
+  fn configuring(program: Program) -> FromAST {
+    FromAST {
+      program,
+      conditional: true,
+      ..FromAST::new()
+    }
+  }
+
+  fn build(mut self, f: ast::File) -> Result<Program, Diagnostics> {
+    self.populate_scope(ScopeId::Global, &f.stmts);
+    self.build_scope(ScopeId::Global, f.stmts);
+    check::check(&mut self.program, &mut self.diags);
+    // Conditions and configsets are evaluated as constants, which is only
+    // safe once they are known to be well-typed.
+    if self.diags.errors.is_empty() {
+      self.check_conds(0);
+      config::expand_configsets(&mut self.program, &mut self.diags);
+    }
+    if self.diags.errors.is_empty() {
+      let loops = deps::check(&self.program, &self.declarers);
+      self.diags.errors.extend(loops);
+    }
+    if self.diags.errors.is_empty() {
+      self.program.fill_stable_ids();
+      self.program.warnings = self.diags.warnings;
+      Ok(self.program)
+    } else {
+      Err(self.diags)
+    }
+  }
+
+  // Record the error from a statement, if there was one, so that checking can
+  // carry on with the next.
+  fn report(&mut self, r: Result<(), CompileError>) {
+    if let Err(e) = r {
+      self.diags.errors.push(e);
+    }
+  }
+  fn warn(&mut self, w: CompileWarning) {
+    self.diags.warnings.push(w);
+  }
+
+  // Record the outcome of declaring an entity for the second pass.
+  fn push_declared(&mut self, r: Result<EntityId, CompileError>) -> Result<(), CompileError> {
+    self.declared.push_back(r.as_ref().ok().copied());
+    r.map(|_| ())
+  }
+  fn next_declared(&mut self) -> Option<EntityId> {
+    self.declared.pop_front().flatten()
+  }
+
+  // Check that the conditions of the conditional blocks from the given one
+  // onwards can be evaluated before the program is used, and that neither of
+  // their blocks has mutually exclusive tags.
+  fn check_conds(&mut self, from: usize) {
+    for id in from..self.conds.len() {
+      let c = &self.conds[id];
+      let span = self.scope_span(c.parent);
+      let mut errors = Vec::new();
+      if !config::is_constant(&self.program, &c.cond) {
+        errors.push(CompileError::NonConstantCondition { span });
+      }
+      // An else block which is absent has no tags to conflict.
+      for then in [true, false] {
+        if !then && c.otherwise.is_empty() {
+          continue;
+        }
+        let tags = c.block_tags(then);
+        for (i, &a) in tags.iter().enumerate() {
+          for &b in &tags[i + 1..] {
+            if self.mutually_exclusive(a, b) {
+              errors.push(CompileError::ExclusiveTags {
+                first: self.cond_tag_str(a),
+                second: self.cond_tag_str(b),
+                span,
+              });
+            }
+          }
+        }
+      }
+      self.diags.errors.extend(errors);
+    }
+  }
+
+  // Report references to deleted entities from the expressions of those which
+  // remain. Match arms may still name deleted variants, since they are not
+  // references to them.
+  fn check_deleted(&mut self) {
+    if self.deleted.is_empty() {
+      return;
+    }
+    let mut errors = Vec::new();
+    let scopes = std::iter::once(None).chain(self.entities().map(Some));
+    for e in scopes {
+      let exprs = match e {
+        None => self
+          .props
+          .requires
+          .iter()
+          .chain(&self.props.visible)
+          .collect(),
+        Some(e) => self.exprs_of(e),
+      };
+      let mut refs = Vec::new();
+      for x in exprs {
+        entity_refs(x, &mut refs);
+      }
+      if let Some(EntityId::Link(l)) = e {
+        refs.extend(self.links[l.0].targets.iter().map(|r| EntityId::Region(*r)));
+      }
+      let span = match e {
+        None => None,
+        Some(EntityId::Cond(c)) => self.scope_span(self.conds[c.0].parent),
+        Some(e) => self.get_entity_name_span(e),
+      };
+      let mut seen = HashSet::new();
+      for r in refs {
+        if self.deleted.contains(&r) && seen.insert(r) {
+          let path = self.path_of(r);
+          errors.push(CompileError::Deleted { path, span });
+        }
+      }
+    }
+    self.diags.errors.extend(errors);
+  }
+
+  // A replaced function must keep the signature of the original, since the
+  // rest of the program was checked against it.
+  fn check_signatures(&mut self) {
+    let mut errors = Vec::new();
+    for (id, (params, ret_ty)) in &self.signatures {
+      let f = &self.functions[id.0];
+      let same = f.params.len() == params.len()
+        && f.params.iter().zip(params).all(|(p, ty)| p.ty == *ty)
+        && f.ret_ty == *ret_ty;
+      if !same && !self.deleted.contains(&EntityId::Function(*id)) {
+        let path = self.path_of(EntityId::Function(*id));
+        errors.push(CompileError::SignatureChanged {
+          path,
+          span: f.name.span,
+        });
+      }
+    }
+    self.diags.errors.extend(errors);
+  }
+
+  fn populate_scope(&mut self, scope: ScopeId, stmts: &[ast::Stmt]) {
+    // First pass: declare all the entities, so that name lookup becomes
+    // possible. Their contents are filled in by the second pass, except for
+    // tags and enum variants, which are themselves declarations.
+    for s in stmts {
+      let r = match s {
+        Stmt::Decl(d) => {
+          let start = self.declared.len();
+          let r = self.add_decl(scope, d);
+          if let Some(c) = self.block {
+            let declared: Vec<_> = self.declared.range(start..).flatten().copied().collect();
+            self.declarers.extend(declared.into_iter().map(|e| (e, c)));
+          }
+          r
+        }
+        Stmt::Prop(_) | Stmt::Cond(..) => Ok(()),
+        Stmt::Modify(d) | Stmt::Override(d) | Stmt::Delete(d) if !self.conditional => {
+          let span = d.name().and_then(|n| n.span);
+          Err(CompileError::OverrideOutsideConditional { span })
+        }
+        Stmt::Modify(d) => self.add_override(scope, d, OverrideKind::Modify),
+        Stmt::Override(d) => self.add_override(scope, d, OverrideKind::Replace),
+        Stmt::Delete(d) => self.add_override(scope, d, OverrideKind::Delete),
+      };
+      self.report(r);
+    }
+  }
+
+  fn add_decl(&mut self, scope: ScopeId, decl: &ast::Decl) -> Result<(), CompileError> {
+    match decl {
+      Decl::Region(r) => self.add_region(scope, r),
+      Decl::Link(l) => {
+        let id = self.add_link(scope, l);
+        self.push_declared(id)
+      }
+      Decl::Item(i) => self.add_item(scope, i),
+      Decl::Items(i) => {
+        self.add_items(scope, i);
+        Ok(())
+      }
+      Decl::Location(l) => {
+        let id = self.add_location(scope, l);
+        self.push_declared(id)
+      }
+      Decl::Locations(l) => {
+        for l in &l.decls {
+          let id = self.add_location(scope, l);
+          let r = self.push_declared(id);
+          self.report(r);
+        }
+        Ok(())
+      }
+      Decl::Fn(f) => {
+        let id = self.add_fn(scope, f);
+        self.push_declared(id)
+      }
+      Decl::Enum(e) => self.add_enum(scope, e),
+      Decl::Config(c) => {
+        let id = self.add_config(scope, &c.name);
+        self.push_declared(id)
+      }
+      Decl::ConfigEnum(c) => {
+        let id = self.add_config_enum(scope, c);
+        self.push_declared(id)
+      }
+      Decl::Configs(c) => {
+        for tc in &c.configs {
+          let id = self.add_config(scope, &tc.name);
+          let r = self.push_declared(id);
+          self.report(r);
+        }
+        Ok(())
+      }
+      Decl::Configset(c) => {
+        let id = self.add_configset(scope, c);
+        self.push_declared(id)
+      }
+      Decl::Random(r) => {
+        let id = self.add_random(scope, r);
+        self.push_declared(id)
+      }
+    }
+  }
+
+  fn add_region(&mut self, parent: ScopeId, region: &ast::Region) -> Result<(), CompileError> {
+    let n = self.add_name(&region.name);
+    let id = self.validate_name_collisions(parent, &n).and_then(|()| {
+      let r = Region {
+        parent,
+        name: n,
+        children: IndexMap::new(),
+        props: RegionProps::default(),
+      };
+      let n = r.name.ident;
+      let id = Id(self.regions.insert(r));
+      self.declare(parent, n, EntityId::Region(id)).map(|()| id)
+    });
+    // If the region could not be declared, its contents are skipped entirely,
+    // and the second pass will skip them as well.
+    self.push_declared(id.clone().map(EntityId::Region))?;
+    let id = id?;
+    self.add_aliases(parent, EntityId::Region(id), &region.stmts);
+    self.populate_scope(ScopeId::Region(id), &region.stmts);
+    Ok(())
+  }
+
+  fn add_item(&mut self, parent: ScopeId, item: &ast::Item) -> Result<(), CompileError> {
+    let n = self.add_name(&item.name);
+    let id = self.validate_name_collisions(parent, &n).and_then(|()| {
+      // Tags actually declare the tag names, so we process them now.
+      let i = Item {
+        parent,
+        name: n,
+        tags: IndexSet::new(),
+        provides: Vec::new(),
+        progressive: None,
+        vals: IndexMap::new(),
+        max: None,
+        consumable: false,
+      };
+      self.add_item_tags(item);
+      let n = i.name.ident;
+      let id = EntityId::Item(Id(self.items.insert(i)));
+      self.declare(parent, n, id).map(|()| id)
+    });
+    if let Ok(id) = id {
+      self.add_aliases(parent, id, &item.stmts);
+    }
+    self.push_declared(id)
+  }
+  fn add_item_tags(&mut self, item: &ast::Item) {
+    // Value names are interned now too, so that the values of tags can be
+    // resolved before the items are built.
+    for s in &item.stmts {
+      match s {
+        Stmt::Prop(Prop::Tag(t)) => self.add_tag_vec(&t.tags),
+        Stmt::Prop(Prop::Val(v)) => {
+          self.add_ident(&v.name);
+        }
+        _ => {}
+      }
+    }
+  }
+  fn add_items(&mut self, parent: ScopeId, items: &ast::Items) {
+    self.add_tag_vec(&items.tags);
+    for i in &items.items {
+      let r = self.add_item(parent, i);
+      self.report(r);
+    }
+    for i in &items.nested {
+      self.add_items(parent, i);
+    }
+  }
+
+  fn add_location(
+    &mut self,
+    parent: ScopeId,
+    location: &ast::Location,
+  ) -> Result<EntityId, CompileError> {
+    let name = self.declare_name(parent, &location.name)?;
+    let n = name.ident;
+    let id = EntityId::Location(Id(self.locations.insert(Location {
+      parent,
+      name,
+      requires: None,
+      visible: None,
+    })));
+    self.declare(parent, n, id)?;
+    self.add_aliases(parent, id, &location.stmts);
+    Ok(id)
+  }
+
+  fn add_link(&mut self, parent: ScopeId, link: &ast::Link) -> Result<EntityId, CompileError> {
+    let name = match &link.name {
+      Some(n) => Some(self.declare_name(parent, n)?),
+      None => None,
+    };
+    let n = name.as_ref().map(|n| n.ident);
+    let id = EntityId::Link(Id(self.links.insert(Link {
+      parent,
+      name,
+      dir: link_dir(&link.dir),
+      regions: Vec::new(),
+      targets: Vec::new(),
+      requires: None,
+      unlock: None,
+      grants: Vec::new(),
+    })));
+    if let Some(n) = n {
+      self.declare(parent, n, id)?;
+    }
+    self.add_aliases(parent, id, &link.stmts);
+    Ok(id)
+  }
+
+  fn add_fn(&mut self, parent: ScopeId, f: &ast::FnDecl) -> Result<EntityId, CompileError> {
+    let name = self.declare_name(parent, &f.name)?;
+    let n = name.ident;
+    let id = EntityId::Function(Id(self.functions.insert(Function {
+      parent,
+      name,
+      params: Vec::new(),
+      ret_ty: None,
+      body: None,
+    })));
+    self.declare(parent, n, id).map(|()| id)
+  }
+
+  fn add_enum(&mut self, parent: ScopeId, e: &ast::Enum) -> Result<(), CompileError> {
+    let name = self.declare_name(parent, &e.name)?;
+    let n = name.ident;
+    let id = Id(self.enums.insert(Enum {
+      parent,
+      name,
+      variants: Vec::new(),
+    }));
+    self.declare(parent, n, EntityId::Enum(id))?;
+    self.add_variants(parent, id, &e.variants);
+    Ok(())
+  }
+
+  fn add_variants(&mut self, parent: ScopeId, enum_id: Id, variants: &[ast::DeclName]) {
+    for v in variants {
+      let r = self.declare_name(parent, v).and_then(|name| {
+        let n = name.ident;
+        let id = Id(self.variants.insert(Variant {
+          parent,
+          name,
+          enum_id,
+        }));
+        self.enums.get_mut(enum_id.0).unwrap().variants.push(id);
+        self.declare(parent, n, EntityId::Variant(id))
+      });
+      self.report(r);
+    }
+  }
+
+  fn add_config(
+    &mut self,
+    parent: ScopeId,
+    name: &ast::DeclName,
+  ) -> Result<EntityId, CompileError> {
+    let name = self.declare_name(parent, name)?;
+    // The type is replaced by build_config.
+    self.insert_config(parent, name, ConfigTy::Declared(Ty::Bool))
+  }
+  fn insert_config(
+    &mut self,
+    parent: ScopeId,
+    name: Name,
+    ty: ConfigTy,
+  ) -> Result<EntityId, CompileError> {
+    let n = name.ident;
+    let id = EntityId::Config(Id(self.configs.insert(Config {
+      parent,
+      name,
+      ty,
+      default: None,
+      disallowed: Vec::new(),
+    })));
+    self.declare(parent, n, id).map(|()| id)
+  }
+
+  fn add_config_enum(
+    &mut self,
+    parent: ScopeId,
+    c: &ast::ConfigEnum,
+  ) -> Result<EntityId, CompileError> {
+    // The enum shares the config's name, so only the config is declared in the
+    // scope and the enum is reachable only through the config's type.
+    let name = self.declare_name(parent, &c.name)?;
+    let id = Id(self.enums.insert(Enum {
+      parent,
+      name: name.clone(),
+      variants: Vec::new(),
+    }));
+    let config = self.insert_config(parent, name, ConfigTy::Enum(id))?;
+    self.add_variants(parent, id, &c.variants);
+    Ok(config)
+  }
+
+  fn add_configset(
+    &mut self,
+    parent: ScopeId,
+    c: &ast::Configset,
+  ) -> Result<EntityId, CompileError> {
+    let name = self.declare_name(parent, &c.name)?;
+    let n = name.ident;
+    let id = EntityId::Configset(Id(self.configsets.insert(Configset {
+      parent,
+      name,
+      vals: Vec::new(),
+      configsets: Vec::new(),
+      expansion: Vec::new(),
+    })));
+    self.declare(parent, n, id).map(|()| id)
+  }
+
+  fn add_random(&mut self, parent: ScopeId, r: &ast::Random) -> Result<EntityId, CompileError> {
+    let name = self.declare_name(parent, &r.name)?;
+    let n = name.ident;
+    let id = EntityId::Random(Id(self.randoms.insert(Random {
+      parent,
+      name,
+      vals: Vec::new(),
+    })));
+    self.declare(parent, n, id).map(|()| id)
+  }
+
+  // Apply the first pass of an override. Every override which the second pass
+  // builds has an entry in declared, even if it could not be applied, and it
+  // comes before those of any declarations inside it.
+  fn add_override(
+    &mut self,
+    scope: ScopeId,
+    d: &ast::Decl,
+    kind: OverrideKind,
+  ) -> Result<(), CompileError> {
+    let target = self.override_target(scope, d, kind);
+    if kind != OverrideKind::Delete && !matches!(d, Decl::Enum(_)) {
+      self.push_declared(target.clone())?;
+    }
+    self.apply_override(d, target?, kind);
+    Ok(())
+  }
+
+  // Find the entity an override applies to and record the override against
+  // it, checking that it does not conflict with another.
+  fn override_target(
+    &mut self,
+    scope: ScopeId,
+    d: &ast::Decl,
+    kind: OverrideKind,
+  ) -> Result<EntityId, CompileError> {
+    let name = d
+      .name()
+      .ok_or(CompileError::UnnamedOverride { span: None })?;
+    let span = name.span;
+    let target = self
+      .idents
+      .get(&name.ident.0)
+      .and_then(|i| self.lookup(self.get_scope(scope).unwrap(), Ident(i)))
+      .ok_or_else(|| CompileError::NotFound {
+        path: name.ident.0.clone(),
+        span,
+      })?;
+    let path = self.path_of(target);
+    let expected = decl_kind(d);
+    if target.kind() != expected {
+      return Err(CompileError::OverrideMismatch {
+        path,
+        kind: expected,
+        span,
+      });
+    }
+    // Configs must stay as they were declared, regions are only ever
+    // modified, and functions and randoms have no block to modify.
+    let forbidden = matches!(
+      (kind, expected),
+      (_, EntityKind::Config | EntityKind::Configset)
+        | (OverrideKind::Replace, EntityKind::Region)
+        | (
+          OverrideKind::Modify,
+          EntityKind::Function | EntityKind::Random
+        )
+    );
+    if forbidden {
+      return Err(CompileError::CannotOverride {
+        action: kind.action(),
+        path,
+        span,
+      });
+    }
+    let props = match d {
+      Decl::Region(r) => r
+        .stmts
+        .iter()
+        .any(|s| matches!(s, Stmt::Prop(p) if !matches!(p, Prop::Alias(_)))),
+      Decl::Enum(_) => false,
+      _ => true,
+    };
+    let o = Override {
+      kind,
+      depth: self.depth,
+      props,
+    };
+    // Two overrides at the same depth conflict unless both are deletions, or
+    // both are modifications and at most one changes properties. Otherwise,
+    // the later one is more deeply nested, and supersedes the earlier.
+    let o = match self.overrides.get(&target) {
+      Some(prev) if prev.depth == o.depth => match (prev.kind, kind) {
+        (OverrideKind::Delete, OverrideKind::Delete) => o,
+        (OverrideKind::Modify, OverrideKind::Modify) if !(prev.props && props) => Override {
+          props: prev.props || props,
+          ..o
+        },
+        _ => return Err(CompileError::ConflictingOverrides { path, span }),
+      },
+      _ => o,
+    };
+    if kind == OverrideKind::Modify && self.deleted.contains(&target) {
+      return Err(CompileError::Deleted { path, span });
+    }
+    self.overrides.insert(target, o);
+    Ok(target)
+  }
+
+  // Make the changes an override makes to the scopes. Deleting and replacing
+  // clear the entity's contents, and modifications keep them.
+  fn apply_override(&mut self, d: &ast::Decl, target: EntityId, kind: OverrideKind) {
+    let parent = self.get_entity(target).unwrap().parent();
+    match kind {
+      OverrideKind::Delete => {
+        self.delete(target);
+        return;
+      }
+      OverrideKind::Replace => {
+        self.deleted.remove(&target);
+        self.reset(target, d);
+      }
+      OverrideKind::Modify => {}
+    }
+    match (d, target) {
+      (Decl::Region(r), EntityId::Region(id)) => {
+        self.add_aliases(parent, target, &r.stmts);
+        self.populate_scope(ScopeId::Region(id), &r.stmts);
+      }
+      (Decl::Item(i), _) => {
+        self.add_item_tags(i);
+        self.add_aliases(parent, target, &i.stmts);
+      }
+      (Decl::Location(l), _) => self.add_aliases(parent, target, &l.stmts),
+      (Decl::Link(l), _) => self.add_aliases(parent, target, &l.stmts),
+      (Decl::Enum(e), EntityId::Enum(id)) => {
+        let mut new = e.variants.clone();
+        if kind == OverrideKind::Replace {
+          // Variants left out of the replacement are deleted, and those it
+          // repeats are kept.
+          for v in self.enums[id.0].variants.clone() {
+            let name = self
+              .program
+              .resolve(self.variants[v.0].name.ident)
+              .to_string();
+            let len = new.len();
+            new.retain(|n| n.ident.0 != name);
+            if new.len() == len {
+              self.delete(EntityId::Variant(v));
+            } else {
+              self.deleted.remove(&EntityId::Variant(v));
+            }
+          }
+        }
+        self.add_variants(parent, id, &new);
+      }
+      _ => {}
+    }
+  }
+
+  // Delete an entity along with everything it contains.
+  fn delete(&mut self, e: EntityId) {
+    let mut doomed = vec![e];
+    if let EntityId::Region(r) = e {
+      contained(&self.region_contents(), r, &mut doomed);
+    }
+    for e in doomed {
+      self.deleted.insert(e);
+      if let EntityId::Enum(en) = e {
+        let p = &mut self.program;
+        let variants = p.enums[en.0].variants.iter();
+        p.deleted.extend(variants.map(|v| EntityId::Variant(*v)));
+      }
+    }
+  }
+
+  // Clear the contents of an entity which is being replaced. Functions and
+  // randoms are rebuilt entirely anyway, and enums contain only variants.
+  fn reset(&mut self, e: EntityId, d: &ast::Decl) {
+    match (e, d) {
+      (EntityId::Item(i), _) => {
+        let item = &mut self.items[i.0];
+        item.name.human = None;
+        item.tags.clear();
+        item.provides.clear();
+        item.progressive = None;
+        item.vals.clear();
+        item.max = None;
+        item.consumable = false;
+      }
+      (EntityId::Location(l), _) => {
+        let l = &mut self.locations[l.0];
+        l.requires = None;
+        l.visible = None;
+      }
+      (EntityId::Link(l), Decl::Link(input)) => {
+        let l = &mut self.links[l.0];
+        l.dir = link_dir(&input.dir);
+        l.regions.clear();
+        l.requires = None;
+        l.unlock = None;
+        l.grants.clear();
+      }
+      _ => {}
+    }
+  }
+
+  // Declare the names given by alias statements in an entity's block as
+  // further names for it, alongside its primary name.
+  fn add_aliases(&mut self, parent: ScopeId, e: EntityId, stmts: &[ast::Stmt]) {
+    let span = self.get_entity_name_span(e);
+    for s in stmts {
+      if let Stmt::Prop(Prop::Alias(a)) = s {
+        let names = match new_list(&a.names, span) {
+          Ok(names) => names,
+          Err(err) => {
+            self.report(Err(err));
+            continue;
+          }
+        };
+        for n in names {
+          let r = self.add_alias(parent, e, n, span);
+          self.report(r);
+        }
+      }
+    }
+  }
+  fn add_alias(
+    &mut self,
+    parent: ScopeId,
+    e: EntityId,
+    alias: &ast::Ident,
+    span: Option<Span>,
+  ) -> Result<(), CompileError> {
+    let name = Name {
+      ident: self.add_ident(alias),
+      human: None,
+      span,
+    };
+    self.validate_name_collisions(parent, &name)?;
+    self.declare(parent, name.ident, e)?;
+    self.aliases.entry(e).or_default().push(name.ident);
+    Ok(())
+  }
+
+  fn add_ident(&mut self, i: &ast::Ident) -> Ident {
+    Ident(self.idents.get_or_intern(&*i.0))
+  }
+  // add_name adds an identifier into the interning cache and returns a Name
+  // from it. It does not set the human name; that must be done manually
+  // during the second pass.
+  fn add_name(&mut self, n: &ast::DeclName) -> Name {
+    Name {
+      ident: self.add_ident(&n.ident),
+      human: None,
+      span: n.span,
+    }
+  }
+  // declare_name checks that a name is free in the scope, and includes the
+  // human name.
+  fn declare_name(&mut self, parent: ScopeId, n: &ast::DeclName) -> Result<Name, CompileError> {
+    let mut name = self.add_name(n);
+    self.validate_name_collisions(parent, &name)?;
+    name.human = n.human.clone();
+    Ok(name)
+  }
+  fn declare(&mut self, parent: ScopeId, n: Ident, e: EntityId) -> Result<(), CompileError> {
+    let span = self.get_entity_name_span(e);
+    let inserted = self
+      .get_scope_mut(parent)
+      .unwrap()
+      .insert_child(n, e, PrivateHack(()));
+    inserted.map_err(|existing| CompileError::DuplicateName {
+      name: self.path_str(&[n]),
+      existing,
+      span,
+    })
+  }
+  fn add_tag_vec(&mut self, tags: &ModVec<ast::Ident>) {
+    for t in match tags {
+      ModVec::New(v) => either::Left(v.iter()),
+      ModVec::Mod(v) => either::Right(v.iter().map(|p| &p.1)),
+    } {
+      let r = self.add_tag(t);
+      self.report(r);
+    }
+  }
+  fn add_tag(&mut self, tag: &ast::Ident) -> Result<(), CompileError> {
+    let t = self.add_ident(tag);
+    let mut to_check = vec![&**self as &dyn Scope];
+    while let Some(s) = to_check.pop() {
+      for (n, e) in s.children() {
+        if *n == t {
+          if e == &EntityId::Tag(t) {
+            return Ok(());
+          }
+          return Err(CompileError::TagCollision {
+            name: tag.0.clone(),
+            existing: *e,
+          });
+        }
+        #[allow(clippy::single_match)]
+        match e {
+          EntityId::Region(r) => to_check.push(self.regions.get(r.0).unwrap()),
+          _ => {}
+        }
+      }
+    }
+    self.global_decls.insert(t, EntityId::Tag(t));
+    Ok(())
+  }
+
+  fn validate_name_collisions(&self, s: ScopeId, n: &Name) -> Result<(), CompileError> {
+    if let Some(existing) = self.lookup(self.get_scope(s).unwrap(), n.ident) {
+      let name = self.path_str(&[n.ident]);
+      if self.get_entity(existing).unwrap().parent() == s {
+        Err(CompileError::DuplicateName {
+          name,
+          existing,
+          span: n.span,
+        })
+      } else {
+        Err(CompileError::ShadowedName {
+          name,
+          existing,
+          span: n.span,
+        })
+      }
+    } else {
+      Ok(())
+    }
+  }
+
+  // Resolve the names in an expression appearing in a scope. Names which
+  // cannot be resolved are reported, and None is returned.
+  fn resolve_expr(&mut self, scope: ScopeId, e: &ast::Expr, span: Option<Span>) -> Option<Expr> {
+    self.resolve(scope, span, |r| r.expr(e))
+  }
+  fn resolve_ty(&mut self, scope: ScopeId, ty: &ast::Ty, span: Option<Span>) -> Option<Ty> {
+    self.resolve(scope, span, |r| r.ty(ty))
+  }
+  fn resolve<T>(
+    &mut self,
+    scope: ScopeId,
+    span: Option<Span>,
+    f: impl FnOnce(&mut Resolver<'_>) -> Option<T>,
+  ) -> Option<T> {
+    let mut r = Resolver::new(&self.program, scope, span);
+    let t = f(&mut r);
+    let errors = r.errors;
+    self.diags.errors.extend(errors);
+    t
+  }
+
+  fn build_scope(&mut self, scope: ScopeId, stmts: Vec<ast::Stmt>) {
+    // Second pass: fill in the entities declared by the first pass, resolving
+    // the names in their expressions.
+    for s in stmts {
+      match s {
+        Stmt::Decl(d) => self.build_decl(scope, d),
+        Stmt::Prop(prop) => {
+          let r = self.build_region_prop(scope, prop);
+          self.report(r);
+        }
+        Stmt::Cond(tags, cond, then, otherwise) => {
+          self.add_cond(scope, tags, cond, then, otherwise)
+        }
+        Stmt::Modify(d) if self.conditional => self.build_override(d, true),
+        Stmt::Override(d) if self.conditional => self.build_override(d, false),
+        // Deletions are complete after the first pass, and overrides outside
+        // conditional blocks were already reported by it.
+        Stmt::Modify(_) | Stmt::Override(_) | Stmt::Delete(_) => {}
+      }
+    }
+  }
+
+  fn build_decl(&mut self, scope: ScopeId, decl: ast::Decl) {
+    match decl {
+      Decl::Region(region) => {
+        if let Some(id) = self.next_declared() {
+          self.build_region(id.unwrap_region(), region);
+        }
+      }
+      Decl::Item(item) => {
+        if let Some(id) = self.next_declared() {
+          self.build_item(id.unwrap_item(), item, IndexSet::new(), false);
+        }
+      }
+      Decl::Items(items) => self.build_items(items, IndexSet::new()),
+      Decl::Location(l) => {
+        if let Some(id) = self.next_declared() {
+          self.build_location(scope, id.unwrap_location(), l);
+        }
+      }
+      Decl::Locations(ls) => {
+        for l in ls.decls {
+          if let Some(id) = self.next_declared() {
+            self.build_location(scope, id.unwrap_location(), l);
+          }
+        }
+      }
+      Decl::Link(l) => {
+        if let Some(id) = self.next_declared() {
+          self.build_link(scope, id.unwrap_link(), l, false);
+        }
+      }
+      Decl::Fn(f) => {
+        if let Some(id) = self.next_declared() {
+          self.build_fn(scope, id.unwrap_function(), f);
+        }
+      }
+      // Enums contain nothing but their variants, which are already declared.
+      Decl::Enum(_) => {}
+      Decl::Config(c) => {
+        if let Some(id) = self.next_declared() {
+          self.build_config(scope, id.unwrap_config(), Some(&c.ty), c.default, c.stmts);
+        }
+      }
+      Decl::ConfigEnum(c) => {
+        if let Some(id) = self.next_declared() {
+          self.build_config(scope, id.unwrap_config(), None, c.default, c.stmts);
+        }
+      }
+      Decl::Configs(cs) => {
+        for c in cs.configs {
+          if let Some(id) = self.next_declared() {
+            let ty = Some(&cs.ty);
+            self.build_config(scope, id.unwrap_config(), ty, c.default, c.stmts);
+          }
+        }
+      }
+      Decl::Configset(c) => {
+        if let Some(id) = self.next_declared() {
+          self.build_configset(scope, id.unwrap_configset(), c);
+        }
+      }
+      Decl::Random(r) => {
+        if let Some(id) = self.next_declared() {
+          self.build_random(scope, id.unwrap_random(), r);
+        }
+      }
+    }
+  }
+
+  fn build_region(&mut self, region: Id, input: ast::Region) {
+    self.build_scope(ScopeId::Region(region), input.stmts);
+    if let Some(human) = input.name.human {
+      self.regions.get_mut(region.into()).unwrap().name.human = Some(human);
+    }
+  }
+
+  fn build_region_prop(&mut self, scope: ScopeId, prop: Prop) -> Result<(), CompileError> {
+    let span = self.scope_span(scope);
+    match prop {
+      Prop::Requires(r) => match self.resolve_expr(scope, &r.cond, span) {
+        Some(e) => set_once(
+          &mut self.region_props_mut(scope).requires,
+          e,
+          "requires",
+          span,
+        ),
+        None => Ok(()),
+      },
+      Prop::Visible(v) => match self.resolve_expr(scope, &v.cond, span) {
+        Some(e) => set_once(
+          &mut self.region_props_mut(scope).visible,
+          e,
+          "visible",
+          span,
+        ),
+        None => Ok(()),
+      },
+      Prop::Unlock(u) => {
+        let item = self.resolve_path(scope, &u.item, span);
+        set_once(
+          &mut self.region_props_mut(scope).unlock,
+          item,
+          "unlock",
+          span,
+        )
+      }
+      Prop::Avail(a) => {
+        let avail = new_list(&a.items, span)?
+          .iter()
+          .map(|(pos, item, count)| Avail {
+            item: self.resolve_path(scope, item, span),
+            negated: !pos,
+            count: count.clone(),
+          })
+          .collect::<Vec<_>>();
+        self.region_props_mut(scope).avail.extend(avail);
+        Ok(())
+      }
+      Prop::Grants(g) => {
+        let grants = self.convert_grants(scope, &g.items, span)?;
+        self.region_props_mut(scope).grants.extend(grants);
+        Ok(())
+      }
+      Prop::StartWith(s) => {
+        let items = self.resolve_paths(scope, &s.items, span);
+        self.region_props_mut(scope).start_with.extend(items);
+        Ok(())
+      }
+      Prop::StartIn(s) => {
+        if scope != ScopeId::Global {
+          return Err(CompileError::StartInOutsideGlobal { span });
+        }
+        let region = self.resolve_path(scope, &s.region, span);
+        set_once(&mut self.start_in, region, "start in", span)
+      }
+      Prop::Exclusive(x) => {
+        let tags = self.convert_cond_tags(&x.tags);
+        self.exclusive.push(tags);
+        Ok(())
+      }
+      // Registered by the first pass, except that the global scope has no name
+      // to give aliases to.
+      Prop::Alias(_) if scope != ScopeId::Global => Ok(()),
+      p if scope == ScopeId::Global => Err(prop_not_allowed(&p, StmtContext::Global, span)),
+      p => Err(prop_not_allowed(&p, StmtContext::Region, span)),
+    }
+  }
+
+  fn region_props_mut(&mut self, scope: ScopeId) -> &mut RegionProps {
+    match scope {
+      ScopeId::Global => &mut self.props,
+      ScopeId::Region(r) => &mut self.regions.get_mut(r.0).unwrap().props,
+    }
+  }
+
+  // Items are built on top of what they already have, which is nothing unless
+  // this is a modifying declaration.
+  fn build_item(
+    &mut self,
+    item: Id,
+    input: ast::Item,
+    group_tags: IndexSet<Ident>,
+    modifying: bool,
+  ) {
+    let i = &self.items[item.0];
+    let scope = i.parent;
+    let span = input.name.span;
+    let item_name = &input.name.ident.0;
+    let mut tags = i.tags.clone();
+    tags.extend(group_tags);
+    let mut provides = i.provides.clone();
+    let mut progressive = i.progressive.clone();
+    let mut vals = i.vals.clone();
+    let mut max = i.max.clone();
+    let mut consumable = i.consumable;
+    for s in input.stmts {
+      let prop = match s {
+        Stmt::Prop(p) => p,
+        _ => {
+          self.report(Err(decl_not_allowed(StmtContext::Item, span)));
+          continue;
+        }
+      };
+      let r = match prop {
+        Prop::Tag(t) => list_changes(&t.tags, modifying, span).map(|changes| {
+          for (pos, tag) in changes {
+            let t = self.convert_ident(tag);
+            if !pos {
+              tags.shift_remove(&t);
+            } else if !tags.insert(t) {
+              self.warn(CompileWarning::RedundantTag {
+                item: item_name.clone(),
+                tag: tag.0.clone(),
+                span,
+              });
+            }
+          }
+        }),
+        // Registered by the first pass.
+        Prop::Alias(_) => Ok(()),
+        Prop::Provides(p) => list_changes(&p.items, modifying, span).map(|changes| {
+          for (pos, item) in changes {
+            let item = self.resolve_path(scope, item, span);
+            if pos {
+              provides.push(item);
+            } else {
+              provides.retain(|p| *p != item);
+            }
+          }
+        }),
+        Prop::Progressive(p) => new_list(&p.items, span).and_then(|items| {
+          let items = self.resolve_paths(scope, items, span);
+          set_once(&mut progressive, items, "progressive", span)
+        }),
+        Prop::Val(v) => {
+          let n = self.add_ident(&v.name);
+          // Each of these is None if resolution failed.
+          let ty = match &v.ty {
+            Some(ty) => self.resolve_ty(scope, ty, span).map(Some),
+            None => Some(None),
+          };
+          let val = self.resolve_expr(scope, &v.val, span);
+          match vals.entry(n) {
+            Entry::Occupied(_) => Err(CompileError::DuplicateVal {
+              name: v.name.0,
+              span,
+            }),
+            Entry::Vacant(slot) => {
+              if let (Some(ty), Some(val)) = (ty, val) {
+                slot.insert(Val { ty, val });
+              }
+              Ok(())
+            }
+          }
+        }
+        Prop::Max(m) => match self.resolve_expr(scope, &m.expr, span) {
+          Some(e) => set_once(&mut max, e, "max", span),
+          None => Ok(()),
+        },
+        Prop::Consumable if consumable => Err(CompileError::DuplicateProp {
+          keyword: "consumable",
+          span,
+        }),
+        Prop::Consumable => {
+          consumable = true;
+          Ok(())
+        }
+        p => Err(prop_not_allowed(&p, StmtContext::Item, span)),
+      };
+      self.report(r);
+    }
+
+    let item = self.items.get_mut(item.into()).unwrap();
+    if let Some(human) = input.name.human {
+      item.name.human = Some(human);
+    }
+    item.tags = tags;
+    item.provides = provides;
+    item.progressive = progressive;
+    item.vals = vals;
+    item.max = max;
+    item.consumable = consumable;
+  }
+
+  fn build_items(&mut self, items: ast::Items, mut tags: IndexSet<Ident>) {
+    // Multi-item declarations have no name to point at.
+    match new_list(&items.tags, None) {
+      Ok(t) => tags.extend(t.iter().map(|tag| self.convert_ident(tag))),
+      Err(e) => self.report(Err(e)),
+    }
+
+    // This must visit items in the same order as add_items.
+    for item in items.items {
+      if let Some(id) = self.next_declared() {
+        self.build_item(id.unwrap_item(), item, tags.clone(), false);
+      }
+    }
+    for nested in items.nested {
+      self.build_items(nested, tags.clone());
+    }
+  }
+
+  fn build_location(&mut self, scope: ScopeId, id: Id, input: ast::Location) {
+    let span = input.name.span;
+    let l = &self.locations[id.0];
+    let mut requires = l.requires.clone();
+    let mut visible = l.visible.clone();
+    for s in &input.stmts {
+      let r = match s {
+        Stmt::Prop(Prop::Requires(r)) => match self.resolve_expr(scope, &r.cond, span) {
+          Some(e) => set_once(&mut requires, e, "requires", span),
+          None => Ok(()),
+        },
+        Stmt::Prop(Prop::Visible(v)) => match self.resolve_expr(scope, &v.cond, span) {
+          Some(e) => set_once(&mut visible, e, "visible", span),
+          None => Ok(()),
+        },
+        // Registered by the first pass.
+        Stmt::Prop(Prop::Alias(_)) => Ok(()),
+        Stmt::Prop(p) => Err(prop_not_allowed(p, StmtContext::Location, span)),
+        _ => Err(decl_not_allowed(StmtContext::Location, span)),
+      };
+      self.report(r);
+    }
+    let l = &mut self.locations[id.0];
+    l.requires = requires;
+    l.visible = visible;
+  }
+
+  fn build_link(&mut self, scope: ScopeId, id: Id, input: ast::Link, modifying: bool) {
+    let span = input.name.as_ref().and_then(|n| n.span);
+    let l = &self.links[id.0];
+    let mut regions = l.regions.clone();
+    let mut requires = l.requires.clone();
+    let mut unlock = l.unlock.clone();
+    let mut grants = l.grants.clone();
+    match list_changes(&input.regions, modifying, span) {
+      Ok(changes) => {
+        for (pos, region) in changes {
+          let region = self.convert_path(region);
+          if pos {
+            regions.push(region);
+          } else {
+            regions.retain(|r| *r != region);
+          }
+        }
+      }
+      Err(e) => self.report(Err(e)),
+    }
+    for s in &input.stmts {
+      let r = match s {
+        Stmt::Prop(Prop::Requires(r)) => match self.resolve_expr(scope, &r.cond, span) {
+          Some(e) => set_once(&mut requires, e, "requires", span),
+          None => Ok(()),
+        },
+        Stmt::Prop(Prop::Unlock(u)) => {
+          let item = self.resolve_path(scope, &u.item, span);
+          set_once(&mut unlock, item, "unlock", span)
+        }
+        Stmt::Prop(Prop::Grants(g)) => self
+          .convert_grants(scope, &g.items, span)
+          .map(|g| grants.extend(g)),
+        // Registered by the first pass.
+        Stmt::Prop(Prop::Alias(_)) => Ok(()),
+        Stmt::Prop(p) => Err(prop_not_allowed(p, StmtContext::Link, span)),
+        _ => Err(decl_not_allowed(StmtContext::Link, span)),
+      };
+      self.report(r);
+    }
+    let targets = self.link_targets(scope, &regions, span);
+    let l = &mut self.links[id.0];
+    l.regions = regions;
+    l.targets = targets;
+    l.requires = requires;
+    l.unlock = unlock;
+    l.grants = grants;
+  }
+
+  // Find the regions which a link declared in a scope leads to. Links can
+  // only be declared in regions, and only lead to regions.
+  fn link_targets(&mut self, scope: ScopeId, regions: &[Path], span: Option<Span>) -> Vec<Id> {
+    if scope == ScopeId::Global {
+      self.report(Err(CompileError::LinkOutsideRegion { span }));
+      return Vec::new();
+    }
+    let mut targets = Vec::new();
+    for path in regions {
+      let r = self
+        .lookup_entity(self.get_scope(scope).unwrap(), path)
+        .map_err(|e| e.with_span(span))
+        .and_then(|e| match e {
+          EntityId::Region(r) => Ok(r),
+          e => Err(CompileError::NotARegion {
+            found: self.path_of(e),
+            span,
+          }),
+        })
+        .map(|r| targets.push(r));
+      self.report(r);
+    }
+    targets
+  }
+
+  fn build_fn(&mut self, scope: ScopeId, id: Id, input: ast::FnDecl) {
+    let span = input.name.span;
+    let mut params: Vec<Param> = Vec::new();
+    for p in &input.params {
+      let n = self.add_ident(&p.name);
+      if params.iter().any(|p| p.name == n) {
+        self.report(Err(CompileError::DuplicateParam {
+          name: p.name.0.clone(),
+          span,
+        }));
+        continue;
+      }
+      // If the type cannot be resolved, it is left to be inferred so that
+      // calls are still checked against the right number of parameters.
+      let ty = p
+        .ty
+        .as_ref()
+        .and_then(|ty| self.resolve_ty(scope, ty, span));
+      params.push(Param { name: n, ty });
+    }
+    let ret_ty = input
+      .ret_ty
+      .and_then(|ty| self.resolve_ty(scope, &ty, span));
+
+    let mut r = Resolver::new(&self.program, scope, span);
+    r.params = &params;
+    let body = r.expr(&input.body);
+    let errors = r.errors;
+    self.diags.errors.extend(errors);
+
+    let f = &mut self.functions[id.0];
+    f.params = params;
+    f.ret_ty = ret_ty;
+    f.body = body;
+  }
+
+  fn build_config(
+    &mut self,
+    scope: ScopeId,
+    id: Id,
+    ty: Option<&ast::Ty>,
+    default: Option<ast::Expr>,
+    stmts: Vec<ast::Stmt>,
+  ) {
+    let span = self.configs[id.0].name.span;
+    let ty = match ty {
+      Some(ty) => match self.resolve_ty(scope, ty, span) {
+        Some(ty) => Some(ty),
+        // Without a type, checking the default would only give spurious
+        // errors.
+        None => return,
+      },
+      None => None,
+    };
+    let default = default.and_then(|e| self.resolve_expr(scope, &e, span));
+    let c = &mut self.configs[id.0];
+    if let Some(ty) = ty {
+      c.ty = ConfigTy::Declared(ty);
+    }
+    c.default = default;
+    let disallowed = self.build_disallowed(id, &stmts, span);
+    self.configs[id.0].disallowed = disallowed;
+  }
+
+  // Find the variants disallowed by the statements in a config's block. They
+  // are named by their identifiers alone, since they can only be variants of
+  // the config's own enum.
+  fn build_disallowed(&mut self, id: Id, stmts: &[ast::Stmt], span: Option<Span>) -> Vec<Id> {
+    let variants = match &self.configs[id.0].ty {
+      ConfigTy::Enum(e) | ConfigTy::Declared(Ty::Enum(e)) => Some(self.enums[e.0].variants.clone()),
+      ConfigTy::Declared(_) => None,
+    };
+    let config = self.path_of(EntityId::Config(id));
+    let mut disallowed = Vec::new();
+    for s in stmts {
+      let r = match (s, &variants) {
+        (Stmt::Prop(Prop::Disallow(d)), Some(variants)) => {
+          let found = variants
+            .iter()
+            .copied()
+            .find(|v| self.program.resolve(self.variants[v.0].name.ident) == d.value.0);
+          match found {
+            Some(v) => {
+              if !disallowed.contains(&v) {
+                disallowed.push(v);
+              }
+              Ok(())
+            }
+            None => Err(CompileError::UnknownVariant {
+              config: config.clone(),
+              variant: d.value.0.clone(),
+              span,
+            }),
+          }
+        }
+        (Stmt::Prop(Prop::Disallow(_)), None) => Err(CompileError::DisallowNotEnum {
+          config: config.clone(),
+          span,
+        }),
+        (Stmt::Prop(p), _) => Err(prop_not_allowed(p, StmtContext::Config, span)),
+        _ => Err(decl_not_allowed(StmtContext::Config, span)),
+      };
+      self.report(r);
+    }
+    if let Some(variants) = variants {
+      if !variants.is_empty() && variants.iter().all(|v| disallowed.contains(v)) {
+        self.report(Err(CompileError::AllDisallowed { config, span }));
+      }
+    }
+    disallowed
+  }
+
+  fn build_configset(&mut self, scope: ScopeId, id: Id, input: ast::Configset) {
+    let span = input.name.span;
+    let mut vals = Vec::new();
+    for (p, e) in &input.vals {
+      let c = self.resolve(scope, span, |r| r.config(p));
+      let e = self.resolve_expr(scope, e, span);
+      if let (Some(c), Some(e)) = (c, e) {
+        vals.push((c, e));
+      }
+    }
+    let configsets = input
+      .configsets
+      .iter()
+      .filter_map(|p| self.resolve(scope, span, |r| r.configset(p)))
+      .collect();
+    let c = &mut self.configsets[id.0];
+    c.vals = vals;
+    c.configsets = configsets;
+  }
+
+  fn build_random(&mut self, scope: ScopeId, id: Id, input: ast::Random) {
+    let span = input.name.span;
+    let vals = match new_list(&input.vals, span) {
+      Ok(vals) => vals
+        .iter()
+        .filter_map(|e| self.resolve_expr(scope, e, span))
+        .collect(),
+      Err(e) => {
+        self.report(Err(e));
+        Vec::new()
+      }
+    };
+    self.randoms[id.0].vals = vals;
+  }
+
+  fn build_override(&mut self, d: ast::Decl, modifying: bool) {
+    // Enums contain nothing but their variants, which are already declared.
+    if matches!(d, Decl::Enum(_)) {
+      return;
+    }
+    let id = match self.next_declared() {
+      Some(id) => id,
+      None => return,
+    };
+    // Names are looked up from where the original was declared.
+    let scope = self.get_entity(id).unwrap().parent();
+    match d {
+      Decl::Region(r) => {
+        self.clear_props(id, &r.stmts);
+        self.build_region(id.unwrap_region(), r);
+      }
+      Decl::Item(i) => {
+        if modifying {
+          self.clear_props(id, &i.stmts);
+        }
+        self.build_item(id.unwrap_item(), i, IndexSet::new(), modifying);
+      }
+      Decl::Location(l) => {
+        if modifying {
+          self.clear_props(id, &l.stmts);
+        }
+        self.build_location(scope, id.unwrap_location(), l);
+      }
+      Decl::Link(l) => {
+        if modifying {
+          self.clear_props(id, &l.stmts);
+          if let ModVec::New(_) = l.regions {
+            self.links[id.unwrap_link().0].regions.clear();
+          }
+        }
+        self.build_link(scope, id.unwrap_link(), l, modifying);
+      }
+      Decl::Fn(f) => {
+        let id = id.unwrap_function();
+        let orig = &self.functions[id.0];
+        let sig = (
+          orig.params.iter().map(|p| p.ty.clone()).collect(),
+          orig.ret_ty.clone(),
+        );
+        self.signatures.entry(id).or_insert(sig);
+        self.build_fn(scope, id, f);
+      }
+      Decl::Random(r) => self.build_random(scope, id.unwrap_random(), r),
+      _ => unreachable!(),
+    }
+  }
+
+  // In a modifying declaration, a property which is given again replaces the
+  // original, while modifier lists change the original in place.
+  fn clear_props(&mut self, e: EntityId, stmts: &[ast::Stmt]) {
+    for s in stmts {
+      let p = match s {
+        Stmt::Prop(p) => p,
+        _ => continue,
+      };
+      match (e, p) {
+        (EntityId::Region(r), Prop::Requires(_)) => self.regions[r.0].props.requires = None,
+        (EntityId::Region(r), Prop::Visible(_)) => self.regions[r.0].props.visible = None,
+        (EntityId::Region(r), Prop::Unlock(_)) => self.regions[r.0].props.unlock = None,
+        (EntityId::Item(i), Prop::Tag(t)) if matches!(t.tags, ModVec::New(_)) => {
+          self.items[i.0].tags.clear()
+        }
+        (EntityId::Item(i), Prop::Provides(p)) if matches!(p.items, ModVec::New(_)) => {
+          self.items[i.0].provides.clear()
+        }
+        (EntityId::Item(i), Prop::Progressive(_)) => self.items[i.0].progressive = None,
+        (EntityId::Item(i), Prop::Val(v)) => {
+          let n = self.add_ident(&v.name);
+          self.items[i.0].vals.shift_remove(&n);
+        }
+        (EntityId::Item(i), Prop::Max(_)) => self.items[i.0].max = None,
+        (EntityId::Location(l), Prop::Requires(_)) => self.locations[l.0].requires = None,
+        (EntityId::Location(l), Prop::Visible(_)) => self.locations[l.0].visible = None,
+        (EntityId::Link(l), Prop::Requires(_)) => self.links[l.0].requires = None,
+        (EntityId::Link(l), Prop::Unlock(_)) => self.links[l.0].unlock = None,
+        (EntityId::Link(l), Prop::Grants(_)) => self.links[l.0].grants.clear(),
+        _ => {}
+      }
+    }
+  }
+
+  fn add_cond(
+    &mut self,
+    scope: ScopeId,
+    tags: Vec<(bool, ast::Ident)>,
+    cond: ast::Expr,
+    then: Vec<ast::Stmt>,
+    otherwise: Vec<ast::Stmt>,
+  ) {
+    let span = self.scope_span(scope);
+    if then.is_empty() && otherwise.is_empty() {
+      self.warn(CompileWarning::EmptyConditional { span });
+    }
+    self.forbid_configs(&then);
+    self.forbid_configs(&otherwise);
+    if let Some(cond) = self.resolve_expr(scope, &cond, span) {
+      let depth = self.depth;
+      let tags = self.convert_cond_tags(&tags);
+      let inherited = self.cond_tags.clone();
+      let id = self.conds.insert(Cond {
+        parent: scope,
+        cond,
+        then,
+        otherwise,
+        tags,
+        inherited,
+        depth,
+      });
+      if let Some(c) = self.block {
+        self.declarers.insert(EntityId::Cond(Id(id)), c);
+      }
+    }
+  }
+
+  // Configs must all be known before a program is configured, so they cannot
+  // be declared anywhere in a conditional block.
+  fn forbid_configs(&mut self, stmts: &[ast::Stmt]) {
+    for s in stmts {
+      match s {
+        Stmt::Decl(d) | Stmt::Modify(d) | Stmt::Override(d) | Stmt::Delete(d) => match d {
+          Decl::Config(_) | Decl::ConfigEnum(_) | Decl::Configs(_) | Decl::Configset(_) => {
+            let span = d.name().and_then(|n| n.span);
+            self.report(Err(CompileError::ConfigInConditional { span }));
+          }
+          Decl::Region(r) => self.forbid_configs(&r.stmts),
+          _ => {}
+        },
+        Stmt::Cond(_, _, then, otherwise) => {
+          self.forbid_configs(then);
+          self.forbid_configs(otherwise);
+        }
+        _ => {}
+      }
+    }
+  }
+
+  fn convert_ident(&self, ident: &ast::Ident) -> Ident {
+    Ident(self.idents.get(&ident.0).unwrap())
+  }
+
+  // Paths may name entities that have not been declared, so unlike
+  // convert_ident this interns any new identifiers.
+  fn convert_path(&mut self, path: &ast::Path) -> Path {
+    Path(path.iter().map(|i| self.add_ident(i)).collect())
+  }
+  // Paths naming entities in properties are looked up as names in
+  // expressions are, and reported if they are not found.
+  fn resolve_path(&mut self, scope: ScopeId, path: &ast::Path, span: Option<Span>) -> Path {
+    let mut r = Resolver::new(&self.program, scope, span);
+    r.lookup(path);
+    let errors = r.errors;
+    self.diags.errors.extend(errors);
+    self.convert_path(path)
+  }
+  fn resolve_paths(
+    &mut self,
+    scope: ScopeId,
+    paths: &[ast::Path],
+    span: Option<Span>,
+  ) -> Vec<Path> {
+    paths
+      .iter()
+      .map(|p| self.resolve_path(scope, p, span))
+      .collect()
+  }
+  fn convert_cond_tags(&mut self, tags: &[(bool, ast::Ident)]) -> Vec<CondTag> {
+    tags
+      .iter()
+      .map(|(pos, t)| CondTag {
+        tag: self.add_ident(t),
+        negated: !pos,
+      })
+      .collect()
+  }
+  fn convert_grants(
+    &mut self,
+    scope: ScopeId,
+    grants: &ModVec<(bool, ast::Path)>,
+    span: Option<Span>,
+  ) -> Result<Vec<Grant>, CompileError> {
+    Ok(
+      new_list(grants, span)?
+        .iter()
+        .map(|(pos, item)| Grant {
+          item: self.resolve_path(scope, item, span),
+          negated: !pos,
+        })
+        .collect(),
+    )
+  }
+}
 
-  fn add_ident(&mut self, i: &ast::Ident) -> Ident {
-    Ident(self.idents.get_or_intern(&*i.0))
+fn link_dir(dir: &ast::LinkDir) -> LinkDir {
+  match dir {
+    ast::LinkDir::To => LinkDir::To,
+    ast::LinkDir::From => LinkDir::From,
+    ast::LinkDir::With => LinkDir::With,
   }
-  // add_name adds an identifier into the interning cache and returns a Name
-  // from it. It does not set the human name; that must be done manually
-  // during the second pass.
-  fn add_name(&mut self, n: &ast::DeclName) -> Name {
-    Name {
-      ident: self.add_ident(&n.ident),
-      human: None,
+}
+
+// The kind of entity a declaration declares, if it declares just one.
+fn decl_kind(d: &Decl) -> EntityKind {
+  match d {
+    Decl::Region(_) => EntityKind::Region,
+    Decl::Link(_) => EntityKind::Link,
+    Decl::Item(_) | Decl::Items(_) => EntityKind::Item,
+    Decl::Location(_) | Decl::Locations(_) => EntityKind::Location,
+    Decl::Fn(_) => EntityKind::Function,
+    Decl::Enum(_) => EntityKind::Enum,
+    Decl::Config(_) | Decl::ConfigEnum(_) | Decl::Configs(_) => EntityKind::Config,
+    Decl::Configset(_) => EntityKind::Configset,
+    Decl::Random(_) => EntityKind::Random,
+  }
+}
+
+// The changes a list makes to the one it modifies: every element of a plain
+// list is added, and a modifier list, which is only allowed in a modifying
+// declaration, can remove elements as well.
+fn list_changes<T>(
+  v: &ModVec<T>,
+  modifying: bool,
+  span: Option<Span>,
+) -> Result<Vec<(bool, &T)>, CompileError> {
+  match v {
+    ModVec::New(v) => Ok(v.iter().map(|t| (true, t)).collect()),
+    ModVec::Mod(v) if modifying => Ok(v.iter().map(|(pos, t)| (*pos, t)).collect()),
+    ModVec::Mod(_) => Err(CompileError::UnexpectedModifierList { span }),
+  }
+}
+
+// Collect everything inside a region, at any depth, given the contents of each
+// region as found by Program::region_contents.
+pub(crate) fn contained(contents: &HashMap<Id, Vec<EntityId>>, r: Id, found: &mut Vec<EntityId>) {
+  let mut regions = vec![r];
+  while let Some(r) = regions.pop() {
+    for &e in contents.get(&r).into_iter().flatten() {
+      found.push(e);
+      if let EntityId::Region(inner) = e {
+        regions.push(inner);
+      }
     }
   }
+}
 
-  fn add_tag_vec(&mut self, tags: &ModVec<ast::Ident>) -> Result<(), Error> {
-    for t in match tags {
-      ModVec::New(v) => either::Left(v.iter()),
-      ModVec::Mod(v) => either::Right(v.iter().map(|p| &p.1)),
-    } {
-      self.add_tag(&t)?;
+fn prop_not_allowed(p: &Prop, context: StmtContext, span: Option<Span>) -> CompileError {
+  CompileError::PropNotAllowed {
+    keyword: p.keyword(),
+    context,
+    span,
+  }
+}
+
+fn decl_not_allowed(context: StmtContext, span: Option<Span>) -> CompileError {
+  CompileError::DeclNotAllowed { context, span }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use anyhow::Error;
+
+  fn load(src: &str) -> Result<Program, Error> {
+    Ok(Program::from_ast(ast::parse_file(src)?)?)
+  }
+
+  // Load the named alttp samples together as a single program, since they
+  // refer to each other.
+  // The samples link into the Dark World, whose logic is yet to be written.
+  // Tests stand in an empty one so that the links resolve.
+  const DARK_WORLD: &str = "region DarkWorld { region Eastern { } }";
+
+  fn load_samples(names: &[&str]) -> Program {
+    let mut stmts = Vec::new();
+    for n in names {
+      let path = format!("{}/samples/alttp/{}.rado", env!("CARGO_MANIFEST_DIR"), n);
+      let src = std::fs::read_to_string(path).unwrap();
+      stmts.extend(ast::parse_file(&src).unwrap().stmts);
+    }
+    if names.contains(&"regions") {
+      stmts.extend(ast::parse_file(DARK_WORLD).unwrap().stmts);
+    }
+    Program::from_ast(ast::File { stmts }).unwrap_or_else(|d| panic!("{}", d))
+  }
+
+  #[test]
+  fn sample_dark_world_missing() {
+    let mut stmts = Vec::new();
+    for n in &["config", "items", "regions"] {
+      let path = format!("{}/samples/alttp/{}.rado", env!("CARGO_MANIFEST_DIR"), n);
+      let src = std::fs::read_to_string(path).unwrap();
+      stmts.extend(ast::parse_file(&src).unwrap().stmts);
+    }
+    let errors = match Program::from_ast(ast::File { stmts }) {
+      Ok(_) => panic!("expected the link into the Dark World not to resolve"),
+      Err(d) => d.errors,
+    };
+    assert!(matches!(
+      &errors[..],
+      [CompileError::NotFound { path, .. }] if path == "DarkWorld"
+    ));
+  }
+
+  #[test]
+  fn sample_original_names() {
+    // The samples were first written with these names, which name nothing.
+    let originals = [
+      ("Skills.DarkRooms", "Modes.DarkRooms"),
+      ("requires Glove }", "requires Gloves }"),
+      ("requires HyruleCastle.RescueZelda", "requires RescueZelda"),
+      ("Agahnim1 =>", "Agahnim =>"),
+      ("    SilverlessGanon default false\n", ""),
+    ];
+    let mut stmts = Vec::new();
+    for n in &["config", "items", "regions"] {
+      let path = format!("{}/samples/alttp/{}.rado", env!("CARGO_MANIFEST_DIR"), n);
+      let mut src = std::fs::read_to_string(path).unwrap();
+      for (name, original) in &originals {
+        src = src.replace(name, original);
+      }
+      stmts.extend(ast::parse_file(&src).unwrap().stmts);
+    }
+    stmts.extend(ast::parse_file(DARK_WORLD).unwrap().stmts);
+    let errors = match Program::from_ast(ast::File { stmts }) {
+      Ok(_) => panic!("expected the original names not to resolve"),
+      Err(d) => d.errors,
+    };
+    let paths: Vec<_> = errors
+      .iter()
+      .map(|e| match e {
+        CompileError::NotFound { path, .. } => path.as_str(),
+        e => panic!("unexpected error {}", e),
+      })
+      .collect();
+    assert_eq!(
+      vec![
+        "Agahnim",
+        "Skills.SilverlessGanon",
+        "Modes.DarkRooms",
+        "Modes.DarkRooms",
+        "Gloves",
+        "RescueZelda",
+      ],
+      paths
+    );
+  }
+
+  // Look up a dotted path from the global scope.
+  fn get(p: &Program, path: &str) -> EntityId {
+    let segs = path
+      .split('.')
+      .map(|s| Ident(p.idents.get(s).unwrap()))
+      .collect();
+    p.lookup_entity(p, &Path::new(segs).unwrap()).unwrap()
+  }
+
+  fn ident(p: &Program, s: &str) -> Ident {
+    Ident(p.idents.get(s).unwrap())
+  }
+
+  fn compile_errs(src: &str) -> Vec<CompileError> {
+    match Program::from_ast(ast::parse_file(src).unwrap()) {
+      Ok(_) => panic!("expected error loading {:?}", src),
+      Err(d) => d.errors,
+    }
+  }
+
+  fn compile_err(src: &str) -> CompileError {
+    compile_errs(src).remove(0)
+  }
+
+  #[test]
+  fn sample_config() {
+    let p = load_samples(&["config"]);
+    let state = &p.configs[get(&p, "Modes.State").unwrap_config().0];
+    let e = match state.ty {
+      ConfigTy::Enum(e) => &p.enums[e.0],
+      _ => panic!("State is not a config-enum"),
+    };
+    assert_eq!(
+      vec![get(&p, "Modes.Standard"), get(&p, "Modes.Open")],
+      e.variants
+        .iter()
+        .map(|v| EntityId::Variant(*v))
+        .collect::<Vec<_>>()
+    );
+    assert!(state.default.is_some());
+
+    let water_walk = &p.configs[get(&p, "Skills.WaterWalk").unwrap_config().0];
+    assert!(matches!(water_walk.ty, ConfigTy::Declared(Ty::Bool)));
+    let major = &p.configsets[get(&p, "Skills.MajorGlitches").unwrap_configset().0];
+    assert_eq!(1, major.vals.len());
+    assert_eq!(
+      vec![get(&p, "Skills.MinorGlitches").unwrap_configset()],
+      major.configsets
+    );
+    let expanded = major
+      .expansion
+      .iter()
+      .map(|(c, v)| (p.path_of(EntityId::Config(*c)), v.clone()))
+      .collect::<Vec<_>>();
+    assert_eq!(6, expanded.len());
+    for c in &["Screenwraps", "WaterWalk", "Hover"] {
+      let c = format!("Skills.{}", c);
+      assert!(expanded.contains(&(c.clone(), Value::Bool(true))), "{}", c);
+    }
+    assert!(p.regions[get(&p, "Skills").unwrap_region().0]
+      .props
+      .requires
+      .is_some());
+  }
+
+  #[test]
+  fn sample_items() {
+    let p = load_samples(&["config", "items"]);
+    let c5 = &p.items[get(&p, "Crystal5").unwrap_item().0];
+    for t in &["Crystal56", "Crystal", "Prize"] {
+      assert!(c5.tags.contains(&ident(&p, t)), "missing tag {}", t);
     }
+    assert_eq!(EntityId::Tag(ident(&p, "Crystal56")), get(&p, "Crystal56"));
+
+    let sword = &p.items[get(&p, "ProgressiveSword").unwrap_item().0];
+    assert_eq!(4, sword.progressive.as_ref().unwrap().len());
+    let bow = &p.items[get(&p, "BowWithArrows").unwrap_item().0];
+    assert_eq!(Some("Bow with Arrows"), bow.name.human.as_deref());
+    assert_eq!(2, bow.provides.len());
+    assert!(bow.tags.contains(&ident(&p, "Weapon")));
+    let f = &p.functions[get(&p, "CanDefeat").unwrap_function().0];
+    assert_eq!(1, f.params.len());
+  }
+
+  #[test]
+  fn sample_regions() {
+    let p = load_samples(&["config", "items", "regions"]);
+    let castle = get(&p, "LightWorld.HyruleCastle").unwrap_region();
+    assert_eq!(
+      1,
+      p.conds
+        .values()
+        .filter(|c| c.parent == ScopeId::Region(castle))
+        .count()
+    );
+    assert_eq!(
+      2,
+      p.conds
+        .values()
+        .filter(|c| c.parent == ScopeId::Global)
+        .count()
+    );
+    assert!(p.start_in.is_none());
+
+    let key = &p.items[get(&p, "LightWorld.HyruleCastle.SmallKey").unwrap_item().0];
+    assert!(key.consumable);
+    let upper = &p.regions[get(&p, "LightWorld.HyruleCastle.Basement.Upper")
+      .unwrap_region()
+      .0];
+    assert_eq!(1, upper.props.avail.len());
+    assert_eq!(
+      Some(ast::Num::from_integer(1.into())),
+      upper.props.avail[0].count
+    );
+
+    let links = p
+      .links
+      .values()
+      .filter(|l| l.parent == ScopeId::Region(castle))
+      .collect::<Vec<_>>();
+    assert_eq!(2, links.len());
+    assert!(links
+      .iter()
+      .all(|l| l.name.is_none() && l.requires.is_some()));
+    assert_eq!(LinkDir::With, links[0].dir);
+  }
+
+  #[test]
+  fn all_samples() {
+    let p = load_samples(&["config", "items", "regions"]);
+    assert!(matches!(get(&p, "Modes.Keysanity"), EntityId::Config(_)));
+    assert!(matches!(get(&p, "Boss"), EntityId::Enum(_)));
+    assert!(matches!(get(&p, "Event"), EntityId::Tag(_)));
+    let sword = get(&p, "MasterSword");
+    assert_eq!(sword, get(&p, "LightSaber"));
+    assert_eq!(
+      vec![ident(&p, "L2Sword"), ident(&p, "LightSaber")],
+      p.aliases_of(sword)
+    );
+  }
+
+  #[test]
+  fn aliases() -> Result<(), Error> {
+    let p = load(
+      "region R { alias S; location L { alias M } link K with R { alias J } }
+      item I { alias A, B }
+      fn F = A and not B and I",
+    )?;
+    assert_eq!(get(&p, "R"), get(&p, "S"));
+    assert_eq!(get(&p, "S.L"), get(&p, "R.M"));
+    assert_eq!(get(&p, "R.K"), get(&p, "R.J"));
+    assert_eq!(
+      vec![ident(&p, "A"), ident(&p, "B")],
+      p.aliases_of(get(&p, "I"))
+    );
+    assert!(p.aliases_of(get(&p, "F")).is_empty());
+
+    assert!(matches!(
+      compile_err("item I { alias J } item J"),
+      CompileError::DuplicateName { name, existing: EntityId::Item(_), .. } if name == "J"
+    ));
+    assert!(matches!(
+      compile_err("item I region R { item J { alias I } }"),
+      CompileError::ShadowedName { name, .. } if name == "I"
+    ));
+    assert!(matches!(
+      compile_err("item I { alias I }"),
+      CompileError::DuplicateName { .. }
+    ));
+    assert!(matches!(
+      compile_err("alias A"),
+      CompileError::PropNotAllowed {
+        keyword: "alias",
+        context: StmtContext::Global,
+        ..
+      }
+    ));
     Ok(())
   }
-  fn add_tag(&mut self, tag: &ast::Ident) -> Result<(), Error> {
-    let t = self.add_ident(tag);
-    let mut to_check = vec![&**self as &dyn Scope];
-    while let Some(s) = to_check.pop() {
-      for (n, e) in s.children() {
-        if *n == t {
-          if e == &EntityId::Tag(t) {
-            return Ok(());
-          }
-          return Err(anyhow!("tag declared with same name as entity"));
-        }
-        #[allow(clippy::single_match)]
-        match e {
-          EntityId::Region(r) => to_check.push(self.regions.get(r.0).unwrap()),
-          _ => {}
-        }
+
+  #[test]
+  fn named_links_are_declared() {
+    let p = load("region A { link L \"The Link\" with B } region B {}").unwrap();
+    let l = &p.links[get(&p, "A.L").unwrap_link().0];
+    assert_eq!(Some("The Link"), l.name.as_ref().unwrap().human.as_deref());
+  }
+
+  #[test]
+  fn declaration_errors() {
+    for src in &[
+      "region R { requires true; requires false }",
+      "region R { consumable }",
+      "region R { start in R }",
+      "item I { visible true }",
+      "item I { item J }",
+      "location L { location M }",
+      "enum E { A B } item A",
+      "enum E { A A }",
+      "config C : enum { X } enum C { Y }",
+      "config C : enum { C }",
+      "fn F(a, a) = a",
+      "random R = +[1]",
+      "items +[A] { I }",
+      "modify item I {}",
+      "item I region R { item I }",
+    ] {
+      assert!(load(src).is_err(), "expected error loading {:?}", src);
+    }
+  }
+
+  #[test]
+  fn errors_carry_names_and_spans() {
+    match compile_err("region A {}\nregion A {}") {
+      CompileError::DuplicateName {
+        name,
+        existing,
+        span,
+      } => {
+        assert_eq!("A", name);
+        assert!(matches!(existing, EntityId::Region(_)));
+        assert_eq!(Some(Span { start: 19, end: 20 }), span);
       }
+      e => panic!("wrong error: {:?}", e),
     }
-    self.global_decls.insert(t, EntityId::Tag(t));
+    assert!(matches!(
+      compile_err("item I region R { item I }"),
+      CompileError::ShadowedName { name, existing: EntityId::Item(_), .. } if name == "I"
+    ));
+    assert!(matches!(
+      compile_err("item T items T { I }"),
+      CompileError::TagCollision { name, existing: EntityId::Item(_) } if name == "T"
+    ));
+    assert!(matches!(
+      compile_err("item I { visible true }"),
+      CompileError::PropNotAllowed {
+        keyword: "visible",
+        context: StmtContext::Item,
+        span: Some(Span { start: 5, end: 6 }),
+      }
+    ));
+    assert!(matches!(
+      compile_err("if true {} modify item I {}"),
+      CompileError::OverrideOutsideConditional { span: Some(_) }
+    ));
+  }
+
+  #[test]
+  fn errors_are_accumulated() {
+    let errs = compile_errs(
+      "region A {}\nregion A { item X }\nitem I { visible true; consumable; consumable }\nitem J",
+    );
+    assert_eq!(3, errs.len(), "{:?}", errs);
+    assert!(matches!(errs[0], CompileError::DuplicateName { .. }));
+    assert!(matches!(
+      errs[1],
+      CompileError::PropNotAllowed {
+        keyword: "visible",
+        ..
+      }
+    ));
+    assert!(matches!(
+      errs[2],
+      CompileError::DuplicateProp {
+        keyword: "consumable",
+        ..
+      }
+    ));
+
+    // A region which could not be declared is skipped along with its contents.
+    assert_eq!(1, compile_errs("region A {} region A { item A }").len());
+  }
+
+  #[test]
+  fn warnings() -> Result<(), Error> {
+    let p = load("region R { if true {} }")?;
+    assert!(matches!(
+      p.warnings(),
+      [CompileWarning::EmptyConditional { span: Some(_) }]
+    ));
+    let p = load("items T { I { tag T, U } }")?;
+    assert!(matches!(
+      p.warnings(),
+      [CompileWarning::RedundantTag { item, tag, .. }] if item == "I" && tag == "T"
+    ));
+    assert!(load("item I { tag T }")?.warnings().is_empty());
     Ok(())
   }
 
-  fn validate_name_collisions(&self, s: ScopeId, n: Ident) -> Result<(), Error> {
-    if let Some(e) = self.lookup(self.get_scope(s).unwrap(), n) {
-      if self.get_entity(e).unwrap().parent() == s {
-        Err(anyhow!("name already declared in same scope"))
-      } else {
-        Err(anyhow!("name shadows entity in higher scope"))
+  #[test]
+  fn names_are_resolved() -> Result<(), Error> {
+    let p = load(
+      "region Modes { config State : enum { A B } default A }
+      item Lamp
+      region R { requires Lamp or Modes.State == Modes.B }
+      enum E { X Y }
+      config C : E
+      fn F(x: E) = match x { X => 1, Y => count(Lamp) }",
+    )?;
+    let r = &p.regions[get(&p, "R").unwrap_region().0];
+    let lamp = get(&p, "Lamp");
+    match r.props.requires.as_ref().unwrap() {
+      Expr::Or(es) => {
+        assert!(matches!(es[0], Expr::Entity(e) if e == lamp));
+        assert!(matches!(
+          &es[1],
+          Expr::Bin(l, ast::BinOp::Eq, r)
+            if matches!(**l, Expr::Entity(EntityId::Config(_)))
+              && matches!(**r, Expr::Entity(EntityId::Variant(_)))
+        ));
       }
-    } else {
-      Ok(())
+      e => panic!("wrong expression: {:?}", e),
     }
+
+    let e = get(&p, "E").unwrap_enum();
+    let c = &p.configs[get(&p, "C").unwrap_config().0];
+    assert!(matches!(c.ty, ConfigTy::Declared(Ty::Enum(id)) if id == e));
+    let f = &p.functions[get(&p, "F").unwrap_function().0];
+    assert_eq!(&Ty::Enum(e), f.params[0].ty());
+    match f.body() {
+      Expr::Match(x, arms) => {
+        assert!(matches!(**x, Expr::Param(0)));
+        assert_eq!(get(&p, "X"), EntityId::Variant(arms[0].variant));
+        assert!(matches!(
+          arms[1].expr,
+          Expr::Builtin(ast::Builtin::Count, _)
+        ));
+      }
+      e => panic!("wrong body: {:?}", e),
+    }
+    Ok(())
   }
 
-  fn build_scope(&mut self, scope: ScopeId, stmts: Vec<ast::Stmt>) -> Result<(), Error> {
-    for s in stmts {
-      match s {
-        Stmt::Decl(Decl::Region(region)) => {
-          let id = self
-            .lookup_ast(self.get_scope(scope).unwrap(), &region.name.ident)
-            .unwrap_region();
-          self.build_region(id, region)?;
+  #[test]
+  fn resolution_errors() -> Result<(), Error> {
+    assert!(matches!(
+      &compile_errs("item I { max A + I.B }")[..],
+      [
+        CompileError::NotFound { path: a, span: Some(Span { start: 5, end: 6 }) },
+        CompileError::NotAScope { path: i, entity: EntityId::Item(_), .. },
+      ] if a == "A" && i == "I"
+    ));
+    assert!(matches!(
+      compile_err("region R { location L { requires R.M } }"),
+      CompileError::NotFound { path, span: Some(_) } if path == "R.M"
+    ));
+    assert!(matches!(
+      compile_err("item I fn F(x) = match x { I => true }"),
+      CompileError::NotAVariant { path, entity: EntityId::Item(_), .. } if path == "I"
+    ));
+    assert!(matches!(
+      compile_err("item I config C : I"),
+      CompileError::NotAType { path, entity: EntityId::Item(_), .. } if path == "I"
+    ));
+    // Parameters are only visible in their function's body.
+    assert!(matches!(
+      compile_err("fn F(x) = x fn G = x"),
+      CompileError::NotFound { path, .. } if path == "x"
+    ));
+    assert!(matches!(
+      compile_err("if Nope {}"),
+      CompileError::NotFound { path, .. } if path == "Nope"
+    ));
+    // So are the paths naming entities in properties.
+    let missing = |src| {
+      let errs = compile_errs(src);
+      let paths = errs.into_iter().map(|e| match e {
+        CompileError::NotFound { path, .. } => path,
+        e => panic!("unexpected error {}", e),
+      });
+      paths.collect::<Vec<_>>()
+    };
+    assert_eq!(
+      vec!["Lamp", "Key", "Sword", "Boots", "Start"],
+      missing(
+        "region R { unlock Lamp avail Key grants Sword }
+        item Bow { provides Boots }
+        start in Start"
+      )
+    );
+    assert_eq!(
+      vec!["Bows", "R.Nope"],
+      missing("item Bow { progressive Bows } region R { } start with R.Nope")
+    );
+    load("region R { item Key avail Key link to R { unlock Key } } start with R.Key")?;
+    Ok(())
+  }
+
+  #[test]
+  fn types_are_inferred() -> Result<(), Error> {
+    let p = load(
+      "fn F(x) = x + 1
+      fn G(b, l) = not b or count(l) > 0
+      fn H = F(2)
+      fn K = if H > 1 then Modes.A else Modes.B
+      region Modes { config M : enum { A B } default K }
+      item I { val V = 3 }
+      items T { J }
+      location L { requires G(I, T) and not G(true, T) }",
+    )?;
+    let f = |name| &p.functions[get(&p, name).unwrap_function().0];
+    assert_eq!(&Ty::Num, f("F").params[0].ty());
+    assert_eq!(&Ty::Num, f("F").ret_ty());
+    assert_eq!(&Ty::Bool, f("G").params[0].ty());
+    assert_eq!(&Ty::List(Box::new(Ty::Item)), f("G").params[1].ty());
+    assert_eq!(&Ty::Num, f("H").ret_ty());
+    assert!(matches!(f("K").ret_ty(), Ty::Enum(_)));
+    let i = &p.items[get(&p, "I").unwrap_item().0];
+    assert_eq!(&Ty::Int, i.vals[&ident(&p, "V")].ty());
+    Ok(())
+  }
+
+  #[test]
+  fn type_errors() {
+    let mismatch = |src, e: &str, f: &str| match compile_err(src) {
+      CompileError::TypeMismatch {
+        expected, found, ..
+      } => assert_eq!((e, f), (&expected[..], &found[..]), "{}", src),
+      err => panic!("wrong error for {:?}: {:?}", src, err),
+    };
+    mismatch("item Bow fn F = Bow + 1", "num", "item");
+    mismatch("location L { requires 1 }", "bool", "int");
+    mismatch("enum E { A } fn F = A == 1", "E", "int");
+    mismatch("config C : bool default 3", "bool", "int");
+    mismatch("item I { max true }", "int", "bool");
+    mismatch("if 2 {}", "bool", "int");
+    mismatch(
+      "enum E { A } item I fn F = match I { A => true }",
+      "E",
+      "item",
+    );
+    mismatch("fn F(x: num) -> bool = x", "bool", "num");
+    mismatch("fn F(x) = x and x + 1 > 0", "num", "bool");
+    mismatch("fn F(b) = if b then 1 else true", "int", "bool");
+    assert!(matches!(
+      compile_err("region R {} item I { max R }"),
+      CompileError::NotAValue { name, span: Some(_) } if name == "R"
+    ));
+    assert!(matches!(
+      compile_err("fn F(x) = x + 1 fn G = F(1, 2) > 0"),
+      CompileError::WrongArgCount {
+        expected: 1,
+        found: 2,
+        ..
+      }
+    ));
+    assert!(matches!(
+      compile_err("item I fn F = I(1)"),
+      CompileError::NotAFunction { found, .. } if found == "item"
+    ));
+    assert!(matches!(
+      compile_err("fn F(x) = true"),
+      CompileError::CannotInfer { name, .. } if name == "F.x"
+    ));
+  }
+
+  #[test]
+  fn tags() -> Result<(), Error> {
+    let p = load(
+      "items Crystal { C1 { val Power = 1 } C2 { val Power = 2.5 } C3 }
+      item Other
+      fn Enough = count(Crystal) >= 7
+      fn Power = max(1, Crystal.Power)",
+    )?;
+    let crystal = ident(&p, "Crystal");
+    assert!(p.get_entity(EntityId::Tag(crystal)).is_some());
+    assert!(p.get_entity(EntityId::Tag(ident(&p, "Other"))).is_none());
+    assert!(p.get_entity(EntityId::Tag(ident(&p, "Power"))).is_none());
+    assert_eq!(
+      vec![get(&p, "C1"), get(&p, "C2"), get(&p, "C3")],
+      p.entities_with_tag(crystal).collect::<Vec<_>>()
+    );
+    let f = |name| &p.functions[get(&p, name).unwrap_function().0];
+    assert_eq!(&Ty::Bool, f("Enough").ret_ty());
+    assert_eq!(&Ty::Num, f("Power").ret_ty());
+    assert!(matches!(
+      f("Power").body(),
+      Expr::Builtin(_, args) if matches!(args[1], Expr::TagVals(t, _) if t == crystal)
+    ));
+
+    assert!(matches!(
+      compile_err("items T { A { val V = 1 } B { val V = true } }"),
+      CompileError::TagValMismatch { tag, val, .. } if tag == "T" && val == "V"
+    ));
+    // Only items can be tagged, so every entity with a tag has the same kind.
+    assert!(matches!(
+      compile_err("items T { A } location L { tag T }"),
+      CompileError::PropNotAllowed { .. }
+    ));
+    assert!(ast::parse_file("#[T] location L").is_err());
+    for src in &[
+      "items T { A } fn F = sum(T.V)",
+      "items T { A } item B { val V = 1 } fn F = sum(T.V)",
+    ] {
+      assert!(matches!(
+        compile_err(src),
+        CompileError::NoSuchVal { tag, val, .. } if tag == "T" && val == "V"
+      ));
+    }
+    Ok(())
+  }
+
+  #[test]
+  fn human_names() {
+    for (ident, human) in &[
+      ("MasterSword", "Master Sword"),
+      ("Lamp", "Lamp"),
+      ("Crystal5", "Crystal 5"),
+      ("Crystal56", "Crystal 56"),
+      ("DefeatAgahnim1", "Defeat Agahnim 1"),
+      ("L2Sword", "L2 Sword"),
+      ("HUDIcon", "HUD Icon"),
+      ("OpenHUD", "Open HUD"),
+      ("Level10Boss", "Level 10 Boss"),
+      ("Big_Key", "Big Key"),
+      ("canCast", "can Cast"),
+      ("ÉpéeDeFeu", "Épée De Feu"),
+    ] {
+      assert_eq!(*human, human_name(ident), "{}", ident);
+    }
+  }
+
+  #[test]
+  fn display_names() -> Result<(), Error> {
+    let p = load(
+      "region LinksHouse \"Link's House\" { link to DarkWorld }
+      region DarkWorld {}
+      items BigKeys { EasternBigKey }",
+    )?;
+    assert_eq!("Link's House", p.display_name(get(&p, "LinksHouse")));
+    assert_eq!("Dark World", p.display_name(get(&p, "DarkWorld")));
+    assert_eq!("Eastern Big Key", p.display_name(get(&p, "EasternBigKey")));
+    assert_eq!("Big Keys", p.display_name(get(&p, "BigKeys")));
+    let (id, _) = p.links.iter().next().unwrap();
+    assert_eq!(
+      "Link's House to Dark World",
+      p.display_name(EntityId::Link(Id(id)))
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn introspection() -> Result<(), Error> {
+    let p = load(
+      "region A {
+        alias AA
+        region B { item I { tag T } link to A link to A }
+        location L
+        if true {}
+      }
+      item J { tag T }",
+    )?;
+    let i = get(&p, "A.B.I");
+    assert_eq!("I", p.resolve(p.items[i.unwrap_item().0].name.ident));
+    assert_eq!("A.B.I", p.path_of(i));
+    assert_eq!("T", p.path_of(get(&p, "T")));
+    let links = p
+      .links()
+      .map(|(l, _)| EntityId::Link(l))
+      .collect::<Vec<_>>();
+    assert_eq!(
+      vec!["A.B.<link 1>", "A.B.<link 2>"],
+      links.iter().map(|l| p.path_of(*l)).collect::<Vec<_>>()
+    );
+    let (c, _) = p.conds().next().unwrap();
+    assert_eq!("A.<cond 1>", p.path_of(EntityId::Cond(c)));
+
+    assert_eq!(
+      vec!["I", "J"],
+      p.items()
+        .map(|(_, i)| p.resolve(i.name().ident))
+        .collect::<Vec<_>>()
+    );
+    assert_eq!(vec![ident(&p, "T")], p.tags().collect::<Vec<_>>());
+    assert_eq!(
+      vec![
+        get(&p, "A"),
+        get(&p, "A.B"),
+        i,
+        links[0],
+        links[1],
+        get(&p, "A.L"),
+        EntityId::Cond(c),
+        get(&p, "J"),
+        get(&p, "T"),
+      ],
+      p.walk().collect::<Vec<_>>()
+    );
+    assert_eq!(p.entities().count(), p.walk().count());
+    Ok(())
+  }
+
+  #[test]
+  fn entities() -> Result<(), Error> {
+    let p = load(
+      "region R \"The Region\" { link to R if true {} }
+      item I { tag T }
+      enum E { A }",
+    )?;
+    for e in p.walk() {
+      let entity = p.get_entity(e).unwrap();
+      assert_eq!(e.kind(), entity.kind());
+      match e {
+        EntityId::Region(r) => {
+          assert!(std::ptr::eq(&p.regions[r.0], entity.as_region().unwrap()));
+          assert_eq!(Some("The Region"), entity.name().unwrap().human.as_deref());
         }
-        Stmt::Decl(Decl::Item(item)) => {
-          let id = self
-            .lookup_ast(self.get_scope(scope).unwrap(), &item.name.ident)
-            .unwrap_item();
-          self.build_item(id, item, HashSet::new())?;
+        EntityId::Item(_) => {
+          assert!(entity.as_item().is_some() && entity.as_region().is_none());
+          assert_eq!(vec![ident(&p, "T")], entity.tags().collect::<Vec<_>>());
         }
-        Stmt::Decl(Decl::Items(items)) => self.build_items(items, scope, HashSet::new())?,
-        _ => unimplemented!(),
+        EntityId::Tag(t) => assert_eq!(Some(t), entity.name().map(|n| n.ident)),
+        EntityId::Link(_) | EntityId::Cond(_) => assert!(entity.name().is_none()),
+        _ => assert_eq!(0, entity.tags().count()),
       }
     }
+    assert_eq!("enum variant", get(&p, "A").kind().to_string());
     Ok(())
   }
 
-  fn build_region(&mut self, region: Id, input: ast::Region) -> Result<(), Error> {
-    self.build_scope(ScopeId::Region(region), input.stmts)?;
-    let region = self.regions.get_mut(region.into()).unwrap();
-    region.name.human = input.name.human;
+  #[test]
+  fn deterministic() {
+    let p = load_samples(&["config", "items", "regions"]);
+    let q = load_samples(&["config", "items", "regions"]);
+    let paths = |p: &Program| p.walk().map(|e| (e, p.path_of(e))).collect::<Vec<_>>();
+    assert_eq!(paths(&p), paths(&q));
+    let children = |p: &Program| p.children().map(|(i, e)| (*i, *e)).collect::<Vec<_>>();
+    assert_eq!(children(&p), children(&q));
+    let first = p.items().next().unwrap().1;
+    assert_eq!("Crystal1", p.resolve(first.name().ident));
+
+    // Errors are reported in the order they are found, without regard to
+    // hashing.
+    let src = "fn Id(x) = x item I { val V = Id; val W = Id; val U = Id }";
+    let names = compile_errs(src)
+      .into_iter()
+      .map(|e| match e {
+        CompileError::CannotInfer { name, .. } => name,
+        e => panic!("{}", e),
+      })
+      .collect::<Vec<_>>();
+    assert_eq!(vec!["Id.x", "Id", "I.V", "I.W", "I.U"], names);
+  }
+
+  #[test]
+  fn stable_ids() -> Result<(), Error> {
+    let p = load("region A { item I link to A }")?;
+    let q = load("item J region A { item H link to A item I link to A }")?;
+    let i = get(&p, "A.I");
+    assert_ne!(i, get(&q, "A.I"));
+    assert_eq!(p.stable_id(i), q.stable_id(get(&q, "A.I")));
+    assert_eq!("f560d7373886df6e", p.stable_id(i).to_string());
+    assert_eq!(Some(get(&q, "A.I")), q.entity_by_stable_id(p.stable_id(i)));
+    let (l, _) = p.links().next().unwrap();
+    let (m, _) = q.links().next().unwrap();
+    assert_eq!(
+      p.stable_id(EntityId::Link(l)),
+      q.stable_id(EntityId::Link(m))
+    );
+    assert_eq!(None, p.entity_by_stable_id(q.stable_id(get(&q, "J"))));
+
+    // Anonymous links are identified by what they link, so adding another
+    // before one leaves its id alone, and links which are the same are told
+    // apart by position.
+    let r = load("region A { item I link to B link to A } region B { }")?;
+    let link_ids = |p: &Program| {
+      p.links()
+        .map(|(l, _)| p.stable_id(EntityId::Link(l)))
+        .collect::<Vec<_>>()
+    };
+    assert_eq!(link_ids(&p)[0], link_ids(&r)[1]);
+    let q = link_ids(&q);
+    assert_eq!(link_ids(&p)[0], q[0]);
+    assert_ne!(q[0], q[1]);
+
+    // Conditional blocks have nothing but their position to identify them,
+    // so adding one shifts the ids of those after it.
+    let p = load("region A { if true { } }")?;
+    let q = load("region A { if false { } if true { } }")?;
+    let cond_ids = |p: &Program| {
+      p.conds()
+        .map(|(c, _)| p.stable_id(EntityId::Cond(c)))
+        .collect::<Vec<_>>()
+    };
+    assert_eq!(cond_ids(&p)[0], cond_ids(&q)[0]);
+    assert_ne!(cond_ids(&p)[0], cond_ids(&q)[1]);
+
+    let p = load_samples(&["config", "items", "regions"]);
+    for e in p.entities() {
+      assert_eq!(Some(e), p.entity_by_stable_id(p.stable_id(e)));
+    }
     Ok(())
   }
 
-  fn build_item(&mut self, item: Id, input: ast::Item, tags: HashSet<Ident>) -> Result<(), Error> {
-    let item = self.items.get_mut(item.into()).unwrap();
-    item.name.human = input.name.human;
-    item.tags = tags;
-    unimplemented!()
+  #[test]
+  fn shared_between_threads() {
+    let p = std::sync::Arc::new(load_samples(&["config", "items", "regions"]));
+    let threads = (0..4)
+      .map(|_| {
+        let p = p.clone();
+        std::thread::spawn(move || p.walk().map(|e| p.path_of(e)).collect::<Vec<_>>())
+      })
+      .collect::<Vec<_>>();
+    let expected = p.walk().map(|e| p.path_of(e)).collect::<Vec<_>>();
+    for t in threads {
+      assert_eq!(expected, t.join().unwrap());
+    }
   }
 
-  fn build_items(
-    &mut self,
-    items: ast::Items,
-    scope: ScopeId,
-    mut tags: HashSet<Ident>,
-  ) -> Result<(), Error> {
-    guard::guard!(let ModVec::New(t) = items.tags
-                      else { unimplemented!() });
-    tags.extend(t.into_iter().map(|tag| self.convert_ident(&tag)));
+  #[test]
+  fn configure() {
+    let p = load_samples(&["config", "items", "regions"]);
+    let config = |path| get(&p, path).unwrap_config();
+    let variant = |path| Value::Variant(get(&p, path).unwrap_variant());
 
-    for nested in items.nested {
-      self.build_items(nested, scope, tags.clone())?;
+    let c = p.configure(&ConfigValues::new()).unwrap();
+    assert_eq!(&variant("Modes.Standard"), c.value(config("Modes.State")));
+    assert_eq!(
+      &variant("Modes.UncleAssured"),
+      c.value(config("Modes.Swords"))
+    );
+    assert_eq!(&Value::Bool(false), c.value(config("Modes.Keysanity")));
+    assert_eq!(p.configs().count(), c.values().count());
+
+    // The same program can be configured again, as when a tracker switches
+    // modes.
+    let c = p
+      .configure(
+        ConfigValues::new()
+          .set("Modes.State", "Open")
+          .set("Skills.WaterWalk", true),
+      )
+      .unwrap();
+    assert_eq!(&variant("Modes.Open"), c.value(config("Modes.State")));
+    assert_eq!(&Value::Bool(true), c.value(config("Skills.WaterWalk")));
+
+    // A configured program can be kept after the program it came from is
+    // gone.
+    let owned = {
+      let q = load_samples(&["config", "items", "regions"]);
+      let c = q.configure(ConfigValues::new().set("Modes.State", "Open"));
+      c.unwrap().into_owned()
+    };
+    assert_eq!(&variant("Modes.Open"), owned.value(config("Modes.State")));
+    assert_eq!(p.entities().count(), owned.program().entities().count());
+  }
+
+  #[test]
+  fn configure_defaults() -> Result<(), Error> {
+    let p = load(
+      "region R {
+        config A : num default B * 2 + max(1, C)
+        config B : num default if D then 3 else 4
+        config C : num
+        config D : bool default F(C)
+      }
+      fn F(x) = x >= 10 and x % 4 == 2",
+    )?;
+    let config = |path| get(&p, path).unwrap_config();
+    let num = |n: i64| Value::Num(ast::Num::from_integer(n.into()));
+    let c = p.configure(ConfigValues::new().set("R.C", 10)).unwrap();
+    assert_eq!(&Value::Bool(true), c.value(config("R.D")));
+    assert_eq!(&num(16), c.value(config("R.A")));
+    let c = p.configure(ConfigValues::new().set("R.C", -2)).unwrap();
+    assert_eq!(&num(9), c.value(config("R.A")));
+    Ok(())
+  }
+
+  #[test]
+  fn config_errors() -> Result<(), Error> {
+    let p = load(
+      "region R {
+        config E : enum { X Y } default X
+        config B : bool default false
+        config N : num
+      }
+      config Cyc : bool default not Cyc
+      config Div : num default 1 / (R.N - 1)
+      item I
+      config Const : bool default I
+      fn Loop(x) = Loop(x)
+      config Rec : bool default Loop(1)",
+    )?;
+    let errs = |vals: &ConfigValues| p.configure(vals).err().unwrap().errors;
+    let e = errs(
+      ConfigValues::new()
+        .set("R.E", "Z")
+        .set("R.B", 1)
+        .set("R.Missing", true)
+        .set("I", true)
+        .set("R.N", 1),
+    );
+    let msgs = e.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+    assert_eq!(
+      vec![
+        "Z is not a variant of R.E",
+        "expected bool for R.B, found num",
+        "R.Missing is not a config",
+        "I is not a config",
+        "the default of Cyc depends on itself",
+        "the default of Div divides by zero",
+        "the default of Const is not a constant expression",
+        "the default of Rec recurses too deeply",
+      ],
+      msgs
+    );
+    assert!(matches!(
+      &errs(&ConfigValues::new())[0],
+      ConfigError::Missing { path } if path == "R.N"
+    ));
+    Ok(())
+  }
+
+  #[test]
+  fn configsets() -> Result<(), Error> {
+    let p = load(
+      "config A : bool default false
+      config B : bool default false
+      config N : num default 0
+      configset Low { N => 1 }
+      configset High { N => 2, A => true }
+      configset Both { Low, B => true }",
+    )?;
+    let val = |c: &ConfiguredProgram, path: &str| c.value(get(&p, path).unwrap_config()).clone();
+    let c = p.configure(ConfigValues::new().apply("Both").set("B", false))?;
+    assert_eq!(Value::Num(ast::Num::from_integer(1.into())), val(&c, "N"));
+    assert_eq!(Value::Bool(false), val(&c, "B"));
+
+    let e = p
+      .configure(ConfigValues::new().apply("Low").apply("High").apply("Nope"))
+      .err()
+      .unwrap()
+      .errors;
+    let msgs = e.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+    assert_eq!(
+      vec![
+        "configsets Low and High give N different values",
+        "Nope is not a configset",
+      ],
+      msgs
+    );
+
+    let errs = compile_errs(
+      "config M : num
+      config N : num
+      configset X { Y }
+      configset Y { Z, N => 1 }
+      configset Z { X }
+      configset C { N => 1, Low }
+      configset Low { N => 2 }
+      configset V { N => M }",
+    );
+    let msgs = errs.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+    assert_eq!(
+      vec![
+        "configset X includes itself",
+        "configset C gives N conflicting values",
+        "the value of N in V is not a constant expression",
+      ],
+      msgs
+    );
+    assert!(matches!(
+      compile_err("config A : bool configset W { A }"),
+      CompileError::NotAConfigset { path, .. } if path == "A"
+    ));
+    Ok(())
+  }
+
+  #[test]
+  fn config_files() -> Result<(), Error> {
+    let num = |s: &str| ConfigValue::Num(s.parse().unwrap());
+    let mut expected = ConfigValues::new();
+    expected
+      .set("Modes.State", "Open")
+      .set("Modes.Keysanity", true)
+      .set("Skills", "MinorGlitches")
+      .set("R.N", num("-3/2"))
+      .set("R.M", num("1/10"));
+    let json = ConfigValues::from_json(
+      r#"{"Modes.State": "Open", "Modes": {"Keysanity": true},
+          "Skills": "MinorGlitches", "R": {"N": -1.5, "M": "0.1"}}"#,
+    )?;
+    assert_eq!(expected, json);
+    let rson = ConfigValues::from_rson(
+      r#"{"Modes.State": "Open", "Modes.Keysanity": true,
+          "Skills": "MinorGlitches", "R.N": "-3/2", "R.M": 0.1}"#,
+    )?;
+    assert_eq!(expected, rson);
+    let toml = ConfigValues::from_toml(
+      r#"Skills = "MinorGlitches"
+        "Modes.State" = "Open"
+        [Modes]
+        Keysanity = true
+        [R]
+        N = "-6/4"
+        M = 0.1"#,
+    )?;
+    // TOML puts bare keys before tables, whatever order they were written in.
+    let sorted = |vals: &ConfigValues| {
+      let mut vals = vals
+        .iter()
+        .map(|(p, v)| (p.to_owned(), v.clone()))
+        .collect::<Vec<_>>();
+      vals.sort_by(|a, b| a.0.cmp(&b.0));
+      vals
+    };
+    assert_eq!(sorted(&expected), sorted(&toml));
+
+    for bad in &[
+      r#"{"A": "1/0"}"#,
+      r#"{"A": "-x"}"#,
+      r#"{"A": [true]}"#,
+      r#"{"A.B": 1, "A": {"B": 2}}"#,
+      r#"{"A..B": 1}"#,
+      "true",
+    ] {
+      assert!(ConfigValues::from_json(bad).is_err(), "{}", bad);
     }
-    for item in items.items {
-      let id = self
-        .lookup_ast(self.get_scope(scope).unwrap(), &item.name.ident)
-        .unwrap_item();
-      self.build_item(id, item, tags.clone())?;
+
+    let p = load_samples(&["config"]);
+    let c = p.configure(&ConfigValues::from_json(
+      r#"{"Modes": {"State": "Open"}, "Skills": "MajorGlitches"}"#,
+    )?)?;
+    let config = |path| c.value(get(&p, path).unwrap_config());
+    assert_eq!(
+      &Value::Variant(get(&p, "Modes.Open").unwrap_variant()),
+      config("Modes.State")
+    );
+    assert_eq!(&Value::Bool(true), config("Skills.Screenwraps"));
+    assert_eq!(&Value::Bool(true), config("Skills.Hover"));
+    let e = p
+      .configure(&ConfigValues::from_json(
+        r#"{"Skills": "Nope", "Modes.Keysanity": 1, "Modes.State": "Closed"}"#,
+      )?)
+      .err()
+      .unwrap()
+      .errors;
+    let msgs = e.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+    assert_eq!(
+      vec![
+        "Skills.Nope is not a configset",
+        "expected bool for Modes.Keysanity, found num",
+        "Closed is not a variant of Modes.State",
+      ],
+      msgs
+    );
+    Ok(())
+  }
+
+  #[test]
+  fn config_schema() -> Result<(), Error> {
+    let schema = load_samples(&["config"]).config_schema();
+    let props = &schema["properties"];
+    assert_eq!(
+      serde_json::json!({
+        "title": "Swords",
+        "oneOf": [
+          { "const": "Swordless", "title": "Swordless" },
+          { "const": "UncleAssured", "title": "Uncle Assured" },
+          { "const": "RandomSwords", "title": "Random Swords" },
+        ],
+        "default": "UncleAssured",
+      }),
+      props["Modes.Swords"]
+    );
+    assert_eq!(
+      serde_json::json!({ "type": "boolean", "title": "Water Walk", "default": false }),
+      props["Skills.WaterWalk"]
+    );
+    assert_eq!(
+      serde_json::json!(["MinorGlitches", "MajorGlitches"]),
+      props["Skills"]["oneOf"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| c["const"].clone())
+        .collect::<serde_json::Value>()
+    );
+    let major = &schema["x-presets"]["Skills.MajorGlitches"];
+    assert_eq!(6, major.as_object().unwrap().len());
+    assert_eq!(serde_json::json!(true), major["Skills.Screenwraps"]);
+
+    let p = load(
+      "config N : num
+      config H : num default 1/2
+      config D : num default N + 1",
+    )?;
+    let schema = p.config_schema();
+    assert_eq!(serde_json::json!(["N"]), schema["required"]);
+    assert_eq!(
+      serde_json::json!("1/2"),
+      schema["properties"]["H"]["default"]
+    );
+    assert!(schema["properties"]["D"].get("default").is_none());
+
+    // Titles are display names, so declared human names are used.
+    let p = load("config S \"Game Mode\" : enum { Std \"Standard Mode\" L2Sword HUDIcon }")?;
+    let schema = p.config_schema();
+    let titles = schema["properties"]["S"]["oneOf"]
+      .as_array()
+      .unwrap()
+      .iter();
+    assert_eq!(
+      vec!["Standard Mode", "L2 Sword", "HUD Icon"],
+      titles
+        .map(|c| c["title"].as_str().unwrap())
+        .collect::<Vec<_>>()
+    );
+    assert_eq!("Game Mode", schema["properties"]["S"]["title"]);
+    Ok(())
+  }
+
+  #[test]
+  fn disallowed_values() -> Result<(), Error> {
+    let p = load(
+      "config Swords : enum { Swordless Assured Random } default Assured {
+        disallow Swordless
+      }
+      enum E { X Y Z }
+      config C : E default X { disallow Y; disallow Z }
+      configs : E { C1 default Y { disallow X } C2 default X }",
+    )?;
+    let disallowed = |path| {
+      let c = &p.configs[get(&p, path).unwrap_config().0];
+      let vs = c.disallowed().iter().map(|v| p.variants[v.0].name.ident);
+      vs.map(|i| p.resolve(i)).collect::<Vec<_>>()
+    };
+    assert_eq!(vec!["Swordless"], disallowed("Swords"));
+    assert_eq!(vec!["Y", "Z"], disallowed("C"));
+    assert_eq!(vec!["X"], disallowed("C1"));
+    assert!(disallowed("C2").is_empty());
+
+    p.configure(&ConfigValues::new())?;
+    let errs = p
+      .configure(
+        ConfigValues::new()
+          .set("Swords", "Swordless")
+          .set("C1", "X"),
+      )
+      .err()
+      .unwrap();
+    assert_eq!(
+      vec![
+        "Swordless is disallowed for Swords",
+        "X is disallowed for C1"
+      ],
+      errs
+        .errors
+        .iter()
+        .map(|e| e.to_string())
+        .collect::<Vec<_>>()
+    );
+    let p = load("config C : enum { A B } default A { disallow A }")?;
+    assert!(matches!(
+      &p.configure(&ConfigValues::new()).err().unwrap().errors[..],
+      [ConfigError::Disallowed { path, variant }] if path == "C" && variant == "A"
+    ));
+    assert!(matches!(
+      compile_err(
+        "region R { config C : enum { A B } default B { disallow A } configset S { C => A } }"
+      ),
+      CompileError::ConfigsetValue {
+        error: ConfigError::Disallowed { .. },
+        ..
+      }
+    ));
+
+    // Disallowed variants cannot be chosen in a settings form.
+    let schema =
+      load("config M : enum { A B C } default B { disallow A; disallow C }")?.config_schema();
+    assert_eq!(
+      serde_json::json!({
+        "title": "M",
+        "oneOf": [{ "const": "B", "title": "B" }],
+        "x-disallowed": [{ "const": "A", "title": "A" }, { "const": "C", "title": "C" }],
+        "default": "B",
+      }),
+      schema["properties"]["M"]
+    );
+
+    assert!(matches!(
+      compile_err("config B : bool default true { disallow X }"),
+      CompileError::DisallowNotEnum { config, .. } if config == "B"
+    ));
+    assert!(matches!(
+      compile_err("config C : enum { A B } default A { disallow Nope }"),
+      CompileError::UnknownVariant { config, variant, .. } if config == "C" && variant == "Nope"
+    ));
+    assert!(matches!(
+      compile_err("config C : enum { A B } { disallow A disallow B }"),
+      CompileError::AllDisallowed { config, .. } if config == "C"
+    ));
+    assert!(matches!(
+      compile_err("config C : enum { A B } { requires true }"),
+      CompileError::PropNotAllowed {
+        keyword: "requires",
+        context: StmtContext::Config,
+        ..
+      }
+    ));
+    assert!(matches!(
+      compile_err("location L { disallow A }"),
+      CompileError::PropNotAllowed {
+        keyword: "disallow",
+        ..
+      }
+    ));
+    Ok(())
+  }
+
+  #[test]
+  fn conditional_blocks() -> Result<(), Error> {
+    let p = load_samples(&["config", "items", "regions"]);
+    assert!(p.start_in.is_none());
+    let path = |c: &ConfiguredProgram| {
+      let start_in = c.program().start_in.as_ref().unwrap();
+      let segs = start_in.0.iter().map(|i| p.resolve(*i)).collect::<Vec<_>>();
+      segs.join(".")
+    };
+    let c = p.configure(&ConfigValues::new())?;
+    assert_eq!("LightWorld.SecretPassage", path(&c));
+    let c = p.configure(ConfigValues::new().set("Modes.State", "Open"))?;
+    assert_eq!("LightWorld", path(&c));
+    let castle = get(&p, "LightWorld.HyruleCastle").unwrap_region();
+    let start_with = c.program().regions[castle.0].props.start_with();
+    assert_eq!(2, start_with.len());
+
+    let p = load(
+      "config Hard : bool default false
+      fn IsHard = Hard
+      region R {
+        if IsHard {
+          item Extra
+          if not Hard { item Never }
+        } else {
+          item Easy
+          if not Hard { item Easier }
+        }
+      }",
+    )?;
+    let items = |vals: &ConfigValues| -> Result<Vec<String>, Error> {
+      let c = p.configure(vals)?;
+      let p = c.program();
+      Ok(
+        p.items()
+          .map(|(id, _)| p.path_of(EntityId::Item(id)))
+          .collect(),
+      )
+    };
+    assert_eq!(vec!["R.Easy", "R.Easier"], items(&ConfigValues::new())?);
+    assert_eq!(
+      vec!["R.Extra"],
+      items(ConfigValues::new().set("Hard", true))?
+    );
+
+    // Programs without conditional blocks are not copied.
+    let p = load("config A : bool default true")?;
+    assert!(std::ptr::eq(
+      &p,
+      p.configure(&ConfigValues::new())?.program()
+    ));
+
+    assert!(matches!(
+      compile_err("item I if I { item J }"),
+      CompileError::NonConstantCondition { .. }
+    ));
+    assert!(matches!(
+      compile_err("config C : bool default true if (if C then true else count(I) > 0) { } item I"),
+      CompileError::NonConstantCondition { .. }
+    ));
+    assert!(matches!(
+      compile_err("if true { region R { if false { config C : bool } } }"),
+      CompileError::ConfigInConditional { .. }
+    ));
+
+    let p = load(
+      "config C : bool default true
+      item A
+      if C { item A }
+      if not C { item B if B { } }",
+    )?;
+    let e = p.configure(&ConfigValues::new()).err().unwrap().errors;
+    assert_eq!(1, e.len());
+    assert_eq!(
+      "in a conditional block: A is already declared in the same scope",
+      e[0].to_string()
+    );
+    let e = p
+      .configure(ConfigValues::new().set("C", false))
+      .err()
+      .unwrap()
+      .errors;
+    assert!(matches!(
+      &e[..],
+      [ConfigError::Conditional { error }]
+        if matches!(**error, CompileError::NonConstantCondition { .. })
+    ));
+    Ok(())
+  }
+
+  #[test]
+  fn overrides() -> Result<(), Error> {
+    let base = "
+      config Glitches : bool default true
+      config Hard : bool default true
+      item Sword { tag Weapon }
+      item Bow
+      location Chest { requires Sword }
+      region R { item Gem location Cave { requires Gem } }
+      enum E { A B }
+      fn F(x: num) -> bool = x > 1";
+    let first = "if Glitches {
+        modify item Sword { tag +[Magic] }
+        modify region R { item Shard }
+        override - item Bow
+        modify enum E { C }
+      }";
+    let second = "if Hard {
+        modify region R { location Hidden { requires Shard } }
+        override - item Bow
+        override location Chest { requires Sword and Hard }
+        override fn F(x: num) -> bool = x > 2
+      }";
+    // Everything left in the program apart from the conditional blocks
+    // themselves, with its tags and what it refers to.
+    let summary = |p: &Program| {
+      let mut s = p
+        .walk()
+        .filter(|e| e.kind() != EntityKind::Cond)
+        .map(|e| {
+          let mut tags = p
+            .get_entity(e)
+            .unwrap()
+            .tags()
+            .map(|t| p.resolve(t).to_string())
+            .collect::<Vec<_>>();
+          tags.sort();
+          let mut refs = Vec::new();
+          for x in p.exprs_of(e) {
+            entity_refs(x, &mut refs);
+          }
+          let refs = refs.into_iter().map(|r| p.path_of(r)).collect::<Vec<_>>();
+          format!("{} {:?} {:?}", p.path_of(e), tags, refs)
+        })
+        .collect::<Vec<_>>();
+      s.sort();
+      s
+    };
+    let a = load(&format!("{} {} {}", base, first, second))?;
+    let b = load(&format!("{} {} {}", base, second, first))?;
+    let (a, b) = (
+      a.configure(&ConfigValues::new())?,
+      b.configure(&ConfigValues::new())?,
+    );
+    assert_eq!(summary(a.program()), summary(b.program()));
+    let p = a.program();
+    assert!(p.is_deleted(get(p, "Bow")));
+    assert!(p.items().all(|(_, i)| p.resolve(i.name.ident) != "Bow"));
+    assert!(!p.is_deleted(get(p, "R.Shard")));
+    assert!(!p.is_deleted(get(p, "R.Hidden")));
+    assert_eq!(3, p.enums[get(p, "E").unwrap_enum().0].variants.len());
+    let summary = summary(p);
+    assert!(summary.contains(&r#"Sword ["Magic", "Weapon"] []"#.to_string()));
+    assert!(summary.contains(&r#"Chest [] ["Sword", "Hard"]"#.to_string()));
+
+    // Only the taken branches override anything.
+    let c = load(&format!("{} {} {}", base, first, second))?;
+    let c = c.configure(
+      ConfigValues::new()
+        .set("Glitches", false)
+        .set("Hard", false),
+    )?;
+    assert!(!c.program().is_deleted(get(c.program(), "Bow")));
+
+    let errs = |src: &str| -> Vec<CompileError> {
+      let p = load(&format!("{} {}", base, src)).unwrap();
+      match p.configure(&ConfigValues::new()) {
+        Ok(_) => Vec::new(),
+        Err(e) => e
+          .errors
+          .into_iter()
+          .map(|e| match e {
+            ConfigError::Conditional { error } => *error,
+            e => panic!("unexpected error: {}", e),
+          })
+          .collect(),
+      }
+    };
+    let conflict =
+      |src: &str| matches!(&errs(src)[..], [CompileError::ConflictingOverrides { .. }]);
+    assert!(conflict(
+      "if Hard { override location Chest { requires Bow } }
+      if Glitches { override location Chest { requires Sword } }"
+    ));
+    assert!(conflict(
+      "if Hard { override location Chest { requires Bow } }
+      if Glitches { override - location Chest }"
+    ));
+    assert!(conflict(
+      "if Hard { modify item Sword { tag +[Magic] } }
+      if Glitches { modify item Sword { tag +[Sharp] } }"
+    ));
+    assert!(conflict(
+      "if Hard { modify region R { requires Bow } }
+      if Glitches { modify region R { requires Sword } }"
+    ));
+    assert!(conflict(
+      "if Hard { modify region R { override - location Cave } }
+      if Glitches { modify region R { modify location Cave { requires false } } }"
+    ));
+    // Only one branch of a conditional block is ever taken.
+    assert!(errs(
+      "if Hard { override location Chest { requires Bow } }
+      else { override - location Chest }"
+    )
+    .is_empty());
+
+    // More deeply nested overrides supersede those around them.
+    let p = load(&format!(
+      "{} if Hard {{
+        override location Chest {{ requires Bow }}
+        modify region R {{ override - item Gem }}
+        if Glitches {{
+          override location Chest {{ requires Hard }}
+          modify region R {{ override item Gem {{ tag Shiny }} }}
+        }}
+      }}",
+      base
+    ))?;
+    let c = p.configure(&ConfigValues::new())?;
+    let p = c.program();
+    let chest = &p.locations[get(p, "Chest").unwrap_location().0];
+    assert!(matches!(
+      chest.requires(),
+      Some(Expr::Entity(EntityId::Config(_)))
+    ));
+    assert!(!p.is_deleted(get(p, "R.Gem")));
+    assert!(p.items[get(p, "R.Gem").unwrap_item().0].tags.len() == 1);
+    assert!(matches!(
+      &errs("if Hard { override - item Bow if Glitches { modify item Bow { tag Ranged } } }")[..],
+      [CompileError::Deleted { path, .. }] if path == "Bow"
+    ));
+
+    // Deleted entities stay as placeholders which cannot be referred to.
+    assert!(matches!(
+      &errs("if Hard { override - item Sword }")[..],
+      [CompileError::Deleted { path, .. }] if path == "Sword"
+    ));
+    let p = load(&format!("{} if Hard {{ override - region R {{ }} }}", base))?;
+    let c = p.configure(&ConfigValues::new())?;
+    let p = c.program();
+    assert!(p.is_deleted(get(p, "R")) && p.is_deleted(get(p, "R.Cave")));
+    assert!(p.walk().all(|e| !p.path_of(e).starts_with("R")));
+    assert_eq!("R.Cave", p.path_of(get(p, "R.Cave")));
+
+    // Everything inside is deleted, however deep, including anonymous links
+    // and the variants of enums.
+    let p = load(&format!(
+      "{} if Hard {{ override - region R {{ }} }}",
+      "config Hard : bool default true region R { region S { link to R enum K { X } if true { } } }"
+    ))?;
+    let c = p.configure(&ConfigValues::new())?;
+    let p = c.program();
+    assert!(p.links().next().is_none() && p.variants().next().is_none());
+    assert_eq!(
+      0,
+      p.conds()
+        .filter(|(_, c)| c.parent != ScopeId::Global)
+        .count()
+    );
+
+    assert!(matches!(
+      &errs("if Hard { override fn F(x: bool) -> bool = x }")[..],
+      [CompileError::SignatureChanged { path, .. }] if path == "F"
+    ));
+    assert!(matches!(
+      &errs("if Hard { modify region Sword { } }")[..],
+      [CompileError::OverrideMismatch { path, kind: EntityKind::Region, .. }] if path == "Sword"
+    ));
+    assert!(matches!(
+      &errs("if Hard { modify fn F = true }")[..],
+      [CompileError::CannotOverride {
+        action: "modify",
+        ..
+      }]
+    ));
+    assert!(matches!(
+      &errs("if Hard { override region R { } }")[..],
+      [CompileError::CannotOverride {
+        action: "replace",
+        ..
+      }]
+    ));
+    assert!(matches!(
+      &errs("if Hard { modify item Nope { } }")[..],
+      [CompileError::NotFound { path, .. }] if path == "Nope"
+    ));
+    assert!(matches!(
+      compile_err("config Hard : bool if Hard { override - config Hard : bool }"),
+      CompileError::ConfigInConditional { .. }
+    ));
+    Ok(())
+  }
+
+  #[test]
+  fn conditional_tags() -> Result<(), Error> {
+    let p = load(
+      "config Flying : bool default true
+      config Cheats : bool default false
+      exclusive Easy, Hard
+      #[Flying] if Flying {
+        item Bat
+        #[Night] if Cheats { item Owl }
+      } else { item Rat }
+      #[Easy] if Cheats { item Map }
+      #[Hard] if Flying { item Spikes }
+      #[Flying, Night] if Cheats { item Bait }",
+    )?;
+    let errs = |vals: &ConfigValues| match p.configure(vals) {
+      Ok(_) => Vec::new(),
+      Err(e) => e.errors.iter().map(|e| e.to_string()).collect(),
+    };
+    let c = p.configure(&ConfigValues::new())?;
+    let tags = |id: usize, then| {
+      let cp = c.program();
+      let tags = cp.conds[id].block_tags(then).into_iter();
+      tags.map(|t| cp.cond_tag_str(t)).collect::<Vec<_>>()
+    };
+    // Nested blocks inherit tags, and else blocks negate them.
+    assert_eq!(vec!["Flying", "Night"], tags(4, true));
+    assert_eq!(vec!["not Flying"], tags(0, false));
+    assert!(errs(ConfigValues::new().set("Flying", false)).is_empty());
+
+    assert_eq!(
+      vec!["the exclusive conditional tags Easy and Hard are both active"],
+      errs(ConfigValues::new().set("Cheats", true))
+    );
+    assert_eq!(
+      vec![
+        "the exclusive conditional tags not Flying and Flying are both active",
+        "the block of <cond 1> is inactive, although the block of <cond 4>, \
+         which has all of its tags, is active",
+      ],
+      errs(ConfigValues::new().set("Flying", false).set("Cheats", true))
+    );
+
+    assert!(matches!(
+      compile_err("exclusive A, B #[B, not C, A] if true { }"),
+      CompileError::ExclusiveTags { first, second, .. } if first == "B" && second == "A"
+    ));
+    let p = load("config C : bool default false #[A] if C { } else { #[A] if true { } }")?;
+    let e = p.configure(&ConfigValues::new()).err().unwrap().errors;
+    assert!(matches!(
+      &e[..],
+      [ConfigError::Conditional { error }]
+        if matches!(**error, CompileError::ExclusiveTags { .. })
+    ));
+    assert!(matches!(
+      compile_err("#[A, not A] if true { }"),
+      CompileError::ExclusiveTags { .. }
+    ));
+    Ok(())
+  }
+
+  #[test]
+  fn dependency_loops() -> Result<(), Error> {
+    assert!(matches!(
+      compile_err(
+        "config C : bool default false
+        fn Hard = C
+        if Hard { override fn Hard = true }"
+      ),
+      CompileError::DependencyLoop { path, chain, .. }
+        if path == "<cond 1>" && chain == "<cond 1> -> Hard -> <cond 1>"
+    ));
+    assert!(matches!(
+      compile_err(
+        "config C : bool default false
+        fn A = C
+        fn B = not C
+        if A { override fn B = true }
+        if B { override fn A = false }"
+      ),
+      CompileError::DependencyLoop { chain, .. }
+        if chain == "<cond 1> -> A -> <cond 2> -> B -> <cond 1>"
+    ));
+    // Recursive functions are not loops, and neither is modifying the region
+    // which holds a config that the condition reads.
+    let p = load(
+      "region Modes { config Open : bool default true }
+      fn Even(n) = if n == 0 then true else Odd(n - 1)
+      fn Odd(n) = if n == 0 then false else Even(n - 1)
+      if Modes.Open and Even(2) { modify region Modes { item Key } }",
+    )?;
+    p.configure(&ConfigValues::new())?;
+
+    // Loops through nested blocks are only found once they are declared.
+    let p = load(
+      "config C : bool default true
+      config D : bool default false
+      fn F = C
+      if F { if D { override fn F = false } }",
+    )?;
+    let e = p.configure(&ConfigValues::new()).err().unwrap().errors;
+    assert_eq!(
+      vec!["in a conditional block: <cond 2> depends on itself: <cond 2> -> F -> <cond 2>"],
+      e.iter().map(|e| e.to_string()).collect::<Vec<_>>()
+    );
+    p.configure(ConfigValues::new().set("C", false))?;
+    Ok(())
+  }
+
+  #[test]
+  fn inherited_props() -> Result<(), Error> {
+    let p = load(
+      "item Lamp
+      item Key
+      item Hammer
+      region Front {
+        requires Lamp
+        visible Key
+        avail Key
+        region Inner {
+          requires Key
+          avail Hammer
+          location Chest
+          link to Front { requires Hammer }
+        }
+        location Door { visible false }
+      }
+      region Modes {
+        requires false
+        config State : bool default true
+      }",
+    )?;
+    let entity = |e: &Expr, path| matches!(e, Expr::Entity(x) if *x == get(&p, path));
+    let chest = get(&p, "Front.Inner.Chest");
+    assert!(matches!(
+      p.effective_requirement(chest),
+      Some(Expr::And(es)) if entity(&es[0], "Lamp") && entity(&es[1], "Key")
+    ));
+    let link = p.links().next().unwrap().0;
+    assert!(matches!(
+      p.effective_requirement(EntityId::Link(link)),
+      Some(Expr::And(es)) if es.len() == 3 && entity(&es[2], "Hammer")
+    ));
+    assert!(matches!(
+      p.effective_requirement(get(&p, "Front")),
+      Some(e) if entity(&e, "Lamp")
+    ));
+    assert!(matches!(
+      p.effective_requirement(get(&p, "Modes.State")),
+      Some(Expr::Bool(false))
+    ));
+    assert!(p.effective_requirement(get(&p, "Lamp")).is_none());
+
+    // The nearest visibility applies.
+    assert!(p
+      .effective_visibility(chest)
+      .is_some_and(|e| entity(e, "Key")));
+    assert!(matches!(
+      p.effective_visibility(get(&p, "Front.Door")),
+      Some(Expr::Bool(false))
+    ));
+    assert!(p.effective_visibility(get(&p, "Modes")).is_none());
+
+    let inner = get(&p, "Front.Inner").unwrap_region();
+    let avail = p.effective_avail(inner);
+    let names = avail.iter().map(|a| p.path_str(&a.item.0));
+    assert_eq!(vec!["Key", "Hammer"], names.collect::<Vec<_>>());
+    Ok(())
+  }
+
+  #[test]
+  fn region_graph() -> Result<(), Error> {
+    let p = load_samples(&["config", "items", "regions"]);
+    let region = |path| get(&p, path).unwrap_region();
+    let g = p.region_graph();
+    let passage = region("LightWorld.SecretPassage");
+    let castle = region("LightWorld.HyruleCastle");
+    let world = region("LightWorld");
+    assert_eq!(vec![castle], g.neighbours(passage));
+    let froms = g.edges_to(passage).map(|e| e.from).collect::<Vec<_>>();
+    assert_eq!(vec![world, region("LightWorld.LinksHouse")], froms);
+
+    // Links with both directions have an edge each way, and one-way links in
+    // opposite directions are separate edges.
+    let both = |a, b| (g.edges_between(a, b).count(), g.edges_between(b, a).count());
+    assert_eq!((1, 1), both(castle, world));
+    let back = region("LightWorld.HyruleCastle.Sewers.Back");
+    assert_eq!((1, 1), both(back, world));
+    let glove = get(&p, "Glove");
+    let e = g.edges_between(world, back).next().unwrap();
+    assert!(matches!(&e.requires, Some(Expr::Entity(x)) if *x == glove));
+    assert!(g
+      .edges_between(back, world)
+      .next()
+      .unwrap()
+      .requires
+      .is_none());
+
+    // Edges take on the requirements of the regions they enter, but not of
+    // those already entered.
+    let front = region("LightWorld.HyruleCastle.Sewers.Front");
+    let mid = region("LightWorld.HyruleCastle.Sewers.Mid");
+    let fetch = get(&p, "LightWorld.HyruleCastle.FetchZelda");
+    let e = g.edges_between(front, castle).next().unwrap();
+    assert!(matches!(&e.requires, Some(Expr::Entity(x)) if *x == fetch));
+    let e = g.edges_between(castle, front).next().unwrap();
+    assert!(matches!(&e.requires, Some(Expr::And(es)) if es.len() == 2));
+    let e = g.edges_between(mid, front).next().unwrap();
+    let key = get(&p, "LightWorld.HyruleCastle.SmallKey");
+    assert!(matches!(
+      &e.requires,
+      Some(Expr::And(es)) if matches!(&es[..], [Expr::Or(_), Expr::Entity(x)] if *x == key)
+    ));
+    let e = g.edges_between(back, world).next().unwrap();
+    assert!(matches!(&e.grants[..], [g] if p.resolve(*g.item.0.last().unwrap()) == "RescueZelda"));
+    let eastern = region("DarkWorld.Eastern");
+    assert!(g.neighbours(castle).contains(&eastern));
+    assert_eq!((1, 0), both(castle, eastern));
+
+    assert!(matches!(
+      compile_err("region R { } link to R"),
+      CompileError::LinkOutsideRegion { .. }
+    ));
+    assert!(matches!(
+      compile_err("item I region R { link to I }"),
+      CompileError::NotARegion { found, .. } if found == "I"
+    ));
+    assert!(matches!(
+      compile_err("region R { link with S }"),
+      CompileError::NotFound { path, .. } if path == "S"
+    ));
+    let p = load(
+      "item Lamp
+      item Key
+      item Map
+      region Castle {
+        requires Key
+        region Dark { requires Lamp }
+        region Hall { link to Dark { requires Map } }
+      }
+      region Field { link to Castle.Dark, Castle.Hall }",
+    )?;
+    let g = p.region_graph();
+    let region = |path| get(&p, path).unwrap_region();
+    let entity = |e: &Expr, path| matches!(e, Expr::Entity(x) if *x == get(&p, path));
+    let requires = |from, to| {
+      let e = g.edges_between(region(from), region(to)).next().unwrap();
+      e.requires.clone()
+    };
+    assert!(matches!(
+      requires("Castle.Hall", "Castle.Dark"),
+      Some(Expr::And(es)) if entity(&es[0], "Lamp") && entity(&es[1], "Map")
+    ));
+    assert!(matches!(
+      requires("Field", "Castle.Dark"),
+      Some(Expr::And(es)) if entity(&es[0], "Key") && entity(&es[1], "Lamp")
+    ));
+    assert!(requires("Field", "Castle.Hall").is_some_and(|e| entity(&e, "Key")));
+
+    // Unlocks are required like requirements, and grants go with the edges.
+    let p = load(
+      "item SmallKey
+      item BigKey
+      item Boots
+      region Vault { unlock BigKey }
+      region Mid { link to Vault }
+      region Start { link with Mid { unlock SmallKey grants Boots } }",
+    )?;
+    let g = p.region_graph();
+    let region = |path| get(&p, path).unwrap_region();
+    let key = get(&p, "SmallKey");
+    for (from, to) in &[("Start", "Mid"), ("Mid", "Start")] {
+      let e = g.edges_between(region(from), region(to)).next().unwrap();
+      assert!(matches!(&e.requires, Some(Expr::Entity(x)) if *x == key));
+      assert!(matches!(
+        &e.grants[..],
+        [Grant { item, negated: false }] if p.resolve(item.0[0]) == "Boots"
+      ));
     }
+    let e = g
+      .edges_between(region("Mid"), region("Vault"))
+      .next()
+      .unwrap();
+    assert!(matches!(&e.requires, Some(Expr::Entity(x)) if *x == get(&p, "BigKey")));
+    assert!(e.grants.is_empty());
+
+    let p = load("region A { } region B { link to A } if true { override - region A { } }")?;
+    let e = p.configure(&ConfigValues::new()).err().unwrap().errors;
+    assert!(matches!(
+      &e[..],
+      [ConfigError::Conditional { error }]
+        if matches!(&**error, CompileError::Deleted { path, .. } if path == "A")
+    ));
     Ok(())
   }
 
-  fn lookup_ast(&self, scope: &dyn Scope, ident: &ast::Ident) -> EntityId {
-    scope.lookup_ident(self.convert_ident(ident)).unwrap()
+  #[test]
+  fn sample_paths() {
+    let p = load_samples(&["config", "items", "regions"]);
+    // Anonymous entities cannot be looked up, and a config-enum's enum is
+    // named by its config.
+    for e in p.walk() {
+      let path = p.path_of(e);
+      if !path.contains('<') {
+        let segs = path.split('.').map(|s| ident(&p, s)).collect();
+        let found = p.lookup_entity(&p, &Path(segs)).unwrap();
+        match found {
+          EntityId::Config(c) if e != found => {
+            assert!(matches!(p.configs[c.0].ty, ConfigTy::Enum(en) if e == EntityId::Enum(en)))
+          }
+          _ => assert_eq!(e, found),
+        }
+      }
+    }
+    let upper = get(&p, "LightWorld.HyruleCastle.Basement.Upper");
+    assert_eq!("LightWorld.HyruleCastle.Basement.Upper", p.path_of(upper));
   }
 
-  fn convert_ident(&self, ident: &ast::Ident) -> Ident {
-    Ident(self.idents.get(&ident.0).unwrap())
+  #[test]
+  fn lookup_errors() {
+    let p = load("region A { item I }").unwrap();
+    let path = |s: &str| Path(s.split('.').map(|s| ident(&p, s)).collect());
+    assert!(matches!(
+      p.lookup_entity(&p, &path("A.I.A")),
+      Err(CompileError::NotAScope { path, entity: EntityId::Item(_), span: None }) if path == "A.I"
+    ));
+    assert!(matches!(
+      p.lookup_entity(&p, &path("I")),
+      Err(CompileError::NotFound { path, span: None }) if path == "I"
+    ));
+    assert!(matches!(Path::new(vec![]), Err(CompileError::EmptyPath)));
   }
 }
@@ -92,6 +92,7 @@ toks! {
     Else <- "else",
     Modify <- "modify",
     Override <- "override",
+    Exclusive <- "exclusive",
 
     // Properties
     Requires <- "requires",
@@ -109,6 +110,7 @@ toks! {
     Grants <- "grants",
     Count <- "count",
     Start <- "start",
+    Disallow <- "disallow",
 
     // Expressions & types not covered above
     Num <- "num",
@@ -153,6 +155,7 @@ toks! {
     Assign <- "=",
     Arrow <- "->",
     DoubleArrow <- "=>",
+    Hash <- "#",
 
     // Operators
     Plus <- "+",
@@ -164,8 +167,8 @@ toks! {
     NEq <- "!=",
     LT <- "<",
     LE <- "<=",
-    GT <- ">=",
-    GE <- ">",
+    GT <- ">",
+    GE <- ">=",
   }
 }
 
@@ -243,7 +246,7 @@ impl<'a> fmt::Display for Tok<'a> {
         s,
         w,
         if d.is_some() { "." } else { "" },
-        d.as_ref().map_or("", |d| &d),
+        d.as_ref().map_or("", |d| d),
       ),
       Tok::String(s) => write!(f, "{:?}", s),
     }
@@ -262,11 +265,9 @@ fn skip_block_comment(mut s: &str) -> Result<&str, LexerError> {
   // lot of work for two two-character search patterns. Also this means every
   // recursion is checking for an EOF, which is pointless.
   loop {
-    let end = s
-      .find("*/")
-      .ok_or_else(|| LexerError::UnterminatedBlockComment)?;
+    let end = s.find("*/").ok_or(LexerError::UnterminatedBlockComment)?;
     match s.find("/*") {
-      Some(inner) if inner < end => s = &skip_block_comment(&s[inner..])?,
+      Some(inner) if inner < end => s = skip_block_comment(&s[inner..])?,
       _ => break Ok(&s[end + 2..]),
     }
   }
@@ -275,25 +276,21 @@ fn skip_block_comment(mut s: &str) -> Result<&str, LexerError> {
 /// Lex a numeric literal.
 #[allow(clippy::type_complexity, clippy::many_single_char_names)]
 fn lex_num_lit(mut s: &str) -> Result<(Cow<'_, str>, Option<Cow<'_, str>>, &str), LexerError> {
-  let i = s
-    .find(|c: char| !c.is_ascii_digit())
-    .unwrap_or_else(|| s.len());
+  let i = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
   let (w, mut f) = (s[0..i].into(), None);
   s = &s[i..];
 
   let mut r = s.chars();
-  if r.next() == Some('.') && r.next().map_or(false, |c| c.is_ascii_digit()) {
+  if r.next() == Some('.') && r.next().is_some_and(|c| c.is_ascii_digit()) {
     s = &s[1..];
-    let i = s
-      .find(|c: char| !c.is_ascii_digit())
-      .unwrap_or_else(|| s.len());
+    let i = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
     f = Some(s[0..i].into());
     s = &s[i..];
   }
   if s
     .chars()
     .next()
-    .map_or(false, |c| c == '_' || is_xid_start(c) || is_xid_continue(c))
+    .is_some_and(|c| c == '_' || is_xid_start(c) || is_xid_continue(c))
   {
     return Err(LexerError::NumericLiteralSuffix);
   }
@@ -306,10 +303,8 @@ fn lex_num_lit(mut s: &str) -> Result<(Cow<'_, str>, Option<Cow<'_, str>>, &str)
 fn lex_string_lit(mut s: &str) -> Result<(Cow<'_, str>, &str), LexerError> {
   // Easy case: there is no escape sequence, so we can just borrow the
   // contents directly.
-  let escape = s.find('\\').unwrap_or_else(|| s.len());
-  let quote = s
-    .find('\"')
-    .ok_or_else(|| LexerError::UnterminatedStringLiteral)?;
+  let escape = s.find('\\').unwrap_or(s.len());
+  let quote = s.find('\"').ok_or(LexerError::UnterminatedStringLiteral)?;
   if quote < escape {
     return Ok((s[0..quote].into(), &s[quote + 1..]));
   }
@@ -330,17 +325,20 @@ fn lex_string_lit(mut s: &str) -> Result<(Cow<'_, str>, &str), LexerError> {
     // Any escape sequence we actually accept is 1 ASCII character long.
     s = &s[1..];
   }
-  let quote = s
-    .find('\"')
-    .ok_or_else(|| LexerError::UnterminatedStringLiteral)?;
+  let quote = s.find('\"').ok_or(LexerError::UnterminatedStringLiteral)?;
   l += &s[0..quote];
   Ok((l.into(), &s[quote + 1..]))
 }
 
-/// Lex a string into a token vector. An error occurs if the string is not made of legal tokens.
-pub fn lex<'a>(mut s: &'a str) -> Result<Vec<Tok<'a>>, LexerError> {
+/// Lex a string into a token vector. Each token is paired with the byte offsets
+/// of its start and end in `src`. An error occurs if the string is not made of
+/// legal tokens.
+pub fn lex<'a>(src: &'a str) -> Result<Vec<(usize, Tok<'a>, usize)>, LexerError> {
+  let mut s = src;
   let mut toks = Vec::new();
+  let mut spans = Vec::new();
   while let Some(c) = s.chars().next() {
+    let start = src.len() - s.len();
     let rest = &s[c.len_utf8()..];
     match c {
       '(' => {
@@ -395,6 +393,10 @@ pub fn lex<'a>(mut s: &'a str) -> Result<Vec<Tok<'a>>, LexerError> {
         toks.push(Tok::Sym(Sym::Percent));
         s = rest;
       }
+      '#' => {
+        toks.push(Tok::Sym(Sym::Hash));
+        s = rest;
+      }
       '/' => match rest.chars().next() {
         Some('/') => {
           // If we don't find \n, we set i to s.len()-1 so that when we add 1 on the next
@@ -476,7 +478,7 @@ pub fn lex<'a>(mut s: &'a str) -> Result<Vec<Tok<'a>>, LexerError> {
       c if c == '_' || is_xid_start(c) => {
         let i = s
           .find(|c: char| c != '_' && !is_xid_continue(c))
-          .unwrap_or_else(|| s.len());
+          .unwrap_or(s.len());
         let ident = &s[0..i];
         s = &s[i..];
         if let Ok(k) = ident.parse() {
@@ -493,14 +495,29 @@ pub fn lex<'a>(mut s: &'a str) -> Result<Vec<Tok<'a>>, LexerError> {
       c if c.is_ascii_whitespace() => s = rest,
       _ => return Err(LexerError::UnrecognizedCharacter(c)),
     }
+    // Comments and whitespace produce no token, and so no span.
+    if toks.len() > spans.len() {
+      spans.push((start, src.len() - s.len()));
+    }
   }
-  Ok(toks)
+  Ok(
+    toks
+      .into_iter()
+      .zip(spans)
+      .map(|(t, (start, end))| (start, t, end))
+      .collect(),
+  )
 }
 
 // TODO: Get a better testing framework, even if just Go-style table tests.
 #[cfg(test)]
 mod tests {
   use super::*;
+
+  // Most tests don't care about spans, so strip them.
+  fn lex(s: &str) -> Result<Vec<Tok<'_>>, LexerError> {
+    Ok(super::lex(s)?.into_iter().map(|(_, t, _)| t).collect())
+  }
   use proptest::{proptest, proptest_helper};
 
   #[test]
@@ -509,6 +526,8 @@ mod tests {
     assert_eq!(Kw::Enum, "enum".parse().unwrap());
     assert_eq!(Kw::To, "to".parse().unwrap());
     assert_eq!(Kw::Modify, "modify".parse().unwrap());
+    assert_eq!(Kw::Exclusive, "exclusive".parse().unwrap());
+    assert_eq!(Kw::Disallow, "disallow".parse().unwrap());
   }
 
   #[test]
@@ -581,13 +600,14 @@ mod tests {
     ];
     assert_eq!(toks, lex(str).unwrap());
 
-    let str = "*+-/%.;:,{}()[]";
+    let str = "*+-/%#.;:,{}()[]";
     let toks = vec![
       Sym(Star),
       Sym(Plus),
       Sym(Minus),
       Sym(Slash),
       Sym(Percent),
+      Sym(Hash),
       Sym(Dot),
       Sym(Semi),
       Sym(Colon),